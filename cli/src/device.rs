@@ -0,0 +1,87 @@
+//! Talking to the device over its USB serial port
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    time::Duration,
+};
+
+use serialport::SerialPort;
+
+use crate::{export::is_streamed, Result};
+
+/// How long the device gets to say something before it counts as not
+/// answering
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Device {
+    port: Box<dyn SerialPort>,
+    reader: BufReader<Box<dyn SerialPort>>,
+    /// What has come in of a line so far
+    line: String,
+}
+
+impl Device {
+    pub fn open(path: &str) -> Result<Self> {
+        // The baud rate doesn't matter over USB
+        let mut port = serialport::new(path, 115_200).timeout(TIMEOUT).open()?;
+        // The device only sends anything once a terminal is ready
+        port.write_data_terminal_ready(true)?;
+        let reader = BufReader::new(port.try_clone()?);
+        Ok(Device {
+            port,
+            reader,
+            line: String::new(),
+        })
+    }
+
+    /// The next line without its line break, `None` if nothing came in for a
+    /// while
+    pub fn read_line(&mut self) -> Result<Option<String>> {
+        match self.reader.read_line(&mut self.line) {
+            Ok(0) => Err("the device went away".into()),
+            Ok(_) if self.line.ends_with('\n') => {
+                let line = self.line.trim_end().to_string();
+                self.line.clear();
+                Ok(Some(line))
+            }
+            Ok(_) => Ok(None),
+            Err(error) if error.kind() == io::ErrorKind::TimedOut => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Sends `command` and hands the lines of the reply to `on_line`, leaving
+    /// out the measurements that got streamed before it. Fails if the device
+    /// answers with an error.
+    pub fn command(
+        &mut self,
+        command: &str,
+        mut on_line: impl FnMut(&str) -> Result<()>,
+    ) -> Result<()> {
+        write!(self.port, "{command}\r\n")?;
+        self.port.flush()?;
+        // The CSV log starts with the same header as the stream. The device
+        // doesn't stream anything while it's sending the log, or any other
+        // reply.
+        let csv_log = matches!(
+            command.split_whitespace().collect::<Vec<_>>()[..],
+            ["log"] | ["log", "csv"]
+        );
+        let mut replying = false;
+        loop {
+            let Some(line) = self.read_line()? else {
+                return Err(format!("no reply to {command:?}").into());
+            };
+            if line == "ok" {
+                return Ok(());
+            }
+            if let Some(error) = line.strip_prefix("error: ") {
+                return Err(format!("{command:?} failed: {error}").into());
+            }
+            replying = replying || !is_streamed(&line) || (csv_log && line.starts_with("time,"));
+            if replying {
+                on_line(&line)?;
+            }
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Output formats, and reading the measurements that the device streams
+
+use std::str::FromStr;
+
+use crate::logic::{
+    csv, influx, json,
+    measurement::{Measurement, Quantity},
+    time::DateTime,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    JsonLines,
+    Influx,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "csv" => Ok(Format::Csv),
+            "jsonl" => Ok(Format::JsonLines),
+            "influx" => Ok(Format::Influx),
+            _ => Err(format!("unknown format {name:?}, try csv, jsonl or influx")),
+        }
+    }
+}
+
+impl Format {
+    /// What goes before the first measurement
+    pub fn header(&self) -> Option<String> {
+        match self {
+            Format::Csv => Some(csv::header().to_string()),
+            Format::JsonLines | Format::Influx => None,
+        }
+    }
+
+    /// A line for `measurement`, with the line break. `None` if the format
+    /// can't have a line without any values.
+    pub fn encode(&self, timestamp: u32, measurement: &Measurement) -> Option<String> {
+        match self {
+            Format::Csv => Some(csv::row(timestamp, measurement).to_string()),
+            Format::JsonLines => Some(json::object(timestamp, measurement).to_string()),
+            Format::Influx => {
+                influx::line(&[], Some(timestamp), measurement).map(|line| line.to_string())
+            }
+        }
+    }
+}
+
+/// Whether `line` is a measurement that the device streamed, rather than a
+/// reply to a command
+pub fn is_streamed(line: &str) -> bool {
+    line.starts_with("time,")
+        || line.starts_with("{\"time\"")
+        || line
+            .split_once(',')
+            .map_or(false, |(time, _)| DateTime::parse(time).is_some())
+}
+
+/// Reads the streamed measurements back, whether they're CSV or JSON
+#[derive(Default)]
+pub struct Stream {
+    /// The quantities in the columns after the time and the timestamp, from
+    /// the CSV header
+    columns: Vec<Option<Quantity>>,
+}
+
+impl Stream {
+    /// The timestamp and the measurement in a streamed line. The CSV header
+    /// gets remembered for the rows that follow it.
+    pub fn parse(&mut self, line: &str) -> Option<(u32, Measurement)> {
+        let line = line.trim_end();
+        if let Some(object) = line.strip_prefix('{') {
+            return parse_object(object.strip_suffix('}')?);
+        }
+        let mut cells = line.split(',');
+        if line.starts_with("time,") {
+            self.columns = cells.skip(2).map(quantity).collect();
+            return None;
+        }
+        let timestamp = cells.nth(1)?.parse().ok()?;
+        let mut measurement = Measurement::default();
+        for (cell, quantity) in cells.zip(&self.columns) {
+            if let (Some(quantity), Ok(value)) = (quantity, cell.parse()) {
+                measurement.set(*quantity, Some(value));
+            }
+        }
+        Some((timestamp, measurement))
+    }
+}
+
+fn quantity(key: &str) -> Option<Quantity> {
+    Quantity::ALL
+        .into_iter()
+        .find(|quantity| quantity.key() == key)
+}
+
+/// The flat objects from [`json::object`], where no value has a comma in it
+fn parse_object(object: &str) -> Option<(u32, Measurement)> {
+    let mut timestamp = None;
+    let mut measurement = Measurement::default();
+    for member in object.split(',') {
+        let (key, value) = member.split_once(':')?;
+        let key = key.trim().strip_prefix('"')?.strip_suffix('"')?;
+        if key == "timestamp" {
+            timestamp = value.trim().parse().ok();
+        } else if let Some(quantity) = quantity(key) {
+            measurement.set(quantity, value.trim().parse().ok());
+        }
+    }
+    Some((timestamp?, measurement))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_streamed, Format, Stream};
+    use crate::logic::{
+        csv, json,
+        measurement::{Measurement, Quantity},
+    };
+
+    fn measurement() -> Measurement {
+        Measurement {
+            co2: Some(612.),
+            temperature: Some(21.37),
+            pm2_5: Some(3.1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reads_csv_stream() {
+        let mut stream = Stream::default();
+        assert_eq!(stream.parse(&csv::header()), None);
+        let row = csv::row(1_700_000_000, &measurement());
+        assert!(is_streamed(&row));
+        let (timestamp, parsed) = stream.parse(&row).unwrap();
+        assert_eq!(timestamp, 1_700_000_000);
+        assert_eq!(parsed, measurement());
+        assert_eq!(parsed.get(Quantity::VocIndex), None);
+    }
+
+    #[test]
+    fn reads_json_stream() {
+        let object = json::object(1_700_000_000, &measurement());
+        assert!(is_streamed(&object));
+        let parsed = Stream::default().parse(&object);
+        assert_eq!(parsed, Some((1_700_000_000, measurement())));
+    }
+
+    #[test]
+    fn replies_are_not_streamed() {
+        for reply in [
+            "ok",
+            "error: unknown command",
+            "altitude 10",
+            "2023-11-14T22:13:20Z",
+        ] {
+            assert!(!is_streamed(reply), "{reply}");
+        }
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!("jsonl".parse(), Ok(Format::JsonLines));
+        assert!("xml".parse::<Format>().is_err());
+        assert_eq!(
+            Format::Influx
+                .encode(1_700_000_000, &measurement())
+                .unwrap(),
+            "airlog co2=612,temperature=21.37,pm2_5=3.1 1700000000000000000\n"
+        );
+        assert_eq!(Format::Influx.encode(0, &Measurement::default()), None);
+    }
+}
@@ -0,0 +1,29 @@
+//! The firmware's record types and encoders, built from the same source, so
+//! that the two can't get out of step
+
+// Not all of it is needed here, and the firmware imports `micromath` for what
+// `std` has built in
+#![allow(dead_code, unused_imports, unused_must_use)]
+
+#[path = "../../src/logic/codec.rs"]
+pub mod codec;
+#[path = "../../src/logic/command.rs"]
+pub mod command;
+#[path = "../../src/logic/csv.rs"]
+pub mod csv;
+#[path = "../../src/logic/flash.rs"]
+pub mod flash;
+#[path = "../../src/logic/formatting.rs"]
+pub mod formatting;
+#[path = "../../src/logic/influx.rs"]
+pub mod influx;
+#[path = "../../src/logic/json.rs"]
+pub mod json;
+#[path = "../../src/logic/logger.rs"]
+pub mod logger;
+#[path = "../../src/logic/measurement.rs"]
+pub mod measurement;
+#[path = "../../src/logic/settings.rs"]
+pub mod settings;
+#[path = "../../src/logic/time.rs"]
+pub mod time;
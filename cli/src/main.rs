@@ -0,0 +1,116 @@
+//! Companion tool for airlog. Downloads the measurement log over USB serial
+//! and exports it, prints the measurements as they come in, and sets the
+//! device's clock.
+
+mod device;
+mod export;
+mod logic;
+
+use std::{
+    env,
+    io::{self, Write},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use device::Device;
+use export::{Format, Stream};
+use logic::{codec::Decoder, command::parse_raw_frame};
+
+const USAGE: &str = "\
+usage: airlog <port> <command>
+
+commands:
+  download [csv|jsonl|influx]  Download the measurement log, as CSV by default
+  live [csv|jsonl|influx]      Print the measurements as they come in
+  set-clock                    Set the device's clock to this computer's
+  send <command>...            Send a command and print the reply, e.g. send get
+
+e.g. airlog /dev/ttyACM0 download influx > airlog.txt";
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let [port, command, rest @ ..] = &args[..] else {
+        eprintln!("{USAGE}");
+        process::exit(2);
+    };
+    let result = Device::open(port).and_then(|mut device| match (*command, rest) {
+        ("download", []) => download(&mut device, Format::Csv),
+        ("download", [format]) => download(&mut device, format.parse()?),
+        ("live", []) => live(&mut device, Format::Csv),
+        ("live", [format]) => live(&mut device, format.parse()?),
+        ("set-clock", []) => set_clock(&mut device),
+        ("send", [_, ..]) => device.command(&rest.join(" "), |line| {
+            println!("{line}");
+            Ok(())
+        }),
+        _ => {
+            eprintln!("{USAGE}");
+            process::exit(2);
+        }
+    });
+    if let Err(error) = result {
+        eprintln!("error: {error}");
+        process::exit(1);
+    }
+}
+
+fn download(device: &mut Device, format: Format) -> Result<()> {
+    let mut out = io::stdout().lock();
+    if let Some(header) = format.header() {
+        out.write_all(header.as_bytes())?;
+    }
+    let mut records = 0;
+    let mut unset_clock = 0;
+    let mut decoder = Decoder::new();
+    device.command("log raw", |line| {
+        let (timestamp, measurement) = parse_raw_frame(line)
+            .and_then(|frame| decoder.decode(&frame).ok())
+            .ok_or_else(|| format!("bad record {line:?}"))?;
+        records += 1;
+        if timestamp < logic::time::EARLIEST {
+            unset_clock += 1;
+        }
+        if let Some(line) = format.encode(timestamp, &measurement) {
+            out.write_all(line.as_bytes())?;
+        }
+        Ok(())
+    })?;
+    eprintln!("{records} records");
+    if unset_clock > 0 {
+        eprintln!("{unset_clock} of them were logged while the clock wasn't set, their timestamps are seconds since boot");
+    }
+    Ok(())
+}
+
+fn live(device: &mut Device, format: Format) -> Result<()> {
+    let mut out = io::stdout().lock();
+    if let Some(header) = format.header() {
+        out.write_all(header.as_bytes())?;
+    }
+    let mut stream = Stream::default();
+    loop {
+        let Some(line) = device.read_line()? else {
+            continue;
+        };
+        if let Some(line) = stream
+            .parse(&line)
+            .and_then(|(timestamp, measurement)| format.encode(timestamp, &measurement))
+        {
+            out.write_all(line.as_bytes())?;
+            out.flush()?;
+        }
+    }
+}
+
+fn set_clock(device: &mut Device) -> Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    device.command(&format!("time {now}"), |_| Ok(()))?;
+    device.command("time", |line| {
+        eprintln!("The device's clock is now at {line}");
+        Ok(())
+    })
+}
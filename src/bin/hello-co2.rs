@@ -1,53 +1,189 @@
 #![no_main]
 #![no_std]
 
-use cortex_m::prelude::{_embedded_hal_blocking_delay_DelayMs, _embedded_hal_timer_CountDown};
+use core::cell::RefCell;
+
+use cortex_m::{
+    interrupt::Mutex,
+    prelude::{_embedded_hal_blocking_delay_DelayMs, _embedded_hal_timer_CountDown},
+};
 use embedded_hal::blocking::i2c;
 use hal::{
     gpio::Level,
-    pac::SPI1,
+    pac::{interrupt, SPI1},
     prelude::OutputPin,
     pwm::{self, Pwm},
     twim, Temp, Twim,
 };
-use hd44780_driver::{Cursor, CursorBlink, Direction, Display, DisplayMode, HD44780};
 use micromath::F32Ext;
-use nrf52840_hal::{self as hal, gpio::p0::Parts as P0Parts, gpio::p1::Parts as P1Parts, Timer};
+use nrf52840_hal::{
+    self as hal,
+    gpio::p0::Parts as P0Parts,
+    gpio::p1::Parts as P1Parts,
+    usbd::{UsbPeripheral, Usbd},
+    Timer,
+};
 
 use airlog::{
     self as _,
     logic::{
         self,
-        formatting::{
-            format_float_measurement, format_float_measurement_optional, format_u32_measurement,
-        },
+        alarm::{self, Alarms, Threshold},
+        aqi::{Index, Scale},
+        barometer::{self, PressureTrend, Tendency},
+        command::{self, Command, LineReader, Setting},
+        compensation::{Compensation, CompensationManager},
+        flash::Partition,
+        history::DayHistory,
+        iaq::{self, Assessment, Rule},
+        layout::{Align, Content, Field, Page, Screen, Source},
+        measurement::{Measurement, Quantity},
+        menu::{Action, Menu},
+        psychro,
+        settings::{Settings, SettingsStore},
+        stats::{MeasurementStats, Period, Statistic, Stats},
+        storage::Storage,
+        thermal::{configured_sensors, TemperatureFusion},
+        time::DateTime,
+        trend::{Advice, DefaultCo2Trend},
     },
     peripherals::{
-        button::Button,
+        ble::Ble,
+        button::{Button, Press},
+        buzzer::Buzzer,
+        display::TextDisplay,
+        ds3231::Ds3231,
+        flash,
         led::{LEDControl, PwmLEDControl},
+        rtc::RtcClock,
         scd30::{SensorReading, SCD30},
         sgp40::SGP40,
+        usb_serial::UsbSerial,
     },
 };
 use smart_leds::{SmartLedsWrite, RGB8};
+use usb_device::bus::UsbBusAllocator;
+
+#[cfg(not(feature = "sdcard"))]
+use airlog::logic::{
+    codec::Encoder,
+    logger::{Logger, Position},
+};
+#[cfg(feature = "ssd1306")]
+use airlog::peripherals::display::Ssd1306Display;
+#[cfg(feature = "sdcard")]
+use airlog::peripherals::sdcard::{CsvStorage, UnknownTime};
+#[cfg(not(feature = "ssd1306"))]
+use airlog::peripherals::{display::Hd44780Display, lcd::Lcd};
+#[cfg(all(feature = "spi-flash", not(feature = "sdcard")))]
+use airlog::{logic::flash::Flash, peripherals::spi_flash::SpiFlash};
+#[cfg(feature = "ssd1306")]
+use embedded_graphics::{prelude::*, primitives::Rectangle};
+
+/// Runs in the RADIO and TIMER4 interrupts, so it has to be shared with them
+static BLE: Mutex<RefCell<Option<Ble>>> = Mutex::new(RefCell::new(None));
+
+#[interrupt]
+fn RADIO() {
+    with_ble(Ble::on_radio);
+}
+
+#[interrupt]
+fn TIMER4() {
+    with_ble(Ble::on_timer);
+}
+
+fn with_ble<R>(f: impl FnOnce(&mut Ble) -> R) -> Option<R> {
+    cortex_m::interrupt::free(|cs| BLE.borrow(cs).borrow_mut().as_mut().map(f))
+}
 
 #[cortex_m_rt::entry]
 fn main() -> ! {
     defmt::println!("Hello, world!");
 
     let board = hal::pac::Peripherals::take().unwrap();
+    // Only the character LCDs need SysTick, for their delays
+    #[cfg(not(feature = "ssd1306"))]
     let core_peripherals = hal::pac::CorePeripherals::take().unwrap();
     let pins_0 = P0Parts::new(board.P0);
     let pins_1 = P1Parts::new(board.P1);
     let mut temp = Temp::new(board.TEMP);
     let mut button = Button::new(pins_0.p0_11.into_pullup_input());
 
+    // The RTC counts on the 32.768 kHz crystal, and USB needs the 32 MHz one
+    let clocks = hal::Clocks::new(board.CLOCK)
+        .enable_ext_hfosc()
+        .set_lfclk_src_external(hal::clocks::LfOscConfiguration::NoExternalNoBypass)
+        .start_lfclk();
+    let mut rtc_clock = RtcClock::new(board.RTC0).unwrap();
+
+    // Nothing else touches the storage area of the flash
+    let storage = unsafe { flash::area(flash::STORAGE_AREA, flash::STORAGE_PAGES) };
+    let storage = RefCell::new(hal::nvmc::Nvmc::new(board.NVMC, storage));
+    let mut settings_store =
+        SettingsStore::new(Partition::new(&storage, SETTINGS_PAGES.0, SETTINGS_PAGES.1));
+    let mut settings = settings_store.load().unwrap_or_else(|_| {
+        defmt::warn!("Couldn't read the settings, using the defaults");
+        Settings::DEFAULT
+    });
+    defmt::info!("Settings: {}", settings);
+
+    #[cfg(not(any(feature = "spi-flash", feature = "sdcard")))]
+    let mut log = {
+        let mut logger = Logger::new(Partition::new(&storage, LOG_PAGES.0, LOG_PAGES.1));
+        if logger.mount().is_err() {
+            defmt::warn!("Couldn't read the measurement log");
+        }
+        defmt::info!(
+            "{=usize} of {=usize} records in the measurement log",
+            logger.count().unwrap_or(0),
+            logger.capacity()
+        );
+        logger
+    };
+    #[cfg(any(feature = "spi-flash", feature = "sdcard"))]
+    let spi_pins = hal::spi::Pins {
+        sck: Some(pins_1.p1_01.into_push_pull_output(Level::Low).degrade()),
+        mosi: Some(pins_1.p1_02.into_push_pull_output(Level::Low).degrade()),
+        miso: Some(pins_1.p1_03.into_floating_input().degrade()),
+    };
+    #[cfg(any(feature = "spi-flash", feature = "sdcard"))]
+    let spi_cs = pins_1.p1_06.into_push_pull_output(Level::High);
+    // The SD card wins if both are enabled, they share the pins
+    #[cfg(all(feature = "spi-flash", not(feature = "sdcard")))]
+    let mut log = {
+        let spi = hal::spi::Spi::new(
+            board.SPI1,
+            spi_pins,
+            hal::spi::Frequency::M8,
+            hal::spi::MODE_0,
+        );
+        let flash = SpiFlash::new(spi, spi_cs).unwrap();
+        defmt::info!("SPI flash of {=usize} bytes", flash.capacity());
+        let mut logger = Logger::new(flash);
+        if logger.mount().is_err() {
+            defmt::warn!("Couldn't read the measurement log");
+        }
+        logger
+    };
+    #[cfg(feature = "sdcard")]
+    let mut log = {
+        // Cards have to be brought up at no more than 400 kHz
+        let spi = hal::spi::Spi::new(
+            board.SPI1,
+            spi_pins,
+            hal::spi::Frequency::K250,
+            hal::spi::MODE_0,
+        );
+        let card = embedded_sdmmc::SdCard::new(spi, spi_cs, Timer::one_shot(board.TIMER3));
+        CsvStorage::new(card, UnknownTime).unwrap()
+    };
+
     let mut builtin_led_1 = pins_0.p0_13.into_push_pull_output(Level::High);
+    // Blinks while there's an alarm
+    let mut builtin_led_2 = pins_0.p0_14.into_push_pull_output(Level::High);
 
     let mut periodic_timer = Timer::periodic(board.TIMER0);
-    // let lcd_timer = DurationTimer(Timer::one_shot(board.TIMER1));
-    // let mut lcd_timer = Timer::one_shot(board.TIMER1);
-    let mut lcd_timer = hal::Delay::new(core_peripherals.SYST);
     let mut sgp40_timer = Timer::one_shot(board.TIMER1);
     let mut sps30_timer = Timer::one_shot(board.TIMER2);
 
@@ -137,6 +273,17 @@ fn main() -> ! {
     let i2c_proxy_bmp388 = i2c_bus.acquire_i2c();
     let i2c_proxy_sps30 = i2c_bus.acquire_i2c();
 
+    // The battery backed clock is optional, without it the time has to be set
+    // after every power cycle
+    let mut ds3231 = Ds3231::new(i2c_bus.acquire_i2c());
+    match ds3231.read_time() {
+        Ok(time) => {
+            defmt::info!("Time from the DS3231: {=str}", time.iso8601().as_str());
+            rtc_clock.set(time.to_unix().unwrap());
+        }
+        Err(_) => defmt::warn!("Couldn't get the time from a DS3231, it isn't set"),
+    }
+
     defmt::info!("Setting up SCD30");
 
     let mut scd30 = SCD30::new(i2c_proxy_scd30);
@@ -179,7 +326,9 @@ fn main() -> ! {
             .into_iter(),
         )
         .unwrap();
-    let desired_offset: f32 = 3.72;
+    // The self-heating gets corrected for by the temperature fusion instead,
+    // which needs the raw readings
+    let desired_offset: f32 = 0.;
     let temperature_offset = scd30.read_temperature_offset().unwrap();
     defmt::info!(
         "SCD30 – current temp. offset: {=f32}, desired offset: {=f32}",
@@ -243,6 +392,7 @@ fn main() -> ! {
     // NOTE: don't forget that there must be atleast 0.6ms of delay before
     // making the first measurement
     let mut sgp40 = SGP40::new(i2c_proxy_sgp40, 1.);
+    let sgp40_serial = sgp40.get_serial_number(&mut sgp40_timer).ok();
 
     defmt::info!("Initializing BMP388 pressure sensor");
     // 0x76 is the address we get when the SDO pin of BMP388 is connected to
@@ -279,14 +429,15 @@ fn main() -> ! {
     // TODO: do we need to pull the i2c lines up to 5V (in hardware, as per the
     // datasheet)? Seems to be doing ok without it, though
     let mut sps30 = sps30_i2c::Sps30::new_sps30(i2c_proxy_sps30, sps30_timer);
+    let sps30_version = sps30.read_firmware_version().ok();
     let mut random = hal::rng::Rng::new(board.RNG);
     let rand_u8 = random.random_u8();
-    // Perform cleaning with p = 0.2, so that we don't needlessly do it on every
-    // startup. There's also automatic cleaning, which we don't have to manage.
+    // Perform cleaning only with the configured probability, so that we don't
+    // needlessly do it on every startup. There's also automatic cleaning, which we don't have to manage.
     // We do manual cleaning for occasions where the sensor doesn't run long
     // enough to trigger automatic cleaning (which happens after a week of
     // runtime).
-    let threshold = (u8::MAX as f32 / 5.) as u8;
+    let threshold = (u8::MAX as u16 * settings.cleaning_probability as u16 / 100) as u8;
     defmt::info!(
         "Rolled {=u8} (must not exceed {=u8} to perform cleaning)",
         rand_u8,
@@ -300,35 +451,85 @@ fn main() -> ! {
     }
     sps30.start_measurement();
 
-    defmt::info!("Initializing LCD");
-    let rs = pins_1.p1_10.into_push_pull_output(Level::Low);
-    let en = pins_1.p1_11.into_push_pull_output(Level::Low);
-    let d4 = pins_1.p1_12.into_push_pull_output(Level::Low);
-    let d5 = pins_1.p1_13.into_push_pull_output(Level::Low);
-    let d6 = pins_1.p1_14.into_push_pull_output(Level::Low);
-    let d7 = pins_1.p1_15.into_push_pull_output(Level::Low);
-
-    // let mut lcd = LCD1602::new(en, rs, d4, d5, d6, d7, lcd_timer).unwrap();
-    let mut lcd = HD44780::new_4bit(rs, en, d4, d5, d6, d7, &mut lcd_timer).unwrap();
-
-    // Unshift display and set cursor to 0
-    lcd.reset(&mut lcd_timer).unwrap();
-
-    // Clear existing characters
-    lcd.clear(&mut lcd_timer).unwrap();
+    defmt::info!("Setting up indoor air quality LED");
+    // The common anode RGB LED from hello-rgb. The pins are driven low during
+    // the "on" part of the PWM period, so the duty cycle is the brightness.
+    let iaq_pwm = Pwm::new(board.PWM1);
+    iaq_pwm
+        .set_output_pin(
+            pwm::Channel::C0,
+            pins_0.p0_03.into_push_pull_output(Level::High).degrade(),
+        )
+        .set_output_pin(
+            pwm::Channel::C1,
+            pins_0.p0_04.into_push_pull_output(Level::High).degrade(),
+        )
+        .set_output_pin(
+            pwm::Channel::C2,
+            pins_0.p0_28.into_push_pull_output(Level::High).degrade(),
+        );
+    let (iaq_red, iaq_green, iaq_blue, _) = iaq_pwm.split_channels();
+    let mut iaq_led = PwmLEDControl::new(iaq_red, iaq_green, iaq_blue);
+    iaq_led.set_color(0, 0, 0);
 
-    // Display the following string
-    lcd.write_str("Hello, world!", &mut lcd_timer).unwrap();
+    defmt::info!("Setting up alarm buzzer");
+    let buzzer_pwm = Pwm::new(board.PWM2);
+    buzzer_pwm
+        .set_output_pin(
+            pwm::Channel::C0,
+            pins_0.p0_29.into_push_pull_output(Level::Low).degrade(),
+        )
+        // Around the resonant frequency of common piezo buzzers
+        .set_period(hal::time::Hertz(2_700));
+    let (buzzer_channel, _, _, _) = buzzer_pwm.split_channels();
+    let mut buzzer = Buzzer::new(buzzer_channel).unwrap();
 
-    // Move the cursor to the second line
-    lcd.set_cursor_pos(40, &mut lcd_timer).unwrap();
+    defmt::info!("Initializing display");
+    #[cfg(not(any(feature = "lcd-i2c", feature = "ssd1306")))]
+    let mut display = {
+        let rs = pins_1.p1_10.into_push_pull_output(Level::Low);
+        let en = pins_1.p1_11.into_push_pull_output(Level::Low);
+        let d4 = pins_1.p1_12.into_push_pull_output(Level::Low);
+        let d5 = pins_1.p1_13.into_push_pull_output(Level::Low);
+        let d6 = pins_1.p1_14.into_push_pull_output(Level::Low);
+        let d7 = pins_1.p1_15.into_push_pull_output(Level::Low);
+        let mut lcd_timer = hal::Delay::new(core_peripherals.SYST);
+        let lcd = Lcd::new_4bit(rs, en, d4, d5, d6, d7, &mut lcd_timer).unwrap();
+        Hd44780Display::new(lcd, lcd_timer, 16, 2)
+    };
+    #[cfg(all(feature = "lcd-i2c", not(feature = "ssd1306")))]
+    let mut display = {
+        let mut lcd_timer = hal::Delay::new(core_peripherals.SYST);
+        // 0x27 is the default address of PCF8574 based backpacks
+        let lcd = Lcd::new_i2c(i2c_bus.acquire_i2c(), 0x27, &mut lcd_timer).unwrap();
+        Hd44780Display::new(lcd, lcd_timer, 16, 2)
+    };
+    #[cfg(feature = "ssd1306")]
+    let mut display = Ssd1306Display::new_i2c(i2c_bus.acquire_i2c()).unwrap();
 
-    // Display the following string on the second line
-    lcd.write_str("I'm on line 2!", &mut lcd_timer).unwrap();
+    display.clear().unwrap();
+    display.write_at(0, 0, "Hello, world!").unwrap();
+    display.write_at(0, 1, "I'm on line 2!").unwrap();
+    display.flush().unwrap();
 
     periodic_timer.delay_ms(500_u32);
     scd30.start_continuous_measurement(1013).unwrap();
-    lcd.clear(&mut lcd_timer).unwrap();
+    display.clear().unwrap();
+
+    // Set up last, so that the host doesn't have to wait for the sensors
+    // while enumerating the device
+    let usb_bus = UsbBusAllocator::new(Usbd::new(UsbPeripheral::new(board.USBD, &clocks)));
+    let mut usb = UsbSerial::new(&usb_bus);
+    let mut commands = LineReader::new();
+    let device_id = device_id(&board.FICR);
+    let mut ble = Ble::new(board.RADIO, board.TIMER4, board.PPI, &board.FICR, settings);
+    defmt::info!("Advertising as {:02x}", ble.address());
+    ble.start();
+    cortex_m::interrupt::free(|cs| BLE.borrow(cs).replace(Some(ble)));
+    unsafe {
+        cortex_m::peripheral::NVIC::unmask(hal::pac::Interrupt::RADIO);
+        cortex_m::peripheral::NVIC::unmask(hal::pac::Interrupt::TIMER4);
+    }
 
     defmt::info!("Entering loop");
     let mut seconds: u32 = 0;
@@ -346,29 +547,96 @@ fn main() -> ! {
     let mut rgb_pressure = RGB8::default();
     let mut rgb_pm10 = RGB8::default();
     let mut pm25_data = sps30_i2c::AirInfo::default();
-    let mut lcd_output_type = InfoType::GasesPressureAndParticles;
-    let mut clear_lcd = false;
+    let mut status = Status::default();
+    let mut alarms = Alarms::new(alarm_thresholds(&settings));
+    let mut thermal = TemperatureFusion::new(configured_sensors(&settings));
+    let mut compensation = CompensationManager::new(
+        COMPENSATION_THRESHOLD,
+        settings.altitude.max(0) as u16,
+        COMPENSATION_TIMEOUT,
+    );
+    let mut screen: Screen<16, 2> = Screen::new();
+    let mut page_idx = settings.page as usize % PAGES.len();
+    display.set_glyphs(PAGES[page_idx].glyph_set()).unwrap();
+    // When the button last changed the page, which gets saved once it's
+    // been left alone for a while rather than on every press
+    let mut page_changed: Option<u32> = None;
+    // What the rest of the loop has been set up for, to tell what changed
+    let mut applied_settings = settings;
     periodic_timer.start(1_000_000_u32);
+    let mut menu = Menu::new();
+    let mut redraw = false;
+    // How far a dump of the log has got
+    #[cfg(not(feature = "sdcard"))]
+    let mut dump: Option<(command::LogFormat, Position, Encoder)> = None;
     loop {
+        rtc_clock.update();
+        status.time = rtc_clock.clock().now();
+
         // One loop iteration runs for a second, so the button must be held for
-        // at least that long for this line to trigger
-        if button.check_rising_edge() {
-            lcd_output_type = lcd_output_type.next();
-            defmt::info!("Switched output to {:?}", lcd_output_type);
-            clear_lcd = true;
+        // at least that long for a press to register
+        match button.check_press(LONG_PRESS_SECONDS) {
+            // While an alarm is going off, the button deals with it instead,
+            // unless it's busy with the menu
+            Some(Press::Short) if alarms.is_sounding() && !menu.is_open() => {
+                defmt::info!("Alarm acknowledged");
+                alarms.acknowledge();
+            }
+            Some(Press::Long) if alarms.level() > alarm::Level::Normal && !menu.is_open() => {
+                defmt::info!("Alarm snoozed for {=u32} s", SNOOZE_SECONDS);
+                alarms.snooze(seconds, SNOOZE_SECONDS);
+            }
+            Some(press) if menu.is_open() || press == Press::Long => {
+                if let Some(Action::ResetStats) = menu.press(press == Press::Long) {
+                    defmt::info!("Resetting statistics");
+                    status.stats.reset();
+                }
+                // Either the menu changed or the page has to come back
+                display.clear().unwrap();
+                screen.invalidate();
+                redraw = true;
+            }
+            Some(Press::Short) => {
+                page_idx = (page_idx + 1) % PAGES.len();
+                defmt::info!("Switched output to {=str}", PAGES[page_idx].name);
+                display.set_glyphs(PAGES[page_idx].glyph_set()).unwrap();
+                // Either the glyphs changed or there's a chart to get rid of
+                display.clear().unwrap();
+                screen.invalidate();
+                settings.page = page_idx as u8;
+                page_changed = Some(seconds);
+            }
+            _ => {}
+        }
+        // Come back to the same page after a reboot
+        if page_changed.map_or(false, |at| seconds.wrapping_sub(at) >= PAGE_SAVE_DELAY) {
+            page_changed = None;
+            if settings_store.save(&settings).is_err() {
+                defmt::warn!("Couldn't save the settings");
+            }
         }
 
         if seconds % 5 == 0 {
             pressure_data = bmp388
                 .as_mut()
                 .and_then(|sensor| sensor.sensor_values().ok());
+            status.measurement.pressure = pressure_data.map(|data| data.pressure as f32);
+            status.measurement.pressure_sensor_temperature =
+                pressure_data.map(|data| data.temperature as f32);
+            status.measurement.sea_level_pressure = status
+                .measurement
+                .pressure
+                .map(|pressure| barometer::sea_level_pressure(pressure, settings.altitude as f32));
+            if let Some(pressure) = status.measurement.pressure {
+                status.pressure_trend.add(seconds, pressure);
+            }
 
-            rgb_pressure = match pressure_data {
-                Some(pressure_data) => {
-                    // 990 and 1040 hPa are min/max recorded atmospheric
-                    // pressures in last 3 years
-                    let pressure_hpa = ((pressure_data.pressure / 100.) as f32).round();
-                    let fraction = (pressure_hpa - 990.) / (1040. - 990.);
+            rgb_pressure = match status.measurement.sea_level_pressure {
+                Some(sea_level_pressure) => {
+                    let (min, max) = settings.pressure_range;
+                    let (min, max) = (min as f32, max as f32);
+                    let pressure_hpa = (sea_level_pressure / 100.).round();
+                    let fraction = (pressure_hpa - min) / (max - min);
                     let fraction = fraction.max(0.);
                     let (r, g, b) = logic::colormap::pressure_map_rgb(fraction);
                     // Scale the values so that we retain eyesight
@@ -384,11 +652,19 @@ fn main() -> ! {
                 .unwrap();
         }
 
-        if seconds % 3600 == 0 {
-            if let Some(pressure_data) = pressure_data {
-                let pressure_hpa = ((pressure_data.pressure / 100.) as f32).round() as u16;
-                defmt::info!("Setting SCD30 ambient pressure to {:?} hPa", pressure_hpa);
-                scd30.start_continuous_measurement(pressure_hpa).unwrap();
+        if seconds % 5 == 0 {
+            match compensation.update(seconds, status.measurement.pressure) {
+                Some(Compensation::Pressure(pressure_hpa)) => {
+                    defmt::info!("Setting SCD30 ambient pressure to {=u16} hPa", pressure_hpa);
+                    scd30.start_continuous_measurement(pressure_hpa).unwrap();
+                }
+                Some(Compensation::Altitude(altitude)) => {
+                    defmt::info!("Setting SCD30 altitude to {=u16} m", altitude);
+                    // Pressure compensation would take precedence
+                    scd30.start_continuous_measurement(0).unwrap();
+                    scd30.set_altitude_compensation(altitude).unwrap();
+                }
+                None => {}
             }
         }
 
@@ -400,9 +676,26 @@ fn main() -> ! {
                 }
             }
             reading = scd30.read_measurement().unwrap();
+            status.measurement.co2 = Some(reading.co2);
+            status.measurement.temperature = Some(reading.temperature);
+            status.measurement.rel_humidity = Some(reading.rel_humidity);
+            status.co2_history.add(seconds, reading.co2);
+            status.co2_trend.add(seconds, reading.co2);
+            if let Some(Action::Recalibrate {
+                reference,
+                correction,
+            }) = menu.update(seconds, reading.co2)
+            {
+                defmt::info!(
+                    "Forcing SCD30 recalibration to {=u16} ppm, correcting by {=f32} ppm",
+                    reference,
+                    correction
+                );
+                scd30.set_forced_recalibration(reference).unwrap();
+            }
 
-            // current baseline ppm is 424
-            let fraction = (reading.co2 - 424.) / (3000 - 424) as f32;
+            let baseline = settings.co2_baseline as f32;
+            let fraction = (reading.co2 - baseline) / (3000. - baseline);
             let fraction = fraction.max(0.);
             let (r, g, b) = logic::colormap::co2_map_rgb(fraction);
             // Scale the values so that we retain eyesight
@@ -421,6 +714,11 @@ fn main() -> ! {
                 }
             }
             pm25_data = sps30.read_measured_values().unwrap();
+            status.measurement.pm1_0 = Some(pm25_data.mass_pm1_0);
+            status.measurement.pm2_5 = Some(pm25_data.mass_pm2_5);
+            status.measurement.pm4_0 = Some(pm25_data.mass_pm4_0);
+            status.measurement.pm10 = Some(pm25_data.mass_pm10);
+            status.pm2_5_history.add(seconds, pm25_data.mass_pm2_5);
 
             let fraction = pm25_data.mass_pm10 / 50.;
             let fraction = fraction.max(0.);
@@ -437,6 +735,7 @@ fn main() -> ! {
 
         if seconds % 5 == 0 {
             builtin_temperature = temp.measure().to_num();
+            status.measurement.builtin_temperature = Some(builtin_temperature);
 
             // let fraction = builtin_temperature / 45.;
             // let fraction = fraction.max(0.);
@@ -451,11 +750,28 @@ fn main() -> ! {
             //     .unwrap();
         }
 
-        let voc_temp = builtin_temperature.round() as i16;
-        let voc_humidity = reading.rel_humidity.round() as u8;
+        let temperatures = [
+            Some(reading.temperature),
+            status.measurement.pressure_sensor_temperature,
+        ];
+        let ambient_temperature = thermal.ambient(temperatures, builtin_temperature);
+        status.measurement.ambient_temperature = ambient_temperature;
+        // The SCD30 measures the humidity of the air that it has warmed up
+        let rel_humidity = match ambient_temperature {
+            Some(ambient) => {
+                psychro::rel_humidity_at(reading.temperature, reading.rel_humidity, ambient)
+            }
+            None => reading.rel_humidity,
+        };
+        status.measurement.rel_humidity = Some(rel_humidity);
+        psychro::derive(&mut status.measurement);
+
+        let voc_temp = ambient_temperature.unwrap_or(reading.temperature).round() as i16;
+        let voc_humidity = rel_humidity.round() as u8;
         voc_index = sgp40
             .measure_signal_compensated(voc_temp, voc_humidity, &mut sgp40_timer)
             .unwrap();
+        status.measurement.voc_index = Some(voc_index as f32);
         let fraction = voc_index as f32 / 500.;
         let fraction = fraction.max(0.);
         let (r, g, b) = logic::colormap::voc_map_rgb(fraction);
@@ -468,7 +784,18 @@ fn main() -> ! {
             .write([rgb_co2, rgb_voc, rgb_pressure, rgb_pm10].into_iter())
             .unwrap();
 
+        if let Some(level) = alarms.update(seconds, &status.measurement) {
+            defmt::info!("Alarm level changed to {}", level);
+        }
+        buzzer.set(alarms.buzzer_on(seconds)).unwrap();
+        if alarms.indicator_on(seconds) {
+            builtin_led_2.set_low().unwrap();
+        } else {
+            builtin_led_2.set_high().unwrap();
+        }
+
         if seconds % 5 == 0 {
+            let self_heating = thermal.heating(temperatures, builtin_temperature);
             // TODO: figure out how to specify {=Option<f64>} explicitly
             defmt::info!(
                 "
@@ -477,9 +804,20 @@ fn main() -> ! {
                 Temp. builtin: {=f32} °C
                 Temp. bmp388: {} °C
                 Temp. diff: {=f32} °C
+                Temp. ambient: {} °C
+                Self-heating SCD30: {} °C
+                Self-heating bmp388: {} °C
                 Rel. humidity: {=f32} %
+                Dew point: {} °C
+                Abs. humidity: {} g/m³
+                Heat index: {} °C
+                Humidex: {}
+                Mould risk: {}
                 VOC idx: {=u16}
                 Pressue: {} Pa
+                Sea level pressure: {} Pa
+                Pressure tendency: {} hPa/3h
+                Forecast: {}
                 ====== Particles ======
                 Mass concentration PM1.0: {=f32} μg/m³
                 Mass concentration PM2.5: {=f32} μg/m³
@@ -497,9 +835,26 @@ fn main() -> ! {
                 builtin_temperature,
                 pressure_data.map(|rust_is_too_verbose| rust_is_too_verbose.temperature),
                 reading.temperature - builtin_temperature,
-                reading.rel_humidity,
+                ambient_temperature,
+                self_heating[0],
+                self_heating[1],
+                rel_humidity,
+                status.measurement.dew_point,
+                status.measurement.absolute_humidity,
+                status.measurement.heat_index,
+                status.measurement.humidex,
+                ambient_temperature.map(|ambient| psychro::mould_risk(ambient, rel_humidity)),
                 voc_index,
                 pressure_data.map(|rust_is_too_verbose| rust_is_too_verbose.pressure),
+                status.measurement.sea_level_pressure,
+                status.pressure_tendency().map(|tendency| tendency.change),
+                status
+                    .measurement
+                    .sea_level_pressure
+                    .zip(status.pressure_tendency())
+                    .map(|(pressure, tendency)| {
+                        barometer::zambretti(pressure, tendency.direction).text()
+                    }),
                 pm25_data.mass_pm1_0,
                 pm25_data.mass_pm2_5,
                 pm25_data.mass_pm4_0,
@@ -512,55 +867,58 @@ fn main() -> ! {
                 pm25_data.typical_size,
             );
 
-            if clear_lcd {
-                lcd.clear(&mut lcd_timer).unwrap();
-                clear_lcd = false;
+            status.stats.add_measurement(seconds, &status.measurement);
+            if seconds % LOG_INTERVAL == 0
+                && Storage::append(&mut log, rtc_clock.clock().timestamp(), &status.measurement)
+                    .is_err()
+            {
+                defmt::warn!("Couldn't log the measurement");
+            }
+            #[cfg(not(feature = "usb-json"))]
+            let line = logic::csv::row(rtc_clock.clock().timestamp(), &status.measurement);
+            #[cfg(feature = "usb-json")]
+            let line = logic::json::object(rtc_clock.clock().timestamp(), &status.measurement);
+            // The CSV log would be indistinguishable from the stream
+            #[cfg(not(feature = "sdcard"))]
+            let streaming = dump.is_none();
+            #[cfg(feature = "sdcard")]
+            let streaming = true;
+            if streaming {
+                usb.write_line(&line);
             }
+            with_ble(|ble| {
+                ble.set_advertising_data(&logic::bthome::advertising_data(&status.measurement));
+                ble.update(&status.measurement);
+            });
+            let (r, g, b) = status
+                .indoor_air_quality()
+                .map_or((0, 0, 0), |iaq| iaq.category.color());
+            // Scale the values so that we retain eyesight
+            iaq_led.set_color(r / 8, g / 8, b / 8);
+        }
 
-            lcd.set_cursor_pos(0, &mut lcd_timer).unwrap();
-            let co2_text = format_float_measurement(reading.co2, 4, 0, "ppm");
-            lcd.write_str(&co2_text, &mut lcd_timer).unwrap();
-
-            lcd.shift_cursor(Direction::Right, &mut lcd_timer).unwrap();
-            // TODO: can we make u32 stuff generic?
-            let voc_text = format_u32_measurement(voc_index as u32, 3, "voc");
-            lcd.write_str(&voc_text, &mut lcd_timer).unwrap();
-
-            match lcd_output_type {
-                InfoType::GasesAndTemp => {
-                    lcd.set_cursor_pos(40, &mut lcd_timer).unwrap();
-                    // TODO: Can't output °, because it's probably part of unicode, not
-                    // ascii, See if there's a workaround using the hd44780 font table
-                    let temp_text = format_float_measurement(builtin_temperature, 2, 2, "C");
-                    lcd.write_str(&temp_text, &mut lcd_timer).unwrap();
-
-                    lcd.shift_cursor(Direction::Right, &mut lcd_timer).unwrap();
-                    lcd.shift_cursor(Direction::Right, &mut lcd_timer).unwrap();
-                    let humidity_text = format_float_measurement(reading.rel_humidity, 2, 2, "%");
-                    lcd.write_str(&humidity_text, &mut lcd_timer).unwrap();
-                }
-                InfoType::GasesAndParticles => {
-                    lcd.set_cursor_pos(40, &mut lcd_timer).unwrap();
-                    let pm25_text = format_float_measurement(pm25_data.mass_pm2_5, 2, 1, "ug");
-                    lcd.write_str(&pm25_text, &mut lcd_timer).unwrap();
-
-                    lcd.shift_cursor(Direction::Right, &mut lcd_timer).unwrap();
-                    lcd.shift_cursor(Direction::Right, &mut lcd_timer).unwrap();
-                    let pm10_text = format_float_measurement(pm25_data.mass_pm10, 2, 1, "ug");
-                    lcd.write_str(&pm10_text, &mut lcd_timer).unwrap();
-                }
-                InfoType::GasesPressureAndParticles => {
-                    lcd.set_cursor_pos(40, &mut lcd_timer).unwrap();
-                    let pressure_value =
-                        pressure_data.map(|pressure_data| (pressure_data.pressure as f32).round());
-                    let pressure_text =
-                        format_float_measurement_optional(pressure_value, 6, 0, "Pa");
-                    lcd.write_str(&pressure_text, &mut lcd_timer).unwrap();
-
-                    // Can't have a space here, becuse there isn't enough horizontal space on the LCD
-                    // lcd.shift_cursor(Direction::Right, &mut lcd_timer).unwrap();
-                    let pm10_text = format_float_measurement(pm25_data.mass_pm10, 2, 1, "ug");
-                    lcd.write_str(&pm10_text, &mut lcd_timer).unwrap();
+        // The menu has to react to presses right away
+        if let Some(lines) = menu.lines() {
+            for (row, line) in lines.iter().enumerate() {
+                screen.frame.overwrite_row(row, line);
+            }
+            display.show(&mut screen).unwrap();
+        } else if seconds % 5 == 0 || redraw {
+            redraw = false;
+            screen.render_page(&PAGES[page_idx], &status);
+            if let Some(banner) = alarms.banner() {
+                screen.frame.overwrite_row(0, &banner);
+            }
+            display.show(&mut screen).unwrap();
+            // There's room for a proper chart below the text on the OLED
+            #[cfg(feature = "ssd1306")]
+            if let Some((_, quantity, span)) = PAGES[page_idx].sparkline() {
+                if let Some(history) = status.history(quantity) {
+                    let area = Rectangle::new(Point::new(0, 28), Size::new(128, 36));
+                    display
+                        .draw_line_chart(history, span as usize, area)
+                        .unwrap();
+                    display.flush().unwrap();
                 }
             }
         }
@@ -568,11 +926,235 @@ fn main() -> ! {
         builtin_led_1.set_state(builtin_led_state).unwrap();
         builtin_led_state = toggle_pin_state(builtin_led_state);
 
-        nb::block!(periodic_timer.wait()).unwrap();
+        // Keep USB going until the next second
+        while periodic_timer.wait().is_err() {
+            if usb.poll() {
+                defmt::info!("USB serial port opened");
+                #[cfg(not(feature = "usb-json"))]
+                usb.write_line(&logic::csv::header());
+            }
+            // A long log goes out a few records at a time, so that the rest
+            // of the loop doesn't have to wait for all of it. Commands wait
+            // until it's done.
+            #[cfg(not(feature = "sdcard"))]
+            if let Some((format, position, encoder)) = dump.as_mut() {
+                let mut records = log.records_from(*position);
+                let mut result = None;
+                for _ in 0..DUMP_BATCH {
+                    let sent = match records.next() {
+                        Some(Ok(record)) if *format == command::LogFormat::Csv => {
+                            usb.send_line(&logic::csv::row(record.timestamp, &record.measurement))
+                        }
+                        Some(Ok(record)) => usb.send_line(&command::raw_frame(
+                            &encoder.encode(record.timestamp, &record.measurement),
+                        )),
+                        Some(Err(_)) => {
+                            result = Some(Err(command::Error::Failed));
+                            break;
+                        }
+                        None if records.is_done() => {
+                            result = Some(Ok(()));
+                            break;
+                        }
+                        // A long empty stretch, carry on with it next time
+                        None => break,
+                    };
+                    // Nobody to send the rest to
+                    if !sent {
+                        result = Some(Err(command::Error::Failed));
+                        break;
+                    }
+                }
+                *position = records.position();
+                if let Some(result) = result {
+                    usb.send_line(match result {
+                        Ok(()) => command::OK,
+                        Err(error) => error.reply(),
+                    });
+                    dump = None;
+                }
+                continue;
+            }
+            let mut received = [0; 64];
+            let count = usb.read(&mut received);
+            for &byte in &received[..count] {
+                let command = match commands.push(byte) {
+                    Some(Ok(line)) => command::parse(&line),
+                    Some(Err(error)) => Err(error),
+                    None => continue,
+                };
+                defmt::info!("Serial command: {}", command);
+                let result = match command {
+                    Ok(Command::Help) => {
+                        for line in command::HELP {
+                            usb.send_line(line);
+                        }
+                        Ok(())
+                    }
+                    Ok(Command::Version) => {
+                        let mut line: heapless::String<32> = heapless::String::new();
+                        ufmt::uwrite!(line, "firmware {}\r\n", env!("CARGO_PKG_VERSION")).ok();
+                        usb.send_line(&line);
+                        line.clear();
+                        ufmt::uwrite!(line, "scd30 {}.{}\r\n", version.major, version.minor).ok();
+                        usb.send_line(&line);
+                        // The SGP40 only has a serial number to show
+                        line.clear();
+                        match sgp40_serial {
+                            Some(serial) => ufmt::uwrite!(line, "sgp40 {:012x}\r\n", serial),
+                            None => ufmt::uwrite!(line, "sgp40 unknown\r\n"),
+                        }
+                        .ok();
+                        usb.send_line(&line);
+                        line.clear();
+                        match sps30_version {
+                            Some((major, minor)) => {
+                                ufmt::uwrite!(line, "sps30 {}.{}\r\n", major, minor)
+                            }
+                            None => ufmt::uwrite!(line, "sps30 unknown\r\n"),
+                        }
+                        .ok();
+                        usb.send_line(&line);
+                        Ok(())
+                    }
+                    Ok(Command::Get(requested)) => {
+                        for setting in Setting::ALL {
+                            if requested.map_or(true, |requested| requested == setting) {
+                                usb.send_line(&setting.show(&settings));
+                            }
+                        }
+                        Ok(())
+                    }
+                    Ok(Command::Set(change)) => change.apply(&settings).and_then(|changed| {
+                        settings = changed;
+                        settings_store
+                            .save(&settings)
+                            .map_err(|_| command::Error::Failed)
+                    }),
+                    Ok(Command::Recalibrate(reference)) => {
+                        defmt::info!("Forcing SCD30 recalibration to {=u16} ppm", reference);
+                        scd30
+                            .set_forced_recalibration(reference)
+                            .map_err(|_| command::Error::Failed)
+                    }
+                    Ok(Command::Clean) => {
+                        defmt::info!("Performing SPS30 cleaning");
+                        sps30
+                            .start_fan_cleaning()
+                            .map_err(|_| command::Error::Failed)
+                    }
+                    Ok(Command::Time(None)) => match rtc_clock.clock().now() {
+                        Some(now) => {
+                            let mut line: heapless::String<24> = heapless::String::new();
+                            line.push_str(&DateTime::from_unix(now).iso8601()).ok();
+                            line.push_str("\r\n").ok();
+                            usb.send_line(&line);
+                            Ok(())
+                        }
+                        None => Err(command::Error::ClockNotSet),
+                    },
+                    Ok(Command::Time(Some(unix))) => {
+                        rtc_clock.set(unix);
+                        status.time = Some(unix);
+                        // Keep the battery backed clock in step, if there is one
+                        if ds3231.set_time(&DateTime::from_unix(unix)).is_err() {
+                            defmt::warn!("Couldn't set the DS3231");
+                        }
+                        Ok(())
+                    }
+                    // The records and the reply come later on. Clients wait for
+                    // the reply before sending more, so there's nothing left in
+                    // the packet to drop.
+                    #[cfg(not(feature = "sdcard"))]
+                    Ok(Command::DumpLog(format)) => {
+                        if format == command::LogFormat::Csv {
+                            usb.send_line(&logic::csv::header());
+                        }
+                        dump = Some((format, log.start(), Encoder::new(DUMP_KEY_INTERVAL)));
+                        break;
+                    }
+                    // The SD card can be read directly
+                    #[cfg(feature = "sdcard")]
+                    Ok(Command::DumpLog(_)) => Err(command::Error::Unavailable),
+                    Ok(Command::Metrics(format)) => {
+                        let labels = [("device", device_id.as_str())];
+                        match format {
+                            command::MetricsFormat::Prometheus => {
+                                for metric in logic::prometheus::metrics(
+                                    &labels,
+                                    status.time,
+                                    &status.measurement,
+                                ) {
+                                    usb.send_line(&metric);
+                                }
+                            }
+                            command::MetricsFormat::Influx => {
+                                if let Some(line) =
+                                    logic::influx::line(&labels, status.time, &status.measurement)
+                                {
+                                    usb.send_line(&line);
+                                }
+                            }
+                        }
+                        Ok(())
+                    }
+                    Err(error) => Err(error),
+                };
+                usb.send_line(match result {
+                    Ok(()) => command::OK,
+                    Err(error) => error.reply(),
+                });
+            }
+        }
+
+        // Over Bluetooth, the settings come all at once
+        if let Some(written) = with_ble(Ble::take_settings).flatten() {
+            defmt::info!("Settings written over Bluetooth: {}", written);
+            settings = written;
+            if settings_store.save(&settings).is_err() {
+                defmt::warn!("Couldn't save the settings");
+            }
+        }
+        if settings != applied_settings {
+            thermal.set_sensors(configured_sensors(&settings));
+            if settings.altitude != applied_settings.altitude {
+                compensation = CompensationManager::new(
+                    COMPENSATION_THRESHOLD,
+                    settings.altitude.max(0) as u16,
+                    COMPENSATION_TIMEOUT,
+                );
+            }
+            if settings.co2_alarm != applied_settings.co2_alarm {
+                alarms.set_thresholds(alarm_thresholds(&settings));
+            }
+            // The button changes the page by itself
+            if settings.page as usize % PAGES.len() != page_idx {
+                page_idx = settings.page as usize % PAGES.len();
+                display.set_glyphs(PAGES[page_idx].glyph_set()).unwrap();
+                display.clear().unwrap();
+                screen.invalidate();
+                redraw = true;
+            }
+            // The rest are read from the settings as they're needed
+            with_ble(|ble| ble.set_settings(settings));
+            applied_settings = settings;
+        }
         seconds = seconds.overflowing_add(1).0;
     }
 }
 
+/// The factory programmed ID as 16 hex digits, which tells devices apart in
+/// the metrics
+fn device_id(ficr: &hal::pac::FICR) -> heapless::String<16> {
+    let mut id = heapless::String::new();
+    let (high, low) = (
+        ficr.deviceid[1].read().bits(),
+        ficr.deviceid[0].read().bits(),
+    );
+    ufmt::uwrite!(id, "{:08x}{:08x}", high, low).unwrap();
+    id
+}
+
 fn toggle_pin_state(value: hal::prelude::PinState) -> hal::prelude::PinState {
     match value {
         hal::prelude::PinState::Low => hal::prelude::PinState::High,
@@ -580,22 +1162,458 @@ fn toggle_pin_state(value: hal::prelude::PinState) -> hal::prelude::PinState {
     }
 }
 
-#[derive(Debug, Copy, Clone, defmt::Format)]
-enum InfoType {
-    /// Display CO2, VOC index, temperature and relative humidity
-    GasesAndTemp,
-    /// Display CO2, VOC index, and PM2.5 and PM10 mass concentrations
-    GasesAndParticles,
-    /// Display CO2, VOC index, atmosphere pressure and PM10 mass concentrations
-    GasesPressureAndParticles,
+/// Everything that the pages can show
+#[derive(Default)]
+struct Status {
+    measurement: Measurement,
+    co2_history: DayHistory,
+    pm2_5_history: DayHistory,
+    stats: MeasurementStats,
+    co2_trend: DefaultCo2Trend,
+    pressure_trend: PressureTrend,
+    /// Unix time, if the clock has been set
+    time: Option<u32>,
+}
+
+impl Source for Status {
+    fn reading(&self, quantity: Quantity) -> Option<f32> {
+        self.measurement.get(quantity)
+    }
+
+    fn history(&self, quantity: Quantity) -> Option<&DayHistory> {
+        match quantity {
+            Quantity::Co2 => Some(&self.co2_history),
+            Quantity::Pm2_5 => Some(&self.pm2_5_history),
+            _ => None,
+        }
+    }
+
+    fn stats(&self, quantity: Quantity, period: Period) -> Option<Stats> {
+        Some(self.stats.get(quantity, period))
+    }
+
+    fn air_quality(&self, scale: Scale) -> Option<Index> {
+        scale.index_from_stats(&self.stats)
+    }
+
+    fn indoor_air_quality(&self) -> Option<Assessment> {
+        iaq::assess(IAQ_RULES, &self.measurement)
+    }
+
+    fn ventilation(&self) -> Option<Advice> {
+        self.co2_trend.advise(VENTILATION_THRESHOLD, OUTDOOR_CO2)
+    }
+
+    fn pressure_tendency(&self) -> Option<Tendency> {
+        self.pressure_trend.tendency()
+    }
+
+    fn time(&self) -> Option<u32> {
+        self.time
+    }
+}
+
+/// CO2 level at which it's time to open a window, ppm
+const VENTILATION_THRESHOLD: f32 = 1000.;
+/// What the CO2 decays towards when ventilating, ppm
+const OUTDOOR_CO2: f32 = 420.;
+
+/// The SCD30 gets the new ambient pressure when it has changed by more than
+/// this many hPa
+const COMPENSATION_THRESHOLD: f32 = 2.;
+/// Seconds without a pressure reading before the SCD30 compensates for the
+/// altitude instead
+const COMPENSATION_TIMEOUT: u32 = 10 * 60;
+
+/// The default alarm thresholds, with the CO2 ones from the settings
+fn alarm_thresholds(settings: &Settings) -> [Threshold; 3] {
+    let mut thresholds = alarm::DEFAULT_THRESHOLDS;
+    for threshold in &mut thresholds {
+        if threshold.quantity == Quantity::Co2 {
+            (threshold.warning, threshold.critical) =
+                (settings.co2_alarm.0 as f32, settings.co2_alarm.1 as f32);
+        }
+    }
+    thresholds
+}
+
+/// First page and number of pages of the storage area for the measurement log
+/// and the settings
+#[cfg(not(any(feature = "spi-flash", feature = "sdcard")))]
+const LOG_PAGES: (usize, usize) = (0, 126);
+const SETTINGS_PAGES: (usize, usize) = (126, 2);
+/// Seconds between logged measurements, which fills the internal flash in 11
+/// days
+const LOG_INTERVAL: u32 = 2 * 60;
+/// Records of the log that go out over USB at a time
+#[cfg(not(feature = "sdcard"))]
+const DUMP_BATCH: usize = 16;
+/// Frames of `log raw` from one key frame to the next
+#[cfg(not(feature = "sdcard"))]
+const DUMP_KEY_INTERVAL: u32 = 32;
+/// Seconds on a page before it's saved as the one to come back to, so that
+/// flicking through the pages doesn't wear out the flash
+const PAGE_SAVE_DELAY: u32 = 30;
+
+/// Limits of the indoor air quality categories
+const IAQ_RULES: &[Rule] = &iaq::DEFAULT_RULES;
+
+/// Holding the button down for this long opens the menu, or snoozes the alarm
+/// if there is one
+const LONG_PRESS_SECONDS: u32 = 3;
+const SNOOZE_SECONDS: u32 = 15 * 60;
+
+const CO2_FIELD: Field = Field::reading(0, 0, 8, Quantity::Co2, 0, "ppm");
+/// In the gap between the CO2 and VOC fields
+const TREND_FIELD: Field = Field {
+    col: 8,
+    row: 0,
+    width: 1,
+    align: Align::Left,
+    label: "",
+    content: Content::TrendArrow,
+};
+const VOC_FIELD: Field = Field::reading(9, 0, 7, Quantity::VocIndex, 0, "voc");
+const LAST_DAY_FIELD: Field = Field {
+    col: 9,
+    row: 0,
+    width: 7,
+    align: Align::Right,
+    label: "",
+    content: Content::Text("24h"),
+};
+
+/// The last 24 hours of `quantity` over the whole second row, 1.5 hours per
+/// character
+const fn sparkline_field(quantity: Quantity) -> Field {
+    Field {
+        col: 0,
+        row: 1,
+        width: 16,
+        align: Align::Left,
+        label: "",
+        content: Content::Sparkline {
+            quantity,
+            span: 288,
+        },
+    }
 }
 
-impl InfoType {
-    pub fn next(&self) -> Self {
-        match self {
-            Self::GasesAndTemp => Self::GasesAndParticles,
-            Self::GasesAndParticles => Self::GasesPressureAndParticles,
-            Self::GasesPressureAndParticles => Self::GasesAndTemp,
+/// A page showing the mean, min, max and standard deviation of `quantity`
+/// over `period`
+const fn stats_fields(
+    title: &'static str,
+    quantity: Quantity,
+    period: Period,
+    precision: u8,
+) -> [Field; 5] {
+    const fn statistic(
+        col: u8,
+        row: u8,
+        width: u8,
+        label: &'static str,
+        statistic: Statistic,
+        of: (Quantity, Period, u8),
+    ) -> Field {
+        Field {
+            col,
+            row,
+            width,
+            align: Align::Right,
+            label,
+            content: Content::Statistic {
+                quantity: of.0,
+                period: of.1,
+                statistic,
+                precision: of.2,
+                unit: "",
+            },
         }
     }
+    let of = (quantity, period, precision);
+    [
+        Field {
+            col: 0,
+            row: 0,
+            width: 8,
+            align: Align::Left,
+            label: "",
+            content: Content::Text(title),
+        },
+        statistic(8, 0, 8, "avg ", Statistic::Mean, of),
+        statistic(0, 1, 5, "↓", Statistic::Min, of),
+        statistic(6, 1, 5, "↑", Statistic::Max, of),
+        statistic(12, 1, 4, "σ", Statistic::StdDev, of),
+    ]
 }
+
+/// The name of the index with its value on the first row and the category
+/// on the second
+const fn air_quality_fields(scale: Scale) -> [Field; 3] {
+    [
+        Field {
+            col: 0,
+            row: 0,
+            width: 8,
+            align: Align::Left,
+            label: "",
+            content: Content::Text(scale.name()),
+        },
+        Field {
+            col: 8,
+            row: 0,
+            width: 8,
+            align: Align::Right,
+            label: "",
+            content: Content::AirQualityIndex(scale),
+        },
+        Field {
+            col: 0,
+            row: 1,
+            width: 16,
+            align: Align::Left,
+            label: "",
+            content: Content::AirQualityCategory(scale),
+        },
+    ]
+}
+
+const US_AQI: [Field; 3] = air_quality_fields(Scale::UsEpa);
+const EU_CAQI: [Field; 3] = air_quality_fields(Scale::EuCaqi);
+const UK_DAQI: [Field; 3] = air_quality_fields(Scale::UkDaqi);
+const CO2_LAST_HOUR: [Field; 5] = stats_fields("CO2 1h", Quantity::Co2, Period::LastHour, 0);
+const CO2_LAST_DAY: [Field; 5] = stats_fields("CO2 24h", Quantity::Co2, Period::LastDay, 0);
+const CO2_SINCE_BOOT: [Field; 5] = stats_fields("CO2 all", Quantity::Co2, Period::SinceBoot, 0);
+const PM2_5_LAST_DAY: [Field; 5] = stats_fields("PM2.5 1d", Quantity::Pm2_5, Period::LastDay, 1);
+
+/// Pages of the 16x2 LCD, the button cycles through them
+const PAGES: [Page; 18] = [
+    Page {
+        name: "gases and temperature",
+        fields: &[
+            CO2_FIELD,
+            TREND_FIELD,
+            VOC_FIELD,
+            Field::reading(0, 1, 8, Quantity::AmbientTemperature, 2, "°C"),
+            Field::reading(9, 1, 7, Quantity::RelHumidity, 2, "%"),
+        ],
+    },
+    Page {
+        name: "gases and particles",
+        fields: &[
+            CO2_FIELD,
+            TREND_FIELD,
+            VOC_FIELD,
+            Field::reading(0, 1, 7, Quantity::Pm2_5, 1, "µg"),
+            Field::reading(9, 1, 7, Quantity::Pm10, 1, "µg"),
+        ],
+    },
+    Page {
+        name: "gases, pressure and particles",
+        fields: &[
+            CO2_FIELD,
+            TREND_FIELD,
+            VOC_FIELD,
+            // There isn't enough horizontal space on the LCD for a gap here
+            Field::reading(0, 1, 9, Quantity::Pressure, 0, "Pa"),
+            Field::reading(9, 1, 7, Quantity::Pm10, 1, "µg"),
+        ],
+    },
+    Page {
+        name: "indoor air quality",
+        fields: &[
+            Field {
+                col: 0,
+                row: 0,
+                width: 16,
+                align: Align::Left,
+                label: "Air: ",
+                content: Content::IndoorAirQuality,
+            },
+            Field {
+                col: 0,
+                row: 1,
+                width: 16,
+                align: Align::Left,
+                label: "Worst: ",
+                content: Content::WorstPollutant,
+            },
+        ],
+    },
+    Page {
+        name: "ventilation",
+        fields: &[
+            CO2_FIELD,
+            TREND_FIELD,
+            Field {
+                col: 9,
+                row: 0,
+                width: 7,
+                align: Align::Right,
+                label: "",
+                content: Content::Co2Rate,
+            },
+            Field {
+                col: 0,
+                row: 1,
+                width: 16,
+                align: Align::Left,
+                label: "",
+                content: Content::VentilationAdvice,
+            },
+        ],
+    },
+    Page {
+        name: "dew point and absolute humidity",
+        fields: &[
+            Field {
+                col: 0,
+                row: 0,
+                width: 16,
+                align: Align::Left,
+                label: "Dew pt  ",
+                content: Content::Reading {
+                    quantity: Quantity::DewPoint,
+                    precision: 1,
+                    unit: "°C",
+                },
+            },
+            Field {
+                col: 0,
+                row: 1,
+                width: 16,
+                align: Align::Left,
+                label: "Abs hum ",
+                content: Content::Reading {
+                    quantity: Quantity::AbsoluteHumidity,
+                    precision: 1,
+                    unit: "g/m³",
+                },
+            },
+        ],
+    },
+    Page {
+        name: "heat index and mould risk",
+        fields: &[
+            Field {
+                col: 0,
+                row: 0,
+                width: 8,
+                align: Align::Left,
+                label: "HI ",
+                content: Content::Reading {
+                    quantity: Quantity::HeatIndex,
+                    precision: 0,
+                    unit: "°C",
+                },
+            },
+            Field {
+                col: 9,
+                row: 0,
+                width: 7,
+                align: Align::Left,
+                label: "Hx ",
+                content: Content::Reading {
+                    quantity: Quantity::Humidex,
+                    precision: 0,
+                    unit: "",
+                },
+            },
+            Field {
+                col: 0,
+                row: 1,
+                width: 16,
+                align: Align::Left,
+                label: "Mould ",
+                content: Content::MouldRisk,
+            },
+        ],
+    },
+    Page {
+        name: "weather forecast",
+        fields: &[
+            Field {
+                col: 0,
+                row: 0,
+                width: 16,
+                align: Align::Left,
+                label: "",
+                content: Content::PressureTendency,
+            },
+            Field {
+                col: 0,
+                row: 1,
+                width: 16,
+                align: Align::Left,
+                label: "",
+                content: Content::WeatherForecast,
+            },
+        ],
+    },
+    Page {
+        name: "CO2 history",
+        fields: &[
+            CO2_FIELD,
+            TREND_FIELD,
+            LAST_DAY_FIELD,
+            sparkline_field(Quantity::Co2),
+        ],
+    },
+    Page {
+        name: "PM2.5 history",
+        fields: &[
+            Field::reading(0, 0, 8, Quantity::Pm2_5, 1, "µg"),
+            LAST_DAY_FIELD,
+            sparkline_field(Quantity::Pm2_5),
+        ],
+    },
+    Page {
+        name: "CO2 statistics of the last hour",
+        fields: &CO2_LAST_HOUR,
+    },
+    Page {
+        name: "CO2 statistics of the last 24 hours",
+        fields: &CO2_LAST_DAY,
+    },
+    Page {
+        name: "CO2 statistics since boot",
+        fields: &CO2_SINCE_BOOT,
+    },
+    Page {
+        name: "PM2.5 statistics of the last 24 hours",
+        fields: &PM2_5_LAST_DAY,
+    },
+    Page {
+        name: "US EPA air quality index",
+        fields: &US_AQI,
+    },
+    Page {
+        name: "European common air quality index",
+        fields: &EU_CAQI,
+    },
+    Page {
+        name: "UK daily air quality index",
+        fields: &UK_DAQI,
+    },
+    Page {
+        name: "date and time",
+        fields: &[
+            Field {
+                col: 0,
+                row: 0,
+                width: 16,
+                align: Align::Left,
+                label: "",
+                content: Content::Date,
+            },
+            Field {
+                col: 0,
+                row: 1,
+                width: 16,
+                align: Align::Left,
+                label: "",
+                content: Content::TimeOfDay,
+            },
+        ],
+    },
+];
@@ -33,7 +33,37 @@ pub fn exit() -> ! {
 #[cfg(test)]
 #[defmt_test::tests]
 mod unit_tests {
+    use super::logic::alarm::tests as alarm_tests;
+    use super::logic::aqi::tests as aqi_tests;
+    use super::logic::barometer::tests as barometer_tests;
+    use super::logic::bthome::tests as bthome_tests;
+    use super::logic::calibration::tests as calibration_tests;
+    use super::logic::characteristic::tests as characteristic_tests;
+    use super::logic::charset::tests as charset_tests;
+    use super::logic::codec::tests as codec_tests;
+    use super::logic::command::tests as command_tests;
+    use super::logic::compensation::tests as compensation_tests;
+    use super::logic::csv::tests as csv_tests;
+    use super::logic::flash::tests as flash_tests;
     use super::logic::formatting::tests as formatting_tests;
+    use super::logic::gatt::tests as gatt_tests;
+    use super::logic::history::tests as history_tests;
+    use super::logic::iaq::tests as iaq_tests;
+    use super::logic::influx::tests as influx_tests;
+    use super::logic::json::tests as json_tests;
+    use super::logic::layout::tests as layout_tests;
+    use super::logic::link::tests as link_tests;
+    use super::logic::logger::tests as logger_tests;
+    use super::logic::menu::tests as menu_tests;
+    use super::logic::prometheus::tests as prometheus_tests;
+    use super::logic::psychro::tests as psychro_tests;
+    use super::logic::settings::tests as settings_tests;
+    use super::logic::sparkline::tests as sparkline_tests;
+    use super::logic::stats::tests as stats_tests;
+    use super::logic::storage::tests as storage_tests;
+    use super::logic::thermal::tests as thermal_tests;
+    use super::logic::time::tests as time_tests;
+    use super::logic::trend::tests as trend_tests;
     use super::peripherals::sgp40::tests as sgp40_tests;
     use defmt::assert;
 
@@ -106,4 +136,449 @@ mod unit_tests {
     fn format_float_carry_over() {
         formatting_tests::format_float_carry_over();
     }
+
+    #[test]
+    fn format_decimals() {
+        formatting_tests::format_decimals();
+    }
+
+    #[test]
+    fn charset_encode_ascii() {
+        charset_tests::encode_ascii();
+    }
+
+    #[test]
+    fn charset_encode_temperature() {
+        charset_tests::encode_temperature();
+    }
+
+    #[test]
+    fn charset_encode_mass_concentration() {
+        charset_tests::encode_mass_concentration();
+    }
+
+    #[test]
+    fn charset_encode_rom_and_unknown() {
+        charset_tests::encode_rom_and_unknown();
+    }
+
+    #[test]
+    fn layout_render_page() {
+        layout_tests::render_page();
+    }
+
+    #[test]
+    fn layout_render_missing_and_truncated() {
+        layout_tests::render_missing_and_truncated();
+    }
+
+    #[test]
+    fn layout_flush_only_changes() {
+        layout_tests::flush_only_changes();
+    }
+
+    #[test]
+    fn charset_encode_bars() {
+        charset_tests::encode_bars();
+    }
+
+    #[test]
+    fn history_averages_buckets() {
+        history_tests::averages_buckets();
+    }
+
+    #[test]
+    fn history_keeps_gaps_and_wraps() {
+        history_tests::keeps_gaps_and_wraps();
+    }
+
+    #[test]
+    fn sparkline_scales_to_range() {
+        sparkline_tests::scales_to_range();
+    }
+
+    #[test]
+    fn sparkline_groups_buckets_and_leaves_gaps() {
+        sparkline_tests::groups_buckets_and_leaves_gaps();
+    }
+
+    #[test]
+    fn layout_render_sparkline() {
+        layout_tests::render_sparkline();
+    }
+
+    #[test]
+    fn stats_stats_of_series() {
+        stats_tests::stats_of_series();
+    }
+
+    #[test]
+    fn stats_merge_matches_single_series() {
+        stats_tests::merge_matches_single_series();
+    }
+
+    #[test]
+    fn stats_window_drops_old_buckets() {
+        stats_tests::window_drops_old_buckets();
+    }
+
+    #[test]
+    fn stats_measurement_stats_and_reset() {
+        stats_tests::measurement_stats_and_reset();
+    }
+
+    #[test]
+    fn aqi_us_epa() {
+        aqi_tests::us_epa();
+    }
+
+    #[test]
+    fn aqi_eu_caqi() {
+        aqi_tests::eu_caqi();
+    }
+
+    #[test]
+    fn aqi_uk_daqi() {
+        aqi_tests::uk_daqi();
+    }
+
+    #[test]
+    fn iaq_categorize() {
+        iaq_tests::categorize();
+    }
+
+    #[test]
+    fn iaq_worst_quantity_wins() {
+        iaq_tests::worst_quantity_wins();
+    }
+
+    #[test]
+    fn iaq_custom_rules() {
+        iaq_tests::custom_rules();
+    }
+
+    #[test]
+    fn alarm_min_duration() {
+        alarm_tests::min_duration();
+    }
+
+    #[test]
+    fn alarm_hysteresis() {
+        alarm_tests::hysteresis();
+    }
+
+    #[test]
+    fn alarm_acknowledge_and_snooze() {
+        alarm_tests::acknowledge_and_snooze();
+    }
+
+    #[test]
+    fn trend_rising() {
+        trend_tests::rising();
+    }
+
+    #[test]
+    fn trend_decay() {
+        trend_tests::decay();
+    }
+
+    #[test]
+    fn trend_steady_and_above() {
+        trend_tests::steady_and_above();
+    }
+
+    #[test]
+    fn psychro_dew_point_and_absolute_humidity() {
+        psychro_tests::dew_point_and_absolute_humidity();
+    }
+
+    #[test]
+    fn psychro_apparent_temperature() {
+        psychro_tests::apparent_temperature();
+    }
+
+    #[test]
+    fn psychro_mould() {
+        psychro_tests::mould();
+    }
+
+    #[test]
+    fn barometer_sea_level() {
+        barometer_tests::sea_level();
+    }
+
+    #[test]
+    fn barometer_tendency() {
+        barometer_tests::tendency();
+    }
+
+    #[test]
+    fn barometer_forecast() {
+        barometer_tests::forecast();
+    }
+
+    #[test]
+    fn compensation_pressure_threshold() {
+        compensation_tests::pressure_threshold();
+    }
+
+    #[test]
+    fn compensation_altitude_fallback() {
+        compensation_tests::altitude_fallback();
+    }
+
+    #[test]
+    fn thermal_configured() {
+        thermal_tests::configured();
+    }
+
+    #[test]
+    fn calibration_pick_reference() {
+        calibration_tests::pick_reference();
+    }
+
+    #[test]
+    fn calibration_settles() {
+        calibration_tests::settles();
+    }
+
+    #[test]
+    fn calibration_times_out() {
+        calibration_tests::times_out();
+    }
+
+    #[test]
+    fn menu_navigate() {
+        menu_tests::navigate();
+    }
+
+    #[test]
+    fn menu_calibrate() {
+        menu_tests::calibrate();
+    }
+
+    #[test]
+    fn flash_crc() {
+        flash_tests::crc();
+    }
+
+    #[test]
+    fn flash_nor_semantics() {
+        flash_tests::nor_semantics();
+    }
+
+    #[test]
+    fn settings_blank_flash() {
+        settings_tests::blank_flash();
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        settings_tests::round_trip();
+    }
+
+    #[test]
+    fn settings_corruption() {
+        settings_tests::corruption();
+    }
+
+    #[test]
+    fn settings_older_and_newer_records() {
+        settings_tests::older_and_newer_records();
+    }
+
+    #[test]
+    fn settings_wear_levelling() {
+        settings_tests::wear_levelling();
+    }
+
+    #[test]
+    fn flash_partitions() {
+        flash_tests::partitions();
+    }
+
+    #[test]
+    fn logger_encoding() {
+        logger_tests::encoding();
+    }
+
+    #[test]
+    fn logger_append_and_read_back() {
+        logger_tests::append_and_read_back();
+    }
+
+    #[test]
+    fn logger_wraps_around() {
+        logger_tests::wraps_around();
+    }
+
+    #[test]
+    fn logger_power_loss() {
+        logger_tests::power_loss();
+    }
+
+    #[test]
+    fn csv_columns() {
+        csv_tests::columns();
+    }
+
+    #[test]
+    fn csv_rows() {
+        csv_tests::rows();
+    }
+
+    #[test]
+    fn storage_backends() {
+        storage_tests::backends();
+    }
+
+    #[test]
+    fn time_conversions() {
+        time_tests::conversions();
+    }
+
+    #[test]
+    fn time_clock() {
+        time_tests::clock();
+    }
+
+    #[test]
+    fn layout_render_time() {
+        layout_tests::render_time();
+    }
+
+    #[test]
+    fn json_objects() {
+        json_tests::objects();
+    }
+
+    #[test]
+    fn command_commands() {
+        command_tests::commands();
+    }
+
+    #[test]
+    fn command_settings() {
+        command_tests::settings();
+    }
+
+    #[test]
+    fn command_line_reader() {
+        command_tests::line_reader();
+    }
+
+    #[test]
+    fn command_raw_frames() {
+        command_tests::raw_frames();
+    }
+
+    #[test]
+    fn influx_lines() {
+        influx_tests::lines();
+    }
+
+    #[test]
+    fn influx_escaping() {
+        influx_tests::escaping();
+    }
+
+    #[test]
+    fn bthome_advertisements() {
+        bthome_tests::advertisements();
+    }
+
+    #[test]
+    fn characteristic_values() {
+        characteristic_tests::values();
+    }
+
+    #[test]
+    fn characteristic_uuids() {
+        characteristic_tests::uuids();
+    }
+
+    #[test]
+    fn characteristic_configs() {
+        characteristic_tests::configs();
+    }
+
+    #[test]
+    fn gatt_discovery() {
+        gatt_tests::discovery();
+    }
+
+    #[test]
+    fn gatt_notifications() {
+        gatt_tests::notifications();
+    }
+
+    #[test]
+    fn gatt_configuration() {
+        gatt_tests::configuration();
+    }
+
+    #[test]
+    fn codec_round_trips() {
+        codec_tests::round_trips();
+    }
+
+    #[test]
+    fn codec_deltas() {
+        codec_tests::deltas();
+    }
+
+    #[test]
+    fn codec_compatibility() {
+        codec_tests::compatibility();
+    }
+
+    #[test]
+    fn prometheus_expositions() {
+        prometheus_tests::expositions();
+    }
+
+    #[test]
+    fn prometheus_label_escaping() {
+        prometheus_tests::label_escaping();
+    }
+
+    #[test]
+    fn link_connect_requests() {
+        link_tests::connect_requests();
+    }
+
+    #[test]
+    fn link_updates() {
+        link_tests::updates();
+    }
+
+    #[test]
+    fn link_channels() {
+        link_tests::channels();
+    }
+
+    #[test]
+    fn format_float_no_fract() {
+        formatting_tests::format_float_no_fract();
+    }
+
+    #[test]
+    fn trend_messages_fit() {
+        trend_tests::messages_fit();
+    }
+
+    #[test]
+    fn logger_resumes() {
+        logger_tests::resumes();
+    }
+
+    #[test]
+    fn logger_empty_stretches() {
+        logger_tests::empty_stretches();
+    }
+
+    #[test]
+    fn thermal_from_settings() {
+        thermal_tests::from_settings();
+    }
 }
@@ -0,0 +1,320 @@
+//! Threshold alarms
+//!
+//! Each watched quantity has a warning and a critical threshold. A level is
+//! only entered after the value has stayed past its threshold for a minimum
+//! duration, and left once the value has dropped below the threshold by the
+//! hysteresis margin (again for the minimum duration), so that values hovering
+//! around a threshold don't make the alarm flap.
+
+use crate::logic::formatting::format_float_measurement;
+use crate::logic::measurement::{Measurement, Quantity};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub enum Level {
+    Normal,
+    Warning,
+    Critical,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Threshold {
+    pub quantity: Quantity,
+    pub warning: f32,
+    pub critical: f32,
+    /// How far below a threshold the value has to drop to leave its level
+    pub hysteresis: f32,
+    /// Seconds that the value has to stay in a new level before it takes
+    /// effect
+    pub min_duration: u32,
+}
+
+impl Threshold {
+    fn level(&self, value: f32) -> Level {
+        if value >= self.critical {
+            Level::Critical
+        } else if value >= self.warning {
+            Level::Warning
+        } else {
+            Level::Normal
+        }
+    }
+
+    /// The level that `value` calls for, given that we're currently at
+    /// `current`
+    fn target(&self, value: f32, current: Level) -> Level {
+        let level = self.level(value);
+        if level >= current {
+            level
+        } else {
+            // Only go down as far as the hysteresis allows
+            self.level(value + self.hysteresis).min(current)
+        }
+    }
+}
+
+pub const DEFAULT_THRESHOLDS: [Threshold; 3] = [
+    Threshold {
+        quantity: Quantity::Co2,
+        warning: 1500.,
+        critical: 3000.,
+        hysteresis: 100.,
+        min_duration: 60,
+    },
+    Threshold {
+        quantity: Quantity::VocIndex,
+        warning: 250.,
+        critical: 400.,
+        hysteresis: 20.,
+        min_duration: 60,
+    },
+    Threshold {
+        quantity: Quantity::Pm2_5,
+        warning: 35.,
+        critical: 55.,
+        hysteresis: 5.,
+        min_duration: 120,
+    },
+];
+
+#[derive(Debug, Clone, Copy)]
+struct State {
+    level: Level,
+    value: Option<f32>,
+    // A level that the value has been calling for since the given time
+    pending: Option<(Level, u32)>,
+}
+
+impl State {
+    const fn new() -> Self {
+        State {
+            level: Level::Normal,
+            value: None,
+            pending: None,
+        }
+    }
+
+    fn update(&mut self, threshold: &Threshold, now: u32, value: f32) {
+        self.value = Some(value);
+        let target = threshold.target(value, self.level);
+        if target == self.level {
+            self.pending = None;
+            return;
+        }
+        let since = match self.pending {
+            Some((level, since)) if level == target => since,
+            _ => {
+                self.pending = Some((target, now));
+                now
+            }
+        };
+        // Wrapping, because the seconds counter is allowed to overflow
+        if now.wrapping_sub(since) >= threshold.min_duration {
+            self.level = target;
+            self.pending = None;
+        }
+    }
+}
+
+pub struct Alarms<const N: usize> {
+    thresholds: [Threshold; N],
+    states: [State; N],
+    // The buzzer stays quiet until the level rises above this
+    acknowledged: Level,
+    // Start and length of the snooze, in seconds
+    snoozed: Option<(u32, u32)>,
+}
+
+impl<const N: usize> Alarms<N> {
+    pub const fn new(thresholds: [Threshold; N]) -> Self {
+        Alarms {
+            thresholds,
+            states: [State::new(); N],
+            acknowledged: Level::Normal,
+            snoozed: None,
+        }
+    }
+
+    /// Replace the thresholds, e.g. after the settings changed. The levels
+    /// catch up with the new thresholds over the following updates.
+    pub fn set_thresholds(&mut self, thresholds: [Threshold; N]) {
+        self.thresholds = thresholds;
+    }
+
+    /// Feed in the latest measurement taken at `now` seconds since boot.
+    /// Returns the new overall level if it changed.
+    pub fn update(&mut self, now: u32, measurement: &Measurement) -> Option<Level> {
+        let before = self.level();
+        for (threshold, state) in self.thresholds.iter().zip(self.states.iter_mut()) {
+            if let Some(value) = measurement.get(threshold.quantity) {
+                state.update(threshold, now, value);
+            }
+        }
+        if let Some((start, duration)) = self.snoozed {
+            if now.wrapping_sub(start) >= duration {
+                self.snoozed = None;
+            }
+        }
+        let level = self.level();
+        if level == Level::Normal {
+            // The next alarm will sound again
+            self.acknowledged = Level::Normal;
+        }
+        (level != before).then_some(level)
+    }
+
+    /// The highest level of any of the quantities
+    pub fn level(&self) -> Level {
+        self.states
+            .iter()
+            .map(|state| state.level)
+            .max()
+            .unwrap_or(Level::Normal)
+    }
+
+    /// The quantity with the highest level along with that level and the
+    /// latest value, unless everything's normal
+    pub fn worst(&self) -> Option<(Quantity, Level, f32)> {
+        self.thresholds
+            .iter()
+            .zip(self.states.iter())
+            .filter(|(_, state)| state.level > Level::Normal)
+            .fold(
+                None,
+                |worst: Option<(Quantity, Level, f32)>, (threshold, state)| match worst {
+                    Some(worst) if worst.1 >= state.level => Some(worst),
+                    _ => Some((threshold.quantity, state.level, state.value?)),
+                },
+            )
+    }
+
+    /// Silence the buzzer until the level rises further or the alarm clears
+    /// and triggers again
+    pub fn acknowledge(&mut self) {
+        self.acknowledged = self.level();
+    }
+
+    /// Silence the buzzer for `duration` seconds, no matter what
+    pub fn snooze(&mut self, now: u32, duration: u32) {
+        self.snoozed = Some((now, duration));
+    }
+
+    pub fn is_snoozed(&self) -> bool {
+        self.snoozed.is_some()
+    }
+
+    /// Whether there's an alarm that should be heard
+    pub fn is_sounding(&self) -> bool {
+        let level = self.level();
+        level > Level::Normal && level > self.acknowledged && !self.is_snoozed()
+    }
+
+    /// Whether the buzzer should be on during second `tick`: a short beep
+    /// every 10 seconds for warnings, every other second when critical
+    pub fn buzzer_on(&self, tick: u32) -> bool {
+        if !self.is_sounding() {
+            return false;
+        }
+        match self.level() {
+            Level::Normal => false,
+            Level::Warning => tick % 10 == 0,
+            Level::Critical => tick % 2 == 0,
+        }
+    }
+
+    /// Whether the indicator LED should be on during second `tick`. It keeps
+    /// blinking while there's an alarm, even if it's been silenced.
+    pub fn indicator_on(&self, tick: u32) -> bool {
+        match self.level() {
+            Level::Normal => false,
+            Level::Warning => tick % 4 == 0,
+            Level::Critical => tick % 2 == 0,
+        }
+    }
+
+    /// A line of text about the worst alarm, e.g. "ALARM CO2 3012"
+    pub fn banner(&self) -> Option<heapless::String<32>> {
+        let (quantity, level, value) = self.worst()?;
+        let mut banner = heapless::String::new();
+        banner
+            .push_str(match level {
+                Level::Critical => "ALARM ",
+                _ => "WARN ",
+            })
+            .ok();
+        banner.push_str(quantity.name()).ok();
+        banner.push(' ').ok();
+        let value = format_float_measurement(value.max(0.), 1, 0, "");
+        banner.push_str(value.trim_end()).ok();
+        Some(banner)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Alarms, Level, Threshold};
+    use crate::logic::measurement::{Measurement, Quantity};
+
+    const CO2: Threshold = Threshold {
+        quantity: Quantity::Co2,
+        warning: 1000.,
+        critical: 2000.,
+        hysteresis: 100.,
+        min_duration: 10,
+    };
+
+    fn co2(value: f32) -> Measurement {
+        Measurement {
+            co2: Some(value),
+            ..Default::default()
+        }
+    }
+
+    pub fn min_duration() {
+        let mut alarms = Alarms::new([CO2]);
+        assert_eq!(alarms.update(0, &co2(1200.)), None);
+        // A short dip resets the timer
+        assert_eq!(alarms.update(5, &co2(800.)), None);
+        assert_eq!(alarms.update(6, &co2(1200.)), None);
+        assert_eq!(alarms.update(15, &co2(1200.)), None);
+        assert_eq!(alarms.update(16, &co2(1200.)), Some(Level::Warning));
+        assert_eq!(alarms.worst(), Some((Quantity::Co2, Level::Warning, 1200.)));
+        assert_eq!(alarms.banner().unwrap().as_str(), "WARN CO2 1200");
+    }
+
+    pub fn hysteresis() {
+        let mut alarms = Alarms::new([CO2]);
+        alarms.update(0, &co2(2500.));
+        assert_eq!(alarms.update(10, &co2(2500.)), Some(Level::Critical));
+        // Below the threshold, but within the hysteresis
+        alarms.update(20, &co2(1950.));
+        assert_eq!(alarms.update(40, &co2(1950.)), None);
+        assert_eq!(alarms.level(), Level::Critical);
+        alarms.update(50, &co2(950.));
+        assert_eq!(alarms.update(60, &co2(950.)), Some(Level::Warning));
+        alarms.update(70, &co2(850.));
+        assert_eq!(alarms.update(80, &co2(850.)), Some(Level::Normal));
+        assert_eq!(alarms.banner(), None);
+    }
+
+    pub fn acknowledge_and_snooze() {
+        let mut alarms = Alarms::new([CO2]);
+        alarms.update(0, &co2(1500.));
+        alarms.update(10, &co2(1500.));
+        assert!(alarms.is_sounding());
+        assert!(alarms.buzzer_on(20));
+        alarms.acknowledge();
+        assert!(!alarms.is_sounding());
+        assert!(alarms.indicator_on(20));
+
+        // Escalating sounds again
+        alarms.update(20, &co2(2500.));
+        alarms.update(30, &co2(2500.));
+        assert!(alarms.is_sounding());
+
+        alarms.snooze(30, 60);
+        alarms.update(60, &co2(2500.));
+        assert!(!alarms.is_sounding());
+        alarms.update(90, &co2(2500.));
+        assert!(alarms.is_sounding());
+    }
+}
@@ -0,0 +1,287 @@
+//! Air quality indices computed from particulate matter concentrations
+//!
+//! Each index is defined over its own averaging period, see
+//! [`Scale::period`]. Only the PM2.5 and PM10 parts of the indices are
+//! covered, since those are the pollutants we measure.
+
+use micromath::F32Ext;
+
+use crate::logic::measurement::Quantity;
+use crate::logic::stats::{MeasurementStats, Period};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Scale {
+    /// US EPA Air Quality Index, 0 to 500
+    UsEpa,
+    /// European Common Air Quality Index (hourly), 0 to 100 and above
+    EuCaqi,
+    /// UK Daily Air Quality Index, 1 to 10
+    UkDaqi,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Category {
+    pub name: &'static str,
+    /// At most 16 characters, for the LCD
+    pub short_name: &'static str,
+    pub color: (u8, u8, u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Index {
+    pub scale: Scale,
+    pub value: u16,
+    pub category: Category,
+    /// The pollutant that determined the index
+    pub pollutant: Quantity,
+}
+
+/// A linear segment of a piecewise index definition
+struct Breakpoint {
+    concentration: (f32, f32),
+    index: (f32, f32),
+    category: usize,
+}
+
+const fn breakpoint(concentration: (f32, f32), index: (f32, f32), category: usize) -> Breakpoint {
+    Breakpoint {
+        concentration,
+        index,
+        category,
+    }
+}
+
+const fn category(name: &'static str, short_name: &'static str, color: u32) -> Category {
+    Category {
+        name,
+        short_name,
+        color: ((color >> 16) as u8, (color >> 8) as u8, color as u8),
+    }
+}
+
+const EPA_CATEGORIES: [Category; 6] = [
+    category("Good", "Good", 0x00e400),
+    category("Moderate", "Moderate", 0xffff00),
+    category(
+        "Unhealthy for Sensitive Groups",
+        "Unhealthy (sens)",
+        0xff7e00,
+    ),
+    category("Unhealthy", "Unhealthy", 0xff0000),
+    category("Very Unhealthy", "Very unhealthy", 0x8f3f97),
+    category("Hazardous", "Hazardous", 0x7e0023),
+];
+
+// The PM2.5 breakpoints are the ones revised in 2024
+const EPA_PM2_5: [Breakpoint; 6] = [
+    breakpoint((0.0, 9.0), (0., 50.), 0),
+    breakpoint((9.1, 35.4), (51., 100.), 1),
+    breakpoint((35.5, 55.4), (101., 150.), 2),
+    breakpoint((55.5, 125.4), (151., 200.), 3),
+    breakpoint((125.5, 225.4), (201., 300.), 4),
+    breakpoint((225.5, 325.4), (301., 500.), 5),
+];
+
+const EPA_PM10: [Breakpoint; 6] = [
+    breakpoint((0., 54.), (0., 50.), 0),
+    breakpoint((55., 154.), (51., 100.), 1),
+    breakpoint((155., 254.), (101., 150.), 2),
+    breakpoint((255., 354.), (151., 200.), 3),
+    breakpoint((355., 424.), (201., 300.), 4),
+    breakpoint((425., 604.), (301., 500.), 5),
+];
+
+const CAQI_CATEGORIES: [Category; 5] = [
+    category("Very low", "Very low", 0x79bc6a),
+    category("Low", "Low", 0xb9ce45),
+    category("Medium", "Medium", 0xedc100),
+    category("High", "High", 0xf69208),
+    category("Very high", "Very high", 0xf03667),
+];
+
+// The last segment is open ended: the index keeps growing at the same rate
+const CAQI_PM2_5: [Breakpoint; 5] = [
+    breakpoint((0., 15.), (0., 25.), 0),
+    breakpoint((15., 30.), (25., 50.), 1),
+    breakpoint((30., 55.), (50., 75.), 2),
+    breakpoint((55., 110.), (75., 100.), 3),
+    breakpoint((110., 165.), (100., 125.), 4),
+];
+
+const CAQI_PM10: [Breakpoint; 5] = [
+    breakpoint((0., 25.), (0., 25.), 0),
+    breakpoint((25., 50.), (25., 50.), 1),
+    breakpoint((50., 90.), (50., 75.), 2),
+    breakpoint((90., 180.), (75., 100.), 3),
+    breakpoint((180., 270.), (100., 125.), 4),
+];
+
+const DAQI_CATEGORIES: [Category; 4] = [
+    category("Low", "Low", 0x31ff00),
+    category("Moderate", "Moderate", 0xffcf00),
+    category("High", "High", 0xff0000),
+    category("Very High", "Very high", 0xce30ff),
+];
+
+/// Upper bounds of the bands 1 to 9, anything above is 10
+const DAQI_PM2_5: [f32; 9] = [11., 23., 35., 41., 47., 53., 58., 64., 70.];
+const DAQI_PM10: [f32; 9] = [16., 33., 50., 58., 66., 75., 83., 91., 100.];
+
+/// Interpolate linearly within the segment that `concentration` falls into.
+/// The index is capped at the top of the last segment unless `open_ended`.
+fn piecewise(breakpoints: &[Breakpoint], concentration: f32, open_ended: bool) -> (f32, usize) {
+    let last = &breakpoints[breakpoints.len() - 1];
+    let segment = breakpoints
+        .iter()
+        .find(|segment| concentration <= segment.concentration.1)
+        .unwrap_or(last);
+    let (c_lo, c_hi) = segment.concentration;
+    let (i_lo, i_hi) = segment.index;
+    let index = (i_hi - i_lo) / (c_hi - c_lo) * (concentration - c_lo) + i_lo;
+    let index = if open_ended { index } else { index.min(i_hi) };
+    (index.max(0.), segment.category)
+}
+
+impl Scale {
+    /// The period over which the concentrations have to be averaged
+    pub fn period(&self) -> Period {
+        match self {
+            Scale::UsEpa | Scale::UkDaqi => Period::LastDay,
+            Scale::EuCaqi => Period::LastHour,
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Scale::UsEpa => "US AQI",
+            Scale::EuCaqi => "EU CAQI",
+            Scale::UkDaqi => "UK DAQI",
+        }
+    }
+
+    /// The index for a single pollutant, either [`Quantity::Pm2_5`] or
+    /// [`Quantity::Pm10`], given its mean concentration over
+    /// [`Scale::period`] in µg/m³
+    pub fn sub_index(&self, pollutant: Quantity, concentration: f32) -> Option<Index> {
+        let (value, category) = match (self, pollutant) {
+            // The EPA truncates PM2.5 to one decimal place and PM10 to an
+            // integer before looking up the breakpoints
+            (Scale::UsEpa, Quantity::Pm2_5) => {
+                let concentration = (concentration * 10.).floor() / 10.;
+                let (value, category) = piecewise(&EPA_PM2_5, concentration, false);
+                (value, EPA_CATEGORIES[category])
+            }
+            (Scale::UsEpa, Quantity::Pm10) => {
+                let (value, category) = piecewise(&EPA_PM10, concentration.floor(), false);
+                (value, EPA_CATEGORIES[category])
+            }
+            (Scale::EuCaqi, Quantity::Pm2_5) => {
+                let (value, category) = piecewise(&CAQI_PM2_5, concentration, true);
+                (value, CAQI_CATEGORIES[category])
+            }
+            (Scale::EuCaqi, Quantity::Pm10) => {
+                let (value, category) = piecewise(&CAQI_PM10, concentration, true);
+                (value, CAQI_CATEGORIES[category])
+            }
+            (Scale::UkDaqi, Quantity::Pm2_5) => daqi(&DAQI_PM2_5, concentration),
+            (Scale::UkDaqi, Quantity::Pm10) => daqi(&DAQI_PM10, concentration),
+            _ => return None,
+        };
+        Some(Index {
+            scale: *self,
+            value: value.round() as u16,
+            category,
+            pollutant,
+        })
+    }
+
+    /// The overall index is that of the worst pollutant. Either of the mean
+    /// concentrations may be missing, but not both.
+    pub fn index(&self, pm2_5: Option<f32>, pm10: Option<f32>) -> Option<Index> {
+        let pm2_5 = pm2_5.and_then(|value| self.sub_index(Quantity::Pm2_5, value));
+        let pm10 = pm10.and_then(|value| self.sub_index(Quantity::Pm10, value));
+        match (pm2_5, pm10) {
+            (Some(pm2_5), Some(pm10)) if pm10.value > pm2_5.value => Some(pm10),
+            (Some(pm2_5), _) => Some(pm2_5),
+            (None, pm10) => pm10,
+        }
+    }
+
+    /// The index from the rolling means in `stats`
+    pub fn index_from_stats(&self, stats: &MeasurementStats) -> Option<Index> {
+        let period = self.period();
+        self.index(
+            stats.get(Quantity::Pm2_5, period).mean(),
+            stats.get(Quantity::Pm10, period).mean(),
+        )
+    }
+}
+
+fn daqi(upper_bounds: &[f32; 9], concentration: f32) -> (f32, Category) {
+    let concentration = concentration.round();
+    let band = upper_bounds
+        .iter()
+        .position(|&bound| concentration <= bound)
+        .unwrap_or(upper_bounds.len())
+        + 1;
+    let category = match band {
+        1..=3 => 0,
+        4..=6 => 1,
+        7..=9 => 2,
+        _ => 3,
+    };
+    (band as f32, DAQI_CATEGORIES[category])
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::Scale;
+    use crate::logic::measurement::Quantity;
+
+    pub fn us_epa() {
+        let index = Scale::UsEpa.index(Some(12.0), Some(20.)).unwrap();
+        assert_eq!(index.value, 56);
+        assert_eq!(index.pollutant, Quantity::Pm2_5);
+        assert_eq!(index.category.name, "Moderate");
+        assert_eq!(index.category.color, (255, 255, 0));
+
+        let index = Scale::UsEpa.index(Some(5.), Some(100.)).unwrap();
+        assert_eq!(index.value, 73);
+        assert_eq!(index.pollutant, Quantity::Pm10);
+
+        // Exact breakpoints, truncation and the cap at the top
+        let pm2_5 = |value| Scale::UsEpa.sub_index(Quantity::Pm2_5, value).unwrap();
+        assert_eq!(pm2_5(35.4).value, 100);
+        assert_eq!(pm2_5(9.09).value, 50);
+        assert_eq!(pm2_5(9.09).category.name, "Good");
+        assert_eq!(pm2_5(1000.).value, 500);
+        assert_eq!(pm2_5(1000.).category.name, "Hazardous");
+    }
+
+    pub fn eu_caqi() {
+        let index = Scale::EuCaqi.index(Some(20.), Some(40.)).unwrap();
+        assert_eq!(index.value, 40);
+        assert_eq!(index.pollutant, Quantity::Pm10);
+        assert_eq!(index.category.name, "Low");
+
+        let index = Scale::EuCaqi.index(Some(20.), None).unwrap();
+        assert_eq!(index.value, 33);
+        // Above 100 the index keeps growing
+        let index = Scale::EuCaqi.index(None, Some(216.)).unwrap();
+        assert_eq!(index.value, 110);
+        assert_eq!(index.category.short_name, "Very high");
+    }
+
+    pub fn uk_daqi() {
+        let index = Scale::UkDaqi.index(Some(30.), Some(60.)).unwrap();
+        assert_eq!(index.value, 5);
+        assert_eq!(index.pollutant, Quantity::Pm10);
+        assert_eq!(index.category.name, "Moderate");
+
+        let index = Scale::UkDaqi.index(Some(71.), None).unwrap();
+        assert_eq!(index.value, 10);
+        assert_eq!(index.category.name, "Very High");
+        assert_eq!(Scale::UkDaqi.index(Some(11.4), None).unwrap().value, 1);
+        assert_eq!(Scale::UkDaqi.index(None, None), None);
+    }
+}
@@ -0,0 +1,271 @@
+//! Weather hints from the atmospheric pressure
+//!
+//! The pressure tendency is the change over the last three hours, classified
+//! the way the WMO and the Met Office describe it in shipping forecasts. The
+//! forecast follows the simplified Zambretti algorithm, which only needs the
+//! sea level pressure and the direction of the tendency. The seasonal and
+//! wind direction corrections of the original are left out, since we have
+//! neither a calendar nor a wind vane.
+
+use micromath::F32Ext;
+
+use crate::logic::history::{History, BUCKET_SECONDS};
+use crate::logic::trend::Direction;
+
+/// The tendency is defined over three hours
+const TENDENCY_BUCKETS: usize = (3 * 60 * 60 / BUCKET_SECONDS) as usize;
+
+/// Until three hours have been recorded, the tendency is extrapolated from at
+/// least an hour
+const MIN_TENDENCY_BUCKETS: usize = TENDENCY_BUCKETS / 3;
+
+/// Reduce the `pressure` measured at `altitude` metres above sea level to sea
+/// level, assuming the international standard atmosphere. Both pressures are
+/// in Pa.
+pub fn sea_level_pressure(pressure: f32, altitude: f32) -> f32 {
+    // That's `pressure * (1 - d)^-5.255` with `d = altitude / 44330`, but
+    // micromath's powf is only good to about 0.2%, which would be 2 hPa. Since
+    // `d` is small, the series for ln and exp converge quickly instead.
+    let d = altitude / 44_330.;
+    let ln = -(d + d * d / 2. + d * d * d / 3. + d * d * d * d / 4.);
+    let exponent = -5.255 * ln;
+    let (mut term, mut factor) = (1., 1.);
+    for k in 1..8 {
+        term *= exponent / k as f32;
+        factor += term;
+    }
+    pressure * factor
+}
+
+/// How fast the pressure is changing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub enum Rate {
+    /// Less than 0.1 hPa in three hours
+    Steady,
+    /// 0.1 to 1.5 hPa
+    Slowly,
+    /// 1.6 to 3.5 hPa
+    Moderately,
+    /// 3.6 to 6.0 hPa
+    Quickly,
+    /// More than 6.0 hPa
+    VeryRapidly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tendency {
+    pub direction: Direction,
+    pub rate: Rate,
+    /// Change over three hours, hPa
+    pub change: f32,
+}
+
+impl Tendency {
+    pub fn from_change(change: f32) -> Self {
+        // The boundaries are in tenths of a hPa
+        let tenths = (change.abs() * 10.).round();
+        let rate = if tenths < 1. {
+            Rate::Steady
+        } else if tenths <= 15. {
+            Rate::Slowly
+        } else if tenths <= 35. {
+            Rate::Moderately
+        } else if tenths <= 60. {
+            Rate::Quickly
+        } else {
+            Rate::VeryRapidly
+        };
+        let direction = match rate {
+            Rate::Steady => Direction::Steady,
+            _ if change > 0. => Direction::Rising,
+            _ => Direction::Falling,
+        };
+        Tendency {
+            direction,
+            rate,
+            change,
+        }
+    }
+
+    /// E.g. "Falling slowly", fits on a 16 character row. "Very rapidly" is
+    /// shortened to "rapidly".
+    pub fn name(&self) -> &'static str {
+        match (self.direction, self.rate) {
+            (Direction::Steady, _) | (_, Rate::Steady) => "Steady",
+            (Direction::Rising, Rate::Slowly) => "Rising slowly",
+            (Direction::Rising, Rate::Moderately) => "Rising",
+            (Direction::Rising, Rate::Quickly) => "Rising quickly",
+            (Direction::Rising, Rate::VeryRapidly) => "Rising rapidly",
+            (Direction::Falling, Rate::Slowly) => "Falling slowly",
+            (Direction::Falling, Rate::Moderately) => "Falling",
+            (Direction::Falling, Rate::Quickly) => "Falling quickly",
+            (Direction::Falling, Rate::VeryRapidly) => "Falling rapidly",
+        }
+    }
+}
+
+/// Keeps enough of the station pressure history for the tendency
+pub struct PressureTrend {
+    history: History<{ TENDENCY_BUCKETS + 1 }>,
+}
+
+impl PressureTrend {
+    pub const fn new() -> Self {
+        PressureTrend {
+            history: History::new(),
+        }
+    }
+
+    /// Add a reading in Pa, taken at `now` seconds since boot
+    pub fn add(&mut self, now: u32, pressure: f32) {
+        self.history.add(now, pressure);
+    }
+
+    /// The change from three hours ago up to the last completed bucket
+    pub fn tendency(&self) -> Option<Tendency> {
+        let (mut oldest, mut newest) = (None, None);
+        for (i, value) in self.history.recent(TENDENCY_BUCKETS + 1).enumerate() {
+            if let Some(value) = value {
+                oldest = oldest.or(Some((i, value)));
+                newest = Some((i, value));
+            }
+        }
+        let ((first, from), (last, to)) = (oldest?, newest?);
+        let span = last - first;
+        if span < MIN_TENDENCY_BUCKETS {
+            return None;
+        }
+        let change = (to - from) / 100. * TENDENCY_BUCKETS as f32 / span as f32;
+        Some(Tendency::from_change(change))
+    }
+}
+
+impl Default for PressureTrend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One of the forecasts of the Zambretti forecaster, numbered 1 to 32
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Forecast(pub u8);
+
+/// (text, at most 16 characters for the LCD)
+const FORECASTS: [(&str, &str); 32] = [
+    // Falling
+    ("Settled fine", "Settled fine"),
+    ("Fine weather", "Fine weather"),
+    ("Fine, becoming less settled", "Fine, worsening"),
+    ("Fairly fine, showery later", "Showers later"),
+    ("Showery, becoming more unsettled", "Showery, worse"),
+    ("Unsettled, rain later", "Rain later"),
+    ("Rain at times, worse later", "Rain, worse"),
+    ("Rain at times, becoming very unsettled", "Rain, unsettled"),
+    ("Very unsettled, rain", "Very unsettled"),
+    // Steady
+    ("Settled fine", "Settled fine"),
+    ("Fine weather", "Fine weather"),
+    ("Fine, possibly showers", "Fine, showers?"),
+    ("Fairly fine, showers likely", "Showers likely"),
+    ("Showery, bright intervals", "Showery, bright"),
+    ("Changeable, some rain", "Changeable"),
+    ("Unsettled, rain at times", "Rain at times"),
+    ("Rain at frequent intervals", "Frequent rain"),
+    ("Very unsettled, rain", "Unsettled, rain"),
+    ("Stormy, much rain", "Stormy, rain"),
+    // Rising
+    ("Settled fine", "Settled fine"),
+    ("Fine weather", "Fine weather"),
+    ("Becoming fine", "Becoming fine"),
+    ("Fairly fine, improving", "Fine, improving"),
+    ("Fairly fine, possibly showers early", "Early showers"),
+    ("Showery early, improving", "Showers, better"),
+    ("Changeable, mending", "Mending"),
+    ("Rather unsettled, clearing later", "Clearing later"),
+    ("Unsettled, probably improving", "Unsettled,better"),
+    ("Unsettled, short fine intervals", "Fine intervals"),
+    ("Very unsettled, finer at times", "Very unsettled"),
+    ("Stormy, possibly improving", "Stormy, better"),
+    ("Stormy, much rain", "Stormy, rain"),
+];
+
+impl Forecast {
+    pub fn text(&self) -> &'static str {
+        FORECASTS[self.0 as usize - 1].0
+    }
+
+    pub fn short_text(&self) -> &'static str {
+        FORECASTS[self.0 as usize - 1].1
+    }
+}
+
+/// Forecast from the sea level pressure in Pa and the direction of the
+/// tendency
+pub fn zambretti(sea_level_pressure: f32, direction: Direction) -> Forecast {
+    let hpa = sea_level_pressure / 100.;
+    let (z, range) = match direction {
+        Direction::Falling => (127. - 0.12 * hpa, 1..=9),
+        Direction::Steady => (144. - 0.13 * hpa, 10..=19),
+        Direction::Rising => (185. - 0.16 * hpa, 20..=32),
+    };
+    let z = (z.round().max(0.) as u8).clamp(*range.start(), *range.end());
+    Forecast(z)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{sea_level_pressure, zambretti, PressureTrend, Rate, Tendency};
+    use crate::logic::history::BUCKET_SECONDS;
+    use crate::logic::trend::Direction;
+    use micromath::F32Ext;
+
+    pub fn sea_level() {
+        assert_eq!(sea_level_pressure(101_325., 0.), 101_325.);
+        // About 12 Pa per metre near sea level
+        let pressure = sea_level_pressure(100_000., 100.);
+        assert!((pressure - 101_194.).abs() < 20., "{}", pressure);
+    }
+
+    pub fn tendency() {
+        assert_eq!(Tendency::from_change(0.04).rate, Rate::Steady);
+        assert_eq!(Tendency::from_change(0.04).direction, Direction::Steady);
+        assert_eq!(Tendency::from_change(-1.5).rate, Rate::Slowly);
+        assert_eq!(Tendency::from_change(-1.5).name(), "Falling slowly");
+        assert_eq!(Tendency::from_change(2.).name(), "Rising");
+        assert_eq!(Tendency::from_change(6.1).name(), "Rising rapidly");
+
+        let mut trend = PressureTrend::new();
+        // Falling 1 Pa per bucket
+        for i in 0..12 {
+            trend.add(i * BUCKET_SECONDS, 100_000. - i as f32);
+        }
+        // Less than an hour recorded
+        assert_eq!(trend.tendency(), None);
+        for i in 12..50 {
+            trend.add(i * BUCKET_SECONDS, 100_000. - i as f32);
+        }
+        let tendency = trend.tendency().unwrap();
+        assert_eq!(tendency.direction, Direction::Falling);
+        assert!(
+            (tendency.change + 0.36).abs() < 0.001,
+            "{}",
+            tendency.change
+        );
+    }
+
+    pub fn forecast() {
+        assert_eq!(zambretti(103_000., Direction::Rising).0, 20);
+        assert_eq!(
+            zambretti(103_000., Direction::Rising).text(),
+            "Settled fine"
+        );
+        assert_eq!(zambretti(101_300., Direction::Steady).0, 12);
+        assert_eq!(zambretti(99_000., Direction::Falling).0, 8);
+        assert_eq!(zambretti(95_000., Direction::Falling).0, 9);
+        assert_eq!(zambretti(95_000., Direction::Rising).0, 32);
+        assert_eq!(
+            zambretti(95_000., Direction::Rising).short_text(),
+            "Stormy, rain"
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! Measurements as BTHome v2 advertisements, which Home Assistant picks up
+//! without any pairing
+//!
+//! The values go into the service data of a legacy advertisement, as
+//! unencrypted objects in the order of their IDs. See
+//! <https://bthome.io/format/>.
+
+use micromath::F32Ext;
+
+use crate::logic::measurement::Measurement;
+
+/// The 16 bit UUID that BTHome's service data is under
+pub const UUID: u16 = 0xfcd2;
+/// BTHome version 2, not encrypted, sent at regular intervals
+const DEVICE_INFO: u8 = 0x40;
+
+/// AD types
+const FLAGS: u8 = 0x01;
+const SERVICE_DATA: u8 = 0x16;
+/// LE General Discoverable Mode, BR/EDR Not Supported
+const LE_ONLY: u8 = 0x06;
+
+/// A legacy advertisement has room for 31 bytes of data
+pub type AdvertisingData = heapless::Vec<u8, 31>;
+
+/// An object ID and how its value is encoded
+struct Object {
+    id: u8,
+    /// The value gets multiplied by this and rounded
+    scale: f32,
+    /// Little endian, 2 or 3 bytes
+    size: usize,
+    signed: bool,
+}
+
+/// °C in 0.01 steps
+const TEMPERATURE: Object = Object {
+    id: 0x02,
+    scale: 100.,
+    size: 2,
+    signed: true,
+};
+/// % in 0.01 steps
+const HUMIDITY: Object = Object {
+    id: 0x03,
+    scale: 100.,
+    size: 2,
+    signed: false,
+};
+/// 0.01 hPa steps, which is Pa
+const PRESSURE: Object = Object {
+    id: 0x04,
+    scale: 1.,
+    size: 3,
+    signed: false,
+};
+/// µg/m³
+const PM2_5: Object = Object {
+    id: 0x0d,
+    scale: 1.,
+    size: 2,
+    signed: false,
+};
+const PM10: Object = Object {
+    id: 0x0e,
+    scale: 1.,
+    size: 2,
+    signed: false,
+};
+/// ppm
+const CO2: Object = Object {
+    id: 0x12,
+    scale: 1.,
+    size: 2,
+    signed: false,
+};
+/// BTHome has no VOC index, only TVOC in µg/m³, which the index isn't, so it
+/// goes out as a plain count
+const COUNT: Object = Object {
+    id: 0x3d,
+    scale: 1.,
+    size: 2,
+    signed: false,
+};
+
+/// The flags and the BTHome service data for `measurement`. Only the values
+/// that are present get sent. With all of them it takes 30 bytes.
+pub fn advertising_data(measurement: &Measurement) -> AdvertisingData {
+    let mut data = AdvertisingData::new();
+    data.extend_from_slice(&[2, FLAGS, LE_ONLY]).unwrap();
+    // The length goes in once the objects are in
+    let start = data.len();
+    data.push(0).unwrap();
+    data.push(SERVICE_DATA).unwrap();
+    data.extend_from_slice(&UUID.to_le_bytes()).unwrap();
+    data.push(DEVICE_INFO).unwrap();
+    let temperature = measurement.ambient_temperature.or(measurement.temperature);
+    for (object, value) in [
+        (TEMPERATURE, temperature),
+        (HUMIDITY, measurement.rel_humidity),
+        (PRESSURE, measurement.pressure),
+        (PM2_5, measurement.pm2_5),
+        (PM10, measurement.pm10),
+        (CO2, measurement.co2),
+        (COUNT, measurement.voc_index),
+    ] {
+        if let Some(value) = value {
+            push_object(&mut data, &object, value);
+        }
+    }
+    data[start] = (data.len() - start - 1) as u8;
+    data
+}
+
+/// Values out of the object's range get clamped to it
+fn push_object(data: &mut AdvertisingData, object: &Object, value: f32) {
+    let bits = 8 * object.size as u32;
+    let (min, max) = if object.signed {
+        (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+    } else {
+        (0, (1 << bits) - 1)
+    };
+    let value = ((value * object.scale).round() as i32).clamp(min, max);
+    data.push(object.id).unwrap();
+    data.extend_from_slice(&value.to_le_bytes()[..object.size])
+        .unwrap();
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::advertising_data;
+    use crate::logic::measurement::Measurement;
+
+    pub fn advertisements() {
+        let measurement = Measurement {
+            co2: Some(612.4),
+            temperature: Some(23.1),
+            ambient_temperature: Some(21.374),
+            rel_humidity: Some(45.67),
+            pressure: Some(101_325.),
+            pm2_5: Some(3.1),
+            pm10: Some(7.8),
+            voc_index: Some(100.),
+            ..Default::default()
+        };
+        assert_eq!(
+            advertising_data(&measurement).as_slice(),
+            &[
+                0x02, 0x01, 0x06, // flags
+                0x1a, 0x16, 0xd2, 0xfc, 0x40, // service data header
+                0x02, 0x59, 0x08, // 21.37 °C
+                0x03, 0xd7, 0x11, // 45.67 %
+                0x04, 0xcd, 0x8b, 0x01, // 1013.25 hPa
+                0x0d, 0x03, 0x00, // 3 µg/m³
+                0x0e, 0x08, 0x00, // 8 µg/m³
+                0x12, 0x64, 0x02, // 612 ppm
+                0x3d, 0x64, 0x00, // 100
+            ]
+        );
+
+        let cold = Measurement {
+            temperature: Some(-5.),
+            co2: Some(100_000.),
+            ..Default::default()
+        };
+        assert_eq!(
+            advertising_data(&cold).as_slice(),
+            &[0x02, 0x01, 0x06, 0x0a, 0x16, 0xd2, 0xfc, 0x40, 0x02, 0x0c, 0xfe, 0x12, 0xff, 0xff]
+        );
+
+        assert_eq!(
+            advertising_data(&Measurement::default()).as_slice(),
+            &[0x02, 0x01, 0x06, 0x04, 0x16, 0xd2, 0xfc, 0x40]
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Guided forced recalibration of the CO2 sensor
+//!
+//! The user picks the CO2 concentration that the device is in, e.g. outdoor
+//! air, and leaves it there. Once the readings have settled, the sensor gets
+//! told the reference value and the correction is reported.
+
+use micromath::F32Ext;
+
+use crate::logic::stats::Window;
+
+/// The reference values to pick from with the button, ppm. Outdoor air comes
+/// first.
+pub const REFERENCES: [u16; 7] = [420, 400, 450, 500, 600, 800, 1000];
+
+/// Readings are considered settled when they've stayed within these limits
+/// over the whole window
+const MAX_STD_DEV: f32 = 10.;
+const MAX_RANGE: f32 = 30.;
+/// Five minutes in 30 second buckets
+const WINDOW_BUCKETS: usize = 10;
+const BUCKET_SECONDS: u32 = 30;
+/// Give up if the readings haven't settled after this many seconds
+const TIMEOUT: u32 = 30 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub enum State {
+    /// Picking one of the [`REFERENCES`]
+    Reference(usize),
+    /// Waiting for the readings to settle since the given time
+    Settling { reference: u16, since: Option<u32> },
+    /// The sensor was recalibrated, correcting it by `correction` ppm
+    Done { reference: u16, correction: f32 },
+    /// The readings didn't settle in time
+    Failed,
+}
+
+pub struct Calibration {
+    state: State,
+    window: Window<WINDOW_BUCKETS>,
+}
+
+impl Calibration {
+    pub const fn new() -> Self {
+        Calibration {
+            state: State::Reference(0),
+            window: Window::new(BUCKET_SECONDS),
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Step to the next reference value, while picking one
+    pub fn next_reference(&mut self) {
+        if let State::Reference(i) = self.state {
+            self.state = State::Reference((i + 1) % REFERENCES.len());
+        }
+    }
+
+    /// Go with the picked reference value and start waiting
+    pub fn confirm(&mut self) {
+        if let State::Reference(i) = self.state {
+            self.window.reset();
+            self.state = State::Settling {
+                reference: REFERENCES[i],
+                since: None,
+            };
+        }
+    }
+
+    /// Feed in a CO2 reading taken at `now` seconds since boot. Returns the
+    /// reference value once it's time to recalibrate the sensor.
+    pub fn update(&mut self, now: u32, co2: f32) -> Option<u16> {
+        let State::Settling { reference, since } = self.state else {
+            return None;
+        };
+        let since = since.unwrap_or(now);
+        self.state = State::Settling {
+            reference,
+            since: Some(since),
+        };
+        self.window.add(now, co2);
+
+        // Wrapping, because the seconds counter is allowed to overflow
+        let elapsed = now.wrapping_sub(since);
+        let stats = self.window.stats();
+        let settled = elapsed >= WINDOW_BUCKETS as u32 * BUCKET_SECONDS
+            && stats
+                .std_dev()
+                .map_or(false, |std_dev| std_dev <= MAX_STD_DEV)
+            && stats
+                .min()
+                .zip(stats.max())
+                .map_or(false, |(min, max)| max - min <= MAX_RANGE);
+        if settled {
+            let mean = stats.mean().unwrap_or(co2);
+            self.state = State::Done {
+                reference,
+                correction: reference as f32 - mean,
+            };
+            Some(reference)
+        } else {
+            if elapsed >= TIMEOUT {
+                self.state = State::Failed;
+            }
+            None
+        }
+    }
+
+    /// What to show on a 16x2 display
+    pub fn lines(&self) -> [heapless::String<16>; 2] {
+        let mut lines = [heapless::String::new(), heapless::String::new()];
+        match self.state {
+            State::Reference(i) => {
+                ufmt::uwrite!(lines[0], "CO2 ref {}ppm", REFERENCES[i]).ok();
+                lines[1].push_str("Press:+  Hold:OK").ok();
+            }
+            State::Settling { .. } => {
+                match self.window.stats().std_dev() {
+                    Some(std_dev) => {
+                        let std_dev = std_dev.round() as u32;
+                        ufmt::uwrite!(lines[0], "Settling, sd {}", std_dev).ok();
+                    }
+                    None => {
+                        lines[0].push_str("Settling").ok();
+                    }
+                }
+                lines[1].push_str("Press to cancel").ok();
+            }
+            State::Done { correction, .. } => {
+                lines[0].push_str("Calibrated").ok();
+                let correction = correction.round() as i32;
+                ufmt::uwrite!(lines[1], "Corr. {}ppm", correction).ok();
+            }
+            State::Failed => {
+                lines[0].push_str("Not stable").ok();
+                lines[1].push_str("Cancelled").ok();
+            }
+        }
+        lines
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Calibration, State, REFERENCES};
+    use micromath::F32Ext;
+
+    pub fn pick_reference() {
+        let mut calibration = Calibration::new();
+        assert_eq!(calibration.state(), State::Reference(0));
+        assert_eq!(calibration.lines()[0].as_str(), "CO2 ref 420ppm");
+        // Readings don't count until the reference has been picked
+        assert_eq!(calibration.update(0, 420.), None);
+        for _ in 0..REFERENCES.len() + 1 {
+            calibration.next_reference();
+        }
+        assert_eq!(calibration.state(), State::Reference(1));
+        calibration.confirm();
+        assert_eq!(
+            calibration.state(),
+            State::Settling {
+                reference: 400,
+                since: None
+            }
+        );
+    }
+
+    pub fn settles() {
+        let mut calibration = Calibration::new();
+        calibration.confirm();
+        // Still coming down after being taken outside
+        for i in 0..10 {
+            assert_eq!(calibration.update(i * 10, 800. - i as f32 * 30.), None);
+        }
+        // Then noisy but stable around 450
+        let mut result = None;
+        for i in 10..100 {
+            let noise = if i % 2 == 0 { 5. } else { -5. };
+            result = calibration.update(i * 10, 450. + noise);
+            if result.is_some() {
+                break;
+            }
+        }
+        assert_eq!(result, Some(420));
+        match calibration.state() {
+            State::Done { correction, .. } => {
+                assert!((correction + 30.).abs() < 1., "{}", correction)
+            }
+            state => panic!("{:?}", state),
+        }
+        assert_eq!(calibration.lines()[1].as_str(), "Corr. -30ppm");
+    }
+
+    pub fn times_out() {
+        let mut calibration = Calibration::new();
+        calibration.confirm();
+        for i in 0..=180 {
+            let swing = if i % 2 == 0 { 100. } else { -100. };
+            assert_eq!(calibration.update(i * 10, 600. + swing), None);
+        }
+        assert_eq!(calibration.state(), State::Failed);
+        assert_eq!(calibration.update(1810, 600.), None);
+    }
+}
@@ -0,0 +1,293 @@
+//! Values of the GATT characteristics that the device serves over Bluetooth
+//!
+//! Temperature, humidity and pressure are the standard characteristics of
+//! the Environmental Sensing Service, so that generic apps understand them.
+//! CO2, the VOC index and particulate matter have no standard characteristics
+//! in units that fit, so they're custom ones in a service of their own, along
+//! with the configuration. The settings don't fit in one value, so the
+//! configuration is split over two characteristics.
+//!
+//! | Characteristic    | UUID     | Value                                            |
+//! |-------------------|----------|--------------------------------------------------|
+//! | Temperature       | `0x2a6e` | `i16`, 0.01 °C, `0x8000` if unknown              |
+//! | Humidity          | `0x2a6f` | `u16`, 0.01 %, `0xffff` if unknown               |
+//! | Pressure          | `0x2a6d` | `u32`, 0.1 Pa, `0xffffffff` if unknown           |
+//! | CO2               | custom   | `u16`, ppm, `0xffff` if unknown                  |
+//! | VOC index         | custom   | `u16`, `0xffff` if unknown                       |
+//! | Particulate mass  | custom   | 4 × `u16`, PM1.0, PM2.5, PM4.0 and PM10, 0.1 µg/m³, `0xffff` if unknown |
+//! | Configuration     | custom   | Bytes 0..18 of the [`Settings`] as [`Settings::encode`] lays them out |
+//! | Temperature configuration | custom | The rest of them, the self-heating of the sensors |
+//!
+//! with everything little endian.
+
+use micromath::F32Ext;
+
+use crate::logic::{measurement::Measurement, settings::Settings};
+
+/// Most that a notification or a read with the default ATT MTU can carry
+pub type Value = heapless::Vec<u8, 20>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Uuid {
+    /// A Bluetooth SIG assigned number
+    Short(u16),
+    Long(u128),
+}
+
+impl Uuid {
+    /// Little endian, the way it goes over the air
+    pub fn bytes(&self) -> heapless::Vec<u8, 16> {
+        match self {
+            Uuid::Short(uuid) => heapless::Vec::from_slice(&uuid.to_le_bytes()).unwrap(),
+            Uuid::Long(uuid) => heapless::Vec::from_slice(&uuid.to_le_bytes()).unwrap(),
+        }
+    }
+}
+
+/// 6f1cxxxx-9f6b-4d4b-9e3a-2b1d5c7e8a90, with xxxx telling the custom
+/// service and characteristics apart
+const fn custom(id: u16) -> Uuid {
+    Uuid::Long(0x6f1c_0000_9f6b_4d4b_9e3a_2b1d_5c7e_8a90 | (id as u128) << 96)
+}
+
+pub const ENVIRONMENTAL_SENSING: Uuid = Uuid::Short(0x181a);
+/// The service with the custom characteristics
+pub const AIRLOG: Uuid = custom(0x0001);
+
+/// The part of the settings that a configuration characteristic holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ConfigPart {
+    General,
+    Temperature,
+}
+
+impl ConfigPart {
+    pub fn uuid(&self) -> Uuid {
+        match self {
+            ConfigPart::General => custom(0x0005),
+            ConfigPart::Temperature => custom(0x0006),
+        }
+    }
+
+    /// Where the part is in what [`Settings::encode`] gives
+    fn bytes(&self) -> core::ops::Range<usize> {
+        match self {
+            ConfigPart::General => 0..18,
+            ConfigPart::Temperature => 18..Settings::PAYLOAD_SIZE,
+        }
+    }
+}
+
+/// The characteristics that carry a measured value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Characteristic {
+    Temperature,
+    Humidity,
+    Pressure,
+    Co2,
+    VocIndex,
+    ParticulateMatter,
+}
+
+impl Characteristic {
+    pub const ALL: [Characteristic; 6] = [
+        Characteristic::Temperature,
+        Characteristic::Humidity,
+        Characteristic::Pressure,
+        Characteristic::Co2,
+        Characteristic::VocIndex,
+        Characteristic::ParticulateMatter,
+    ];
+
+    pub fn uuid(&self) -> Uuid {
+        match self {
+            Characteristic::Temperature => Uuid::Short(0x2a6e),
+            Characteristic::Humidity => Uuid::Short(0x2a6f),
+            Characteristic::Pressure => Uuid::Short(0x2a6d),
+            Characteristic::Co2 => custom(0x0002),
+            Characteristic::VocIndex => custom(0x0003),
+            Characteristic::ParticulateMatter => custom(0x0004),
+        }
+    }
+
+    /// The value for `measurement`, see the [module](self) for the formats
+    pub fn value(&self, measurement: &Measurement) -> Value {
+        let mut value = Value::new();
+        match self {
+            Characteristic::Temperature => {
+                let temperature = measurement.ambient_temperature.or(measurement.temperature);
+                let temperature = temperature.map_or(i16::MIN, |temperature| {
+                    // Leave the unknown value out of the range
+                    ((temperature * 100.).round() as i16).max(i16::MIN + 1)
+                });
+                value.extend_from_slice(&temperature.to_le_bytes())
+            }
+            Characteristic::Humidity => {
+                value.extend_from_slice(&unsigned16(measurement.rel_humidity, 100.).to_le_bytes())
+            }
+            Characteristic::Pressure => {
+                let pressure = measurement.pressure.map_or(u32::MAX, |pressure| {
+                    ((pressure * 10.).round() as u32).min(u32::MAX - 1)
+                });
+                value.extend_from_slice(&pressure.to_le_bytes())
+            }
+            Characteristic::Co2 => {
+                value.extend_from_slice(&unsigned16(measurement.co2, 1.).to_le_bytes())
+            }
+            Characteristic::VocIndex => {
+                value.extend_from_slice(&unsigned16(measurement.voc_index, 1.).to_le_bytes())
+            }
+            Characteristic::ParticulateMatter => [
+                measurement.pm1_0,
+                measurement.pm2_5,
+                measurement.pm4_0,
+                measurement.pm10,
+            ]
+            .into_iter()
+            .try_for_each(|pm| value.extend_from_slice(&unsigned16(pm, 10.).to_le_bytes())),
+        }
+        .unwrap();
+        value
+    }
+}
+
+/// `value` in steps of `1 / scale`, with `u16::MAX` if it's unknown
+fn unsigned16(value: Option<f32>, scale: f32) -> u16 {
+    value.map_or(u16::MAX, |value| {
+        ((value * scale).round() as u16).min(u16::MAX - 1)
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ConfigError {
+    /// Not as long as the part of the settings
+    InvalidLength,
+    /// A value that [`Settings::sanitized`] would replace
+    InvalidValue,
+}
+
+/// The value of the configuration characteristic for `part`
+pub fn config(settings: &Settings, part: ConfigPart) -> Value {
+    let mut payload = [0; Settings::PAYLOAD_SIZE];
+    settings.encode(&mut payload);
+    Value::from_slice(&payload[part.bytes()]).unwrap()
+}
+
+/// `settings` with the `part` that was written to its configuration
+/// characteristic. All of the part has to be there, and it has to make
+/// sense, so that a client can't change a setting it doesn't know about by
+/// accident.
+pub fn parse_config(
+    settings: &Settings,
+    part: ConfigPart,
+    value: &[u8],
+) -> Result<Settings, ConfigError> {
+    if value.len() != part.bytes().len() {
+        return Err(ConfigError::InvalidLength);
+    }
+    let mut payload = [0; Settings::PAYLOAD_SIZE];
+    settings.encode(&mut payload);
+    payload[part.bytes()].copy_from_slice(value);
+    let settings = Settings::decode(&payload);
+    // Decoding replaces the values that don't make sense
+    if config(&settings, part) != value {
+        return Err(ConfigError::InvalidValue);
+    }
+    Ok(settings)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{config, parse_config, Characteristic, ConfigError, ConfigPart, Uuid, AIRLOG};
+    use crate::logic::{measurement::Measurement, settings::Settings};
+
+    pub fn values() {
+        let measurement = Measurement {
+            co2: Some(612.4),
+            temperature: Some(23.1),
+            ambient_temperature: Some(-5.126),
+            rel_humidity: Some(45.67),
+            pressure: Some(101_325.4),
+            pm1_0: Some(1.23),
+            pm2_5: Some(3.1),
+            pm10: Some(700.),
+            voc_index: Some(100.),
+            ..Default::default()
+        };
+        let value = |characteristic: Characteristic| characteristic.value(&measurement);
+        assert_eq!(&value(Characteristic::Temperature), &[0xff, 0xfd]);
+        assert_eq!(&value(Characteristic::Humidity), &[0xd7, 0x11]);
+        assert_eq!(&value(Characteristic::Pressure), &[0x06, 0x76, 0x0f, 0x00]);
+        assert_eq!(&value(Characteristic::Co2), &[0x64, 0x02]);
+        assert_eq!(&value(Characteristic::VocIndex), &[0x64, 0x00]);
+        assert_eq!(
+            &value(Characteristic::ParticulateMatter),
+            &[0x0c, 0x00, 0x1f, 0x00, 0xff, 0xff, 0x58, 0x1b]
+        );
+
+        let unknown = Measurement::default();
+        for (characteristic, expected) in [
+            (Characteristic::Temperature, &[0x00, 0x80][..]),
+            (Characteristic::Humidity, &[0xff, 0xff]),
+            (Characteristic::Pressure, &[0xff, 0xff, 0xff, 0xff]),
+            (Characteristic::Co2, &[0xff, 0xff]),
+            (Characteristic::VocIndex, &[0xff, 0xff]),
+            (Characteristic::ParticulateMatter, &[0xff; 8]),
+        ] {
+            assert_eq!(&characteristic.value(&unknown), expected);
+        }
+    }
+
+    pub fn uuids() {
+        assert_eq!(&Characteristic::Humidity.uuid().bytes(), &[0x6f, 0x2a]);
+        assert_eq!(
+            &AIRLOG.bytes(),
+            &[
+                0x90, 0x8a, 0x7e, 0x5c, 0x1d, 0x2b, 0x3a, 0x9e, 0x4b, 0x4d, 0x6b, 0x9f, 0x01, 0x00,
+                0x1c, 0x6f
+            ]
+        );
+        assert_eq!(
+            Characteristic::Co2.uuid(),
+            Uuid::Long(0x6f1c0002_9f6b_4d4b_9e3a_2b1d5c7e8a90)
+        );
+    }
+
+    pub fn configs() {
+        let settings = Settings {
+            altitude: 120,
+            co2_alarm: (1200, 2500),
+            ..Settings::DEFAULT
+        };
+        let value = config(&settings, ConfigPart::General);
+        assert_eq!(value.len(), 18);
+        assert_eq!(
+            parse_config(&Settings::DEFAULT, ConfigPart::General, &value),
+            Ok(settings)
+        );
+        assert_eq!(
+            parse_config(&settings, ConfigPart::General, &value[..10]),
+            Err(ConfigError::InvalidLength)
+        );
+        let mut invalid = value.clone();
+        // A warning above the critical level
+        invalid[14..16].copy_from_slice(&3000_u16.to_le_bytes());
+        assert_eq!(
+            parse_config(&settings, ConfigPart::General, &invalid),
+            Err(ConfigError::InvalidValue)
+        );
+
+        // The other part leaves these settings alone
+        let changed = Settings {
+            temperature_gain: 0.25,
+            temperature_weights: (2, 1),
+            ..settings
+        };
+        let value = config(&changed, ConfigPart::Temperature);
+        assert_eq!(value.len(), 14);
+        assert_eq!(
+            parse_config(&settings, ConfigPart::Temperature, &value),
+            Ok(changed)
+        );
+    }
+}
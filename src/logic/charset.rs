@@ -0,0 +1,262 @@
+//! Translation from UTF-8 text to HD44780 character codes
+
+// The HD44780 has room for eight user defined 5x8 characters in its CGRAM.
+// Character codes 0 to 7 refer to them. There's only room for a single set of
+// glyphs at a time, see `GlyphSet`.
+
+pub const DEGREE_SLOT: u8 = 0;
+pub const MICRO_SLOT: u8 = 1;
+pub const SUPERSCRIPT_THREE_SLOT: u8 = 2;
+pub const ARROW_UP_SLOT: u8 = 3;
+pub const ARROW_DOWN_SLOT: u8 = 4;
+pub const ARROW_FLAT_SLOT: u8 = 5;
+
+// With the bar glyphs loaded, bars of height 1/8 to 7/8 take up the first
+// seven slots (the ROM has a full block) and the degree sign the last one
+pub const BARS_DEGREE_SLOT: u8 = 7;
+
+/// Block elements for bars of height 1/8 to 8/8
+pub const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// A 5x8 character bitmap, one byte per row (top to bottom), only the lower 5
+/// bits of each row are used
+pub type Glyph = [u8; 8];
+
+#[rustfmt::skip]
+const DEGREE: Glyph = [
+    0b01100,
+    0b10010,
+    0b10010,
+    0b01100,
+    0b00000,
+    0b00000,
+    0b00000,
+    0b00000,
+];
+
+#[rustfmt::skip]
+const MICRO: Glyph = [
+    0b00000,
+    0b00000,
+    0b10010,
+    0b10010,
+    0b10010,
+    0b11101,
+    0b10000,
+    0b10000,
+];
+
+#[rustfmt::skip]
+const SUPERSCRIPT_THREE: Glyph = [
+    0b11100,
+    0b00010,
+    0b01100,
+    0b00010,
+    0b11100,
+    0b00000,
+    0b00000,
+    0b00000,
+];
+
+#[rustfmt::skip]
+const ARROW_UP: Glyph = [
+    0b00100,
+    0b01110,
+    0b10101,
+    0b00100,
+    0b00100,
+    0b00100,
+    0b00100,
+    0b00000,
+];
+
+#[rustfmt::skip]
+const ARROW_DOWN: Glyph = [
+    0b00100,
+    0b00100,
+    0b00100,
+    0b00100,
+    0b10101,
+    0b01110,
+    0b00100,
+    0b00000,
+];
+
+#[rustfmt::skip]
+const ARROW_FLAT: Glyph = [
+    0b00000,
+    0b00100,
+    0b00010,
+    0b11111,
+    0b00010,
+    0b00100,
+    0b00000,
+    0b00000,
+];
+
+/// Glyphs that should be uploaded to the CGRAM at LCD init. The position in
+/// the array is the slot (and hence the character code) of the glyph.
+pub const SYMBOL_GLYPHS: [Glyph; 6] = [
+    DEGREE,
+    MICRO,
+    SUPERSCRIPT_THREE,
+    ARROW_UP,
+    ARROW_DOWN,
+    ARROW_FLAT,
+];
+
+/// Glyphs for drawing bar graphs
+pub const BAR_GLYPHS: [Glyph; 8] = [
+    bar(1),
+    bar(2),
+    bar(3),
+    bar(4),
+    bar(5),
+    bar(6),
+    bar(7),
+    DEGREE,
+];
+
+/// A glyph with the bottom `height` rows lit
+const fn bar(height: usize) -> Glyph {
+    let mut glyph = [0; 8];
+    let mut row = 8 - height;
+    while row < 8 {
+        glyph[row] = 0b11111;
+        row += 1;
+    }
+    glyph
+}
+
+/// The set of custom glyphs that's loaded in the CGRAM
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum GlyphSet {
+    /// Degree sign, micro, superscript 3 and trend arrows
+    Symbols,
+    /// Bars for sparklines and the degree sign
+    Bars,
+}
+
+impl GlyphSet {
+    pub fn glyphs(&self) -> &'static [Glyph] {
+        match self {
+            GlyphSet::Symbols => &SYMBOL_GLYPHS,
+            GlyphSet::Bars => &BAR_GLYPHS,
+        }
+    }
+}
+
+/// Character code that gets shown for characters that we don't know how to
+/// display
+pub const UNKNOWN: u8 = b'?';
+
+/// Map a single character to the character code of the HD44780 (A00, i.e.
+/// Japanese, ROM variant) when `glyphs` are loaded in the CGRAM
+pub fn encode_char(c: char, glyphs: GlyphSet) -> u8 {
+    match (c, glyphs) {
+        // These two are replaced with '¥' and '→' in the ROM
+        ('\\' | '~', _) => UNKNOWN,
+        (' '..='}', _) => c as u8,
+        ('°', GlyphSet::Symbols) => DEGREE_SLOT,
+        ('°', GlyphSet::Bars) => BARS_DEGREE_SLOT,
+        ('µ' | 'μ', GlyphSet::Symbols) => MICRO_SLOT,
+        ('³', GlyphSet::Symbols) => SUPERSCRIPT_THREE_SLOT,
+        ('↑', GlyphSet::Symbols) => ARROW_UP_SLOT,
+        ('↓', GlyphSet::Symbols) => ARROW_DOWN_SLOT,
+        ('→', GlyphSet::Symbols) => ARROW_FLAT_SLOT,
+        ('▁'..='▇', GlyphSet::Bars) => (c as u32 - '▁' as u32) as u8,
+        // Without the right glyphs we fall back to lookalikes
+        ('µ' | 'μ', GlyphSet::Bars) => 0xe4,
+        ('³', GlyphSet::Bars) => b'3',
+        ('↑', GlyphSet::Bars) => b'^',
+        ('↓', GlyphSet::Bars) => b'v',
+        ('→', GlyphSet::Bars) => 0x7e,
+        ('▁'..='▃', GlyphSet::Symbols) => b'_',
+        ('▄'..='▇', GlyphSet::Symbols) => 0xff,
+        // The rest are built into the ROM
+        ('█', _) => 0xff,
+        ('¥', _) => 0x5c,
+        ('←', _) => 0x7f,
+        ('·', _) => 0xa5,
+        ('α', _) => 0xe0,
+        ('ä', _) => 0xe1,
+        ('β', _) => 0xe2,
+        ('ε', _) => 0xe3,
+        ('σ', _) => 0xe5,
+        ('ρ', _) => 0xe6,
+        ('√', _) => 0xe8,
+        ('¢', _) => 0xec,
+        ('ñ', _) => 0xee,
+        ('ö', _) => 0xef,
+        ('θ', _) => 0xf2,
+        ('∞', _) => 0xf3,
+        ('Ω', _) => 0xf4,
+        ('ü', _) => 0xf5,
+        ('Σ', _) => 0xf6,
+        ('π', _) => 0xf7,
+        ('÷', _) => 0xfd,
+        _ => UNKNOWN,
+    }
+}
+
+/// Map UTF-8 text to HD44780 character codes, one code per `char`
+pub fn encode(text: &str, glyphs: GlyphSet) -> impl Iterator<Item = u8> + '_ {
+    text.chars().map(move |c| encode_char(c, glyphs))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{
+        encode, encode_char, GlyphSet, ARROW_UP_SLOT, BARS_DEGREE_SLOT, DEGREE_SLOT, MICRO_SLOT,
+        SUPERSCRIPT_THREE_SLOT, UNKNOWN,
+    };
+    use crate::logic::formatting::format_float_measurement;
+
+    pub fn encode_ascii() {
+        let codes: heapless::Vec<u8, 16> = encode("CO2 412 ppm", GlyphSet::Symbols).collect();
+        assert_eq!(codes.as_slice(), b"CO2 412 ppm");
+    }
+
+    pub fn encode_temperature() {
+        let text = format_float_measurement(21.5, 2, 1, "°C");
+        let codes: heapless::Vec<u8, 16> = encode(&text, GlyphSet::Symbols).collect();
+        assert_eq!(
+            codes.as_slice(),
+            &[b'2', b'1', b'.', b'5', b' ', DEGREE_SLOT, b'C']
+        );
+    }
+
+    pub fn encode_mass_concentration() {
+        let codes: heapless::Vec<u8, 16> = encode("12 µg/m³", GlyphSet::Symbols).collect();
+        assert_eq!(
+            codes.as_slice(),
+            &[
+                b'1',
+                b'2',
+                b' ',
+                MICRO_SLOT,
+                b'g',
+                b'/',
+                b'm',
+                SUPERSCRIPT_THREE_SLOT
+            ]
+        );
+    }
+
+    pub fn encode_rom_and_unknown() {
+        assert_eq!(encode_char('↑', GlyphSet::Symbols), ARROW_UP_SLOT);
+        assert_eq!(encode_char('Ω', GlyphSet::Symbols), 0xf4);
+        assert_eq!(encode_char('\\', GlyphSet::Symbols), UNKNOWN);
+        assert_eq!(encode_char('€', GlyphSet::Symbols), UNKNOWN);
+    }
+
+    pub fn encode_bars() {
+        let codes: heapless::Vec<u8, 16> = encode("▁▄█ 5°C", GlyphSet::Bars).collect();
+        assert_eq!(
+            codes.as_slice(),
+            &[0, 3, 0xff, b' ', b'5', BARS_DEGREE_SLOT, b'C']
+        );
+        assert_eq!(encode_char('µ', GlyphSet::Bars), 0xe4);
+        assert_eq!(encode_char('▇', GlyphSet::Symbols), 0xff);
+    }
+}
@@ -0,0 +1,366 @@
+//! Compact binary encoding of timestamped measurements, for sending them over
+//! links where every byte counts
+//!
+//! A frame is laid out as
+//!
+//! | Contents                                                      |
+//! |---------------------------------------------------------------|
+//! | Schema version, [`VERSION`]                                   |
+//! | Kind, [`KEY`] or [`DELTA`]                                    |
+//! | Timestamp                                                     |
+//! | Bit `i` set if the `i`th quantity of [`Quantity::ALL`] is there |
+//! | The quantities that are there, in that order                  |
+//!
+//! with everything after the first two bytes as LEB128 varints. Each quantity
+//! is a signed number of steps of its [`resolution`], zigzag encoded so that
+//! small negative numbers stay short. In a key frame the timestamp and the
+//! values are as they are, in a delta frame they're the difference to the
+//! frame before, which takes a byte for most values that changed a little.
+//! A quantity that wasn't in the frame before counts from zero.
+//!
+//! Quantities that get added later go to the end of [`Quantity::ALL`], and
+//! newer versions may only add to the end of a frame. A decoder skips the
+//! quantities and anything after them that it doesn't know, so old decoders
+//! read new frames, and new ones read old frames with the new quantities
+//! missing.
+
+use micromath::F32Ext;
+
+use crate::logic::{
+    logger::resolution,
+    measurement::{Measurement, Quantity},
+};
+
+pub const VERSION: u8 = 1;
+/// Frame kinds
+pub const KEY: u8 = 0;
+pub const DELTA: u8 = 1;
+
+/// How many quantities fit in the bitmap
+const MAX_QUANTITIES: usize = 32;
+/// Longest varint of a `u32`
+const MAX_VARINT: usize = 5;
+/// With all the quantities there, and far off the frame before
+pub const MAX_FRAME_SIZE: usize = 2 + MAX_VARINT * (2 + Quantity::ALL.len());
+
+pub type Frame = heapless::Vec<u8, MAX_FRAME_SIZE>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error {
+    /// The frame ends in the middle of something
+    Truncated,
+    /// Not a version that can be read, or not a frame at all
+    UnsupportedVersion(u8),
+    UnknownKind(u8),
+    /// A varint that doesn't fit in 32 bits
+    Overflow,
+    /// A delta frame without a frame before it to apply it to
+    NoReference,
+}
+
+/// The timestamp and the values as they get encoded, in steps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Steps {
+    timestamp: u32,
+    values: [Option<i32>; Quantity::ALL.len()],
+}
+
+impl Steps {
+    fn new(timestamp: u32, measurement: &Measurement) -> Self {
+        Steps {
+            timestamp,
+            values: Quantity::ALL.map(|quantity| {
+                // The float to int cast saturates
+                measurement
+                    .get(quantity)
+                    .map(|value| (value / resolution(quantity)).round() as i32)
+            }),
+        }
+    }
+
+    fn measurement(&self) -> Measurement {
+        let mut measurement = Measurement::default();
+        for (quantity, value) in Quantity::ALL.into_iter().zip(self.values) {
+            measurement.set(
+                quantity,
+                value.map(|value| value as f32 * resolution(quantity)),
+            );
+        }
+        measurement
+    }
+}
+
+/// Encodes a stream of frames, with a key frame every so often so that a
+/// decoder can pick up the stream in the middle or after a lost frame
+pub struct Encoder {
+    previous: Option<Steps>,
+    key_interval: u32,
+    /// Frames since the last key frame
+    count: u32,
+}
+
+impl Encoder {
+    /// Sends a key frame first and then every `key_interval` frames, so with
+    /// 1 only key frames
+    pub fn new(key_interval: u32) -> Self {
+        Encoder {
+            previous: None,
+            key_interval: key_interval.max(1),
+            count: 0,
+        }
+    }
+
+    pub fn encode(&mut self, timestamp: u32, measurement: &Measurement) -> Frame {
+        let steps = Steps::new(timestamp, measurement);
+        let reference = match self.previous {
+            Some(previous) if self.count < self.key_interval => Some(previous),
+            _ => {
+                self.count = 0;
+                None
+            }
+        };
+        self.count += 1;
+        self.previous = Some(steps);
+        encode_steps(&steps, reference.as_ref())
+    }
+}
+
+/// A key frame on its own
+pub fn encode(timestamp: u32, measurement: &Measurement) -> Frame {
+    encode_steps(&Steps::new(timestamp, measurement), None)
+}
+
+fn encode_steps(steps: &Steps, reference: Option<&Steps>) -> Frame {
+    let mut frame = Frame::new();
+    frame.push(VERSION).unwrap();
+    frame
+        .push(if reference.is_some() { DELTA } else { KEY })
+        .unwrap();
+    let timestamp = match reference {
+        Some(reference) => zigzag(steps.timestamp.wrapping_sub(reference.timestamp) as i32),
+        None => steps.timestamp,
+    };
+    push_varint(&mut frame, timestamp);
+    let present = steps
+        .values
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| value.is_some())
+        .fold(0, |present, (i, _)| present | 1 << i);
+    push_varint(&mut frame, present);
+    for (i, value) in steps.values.iter().enumerate() {
+        if let Some(value) = value {
+            let base = reference.and_then(|reference| reference.values[i]);
+            push_varint(&mut frame, zigzag(value.wrapping_sub(base.unwrap_or(0))));
+        }
+    }
+    frame
+}
+
+/// Decodes the frames of a stream, which needs the frame before for a delta
+/// frame
+#[derive(Default)]
+pub struct Decoder {
+    previous: Option<Steps>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The timestamp and the measurement in `frame`. A frame that can't be
+    /// read leaves the decoder waiting for the next key frame.
+    pub fn decode(&mut self, frame: &[u8]) -> Result<(u32, Measurement), Error> {
+        let steps = decode_steps(frame, self.previous.as_ref());
+        self.previous = steps.ok();
+        steps.map(|steps| (steps.timestamp, steps.measurement()))
+    }
+}
+
+/// A key frame on its own
+pub fn decode(frame: &[u8]) -> Result<(u32, Measurement), Error> {
+    Decoder::new().decode(frame)
+}
+
+fn decode_steps(frame: &[u8], previous: Option<&Steps>) -> Result<Steps, Error> {
+    let (&version, rest) = frame.split_first().ok_or(Error::Truncated)?;
+    if version == 0 {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let (&kind, mut rest) = rest.split_first().ok_or(Error::Truncated)?;
+    let reference = match kind {
+        KEY => None,
+        DELTA => Some(previous.ok_or(Error::NoReference)?),
+        _ => return Err(Error::UnknownKind(kind)),
+    };
+    let timestamp = read_varint(&mut rest)?;
+    let timestamp = match reference {
+        Some(reference) => reference.timestamp.wrapping_add(unzigzag(timestamp) as u32),
+        None => timestamp,
+    };
+    let present = read_varint(&mut rest)?;
+    let mut steps = Steps {
+        timestamp,
+        values: [None; Quantity::ALL.len()],
+    };
+    for i in (0..MAX_QUANTITIES).filter(|i| present & 1 << i != 0) {
+        let value = unzigzag(read_varint(&mut rest)?);
+        // From a newer version
+        if i >= Quantity::ALL.len() {
+            continue;
+        }
+        let base = reference.and_then(|reference| reference.values[i]);
+        steps.values[i] = Some(value.wrapping_add(base.unwrap_or(0)));
+    }
+    Ok(steps)
+}
+
+fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+fn unzigzag(value: u32) -> i32 {
+    (value >> 1) as i32 ^ -((value & 1) as i32)
+}
+
+fn push_varint(frame: &mut Frame, mut value: u32) {
+    while value >= 0x80 {
+        frame.push(value as u8 | 0x80).unwrap();
+        value >>= 7;
+    }
+    frame.push(value as u8).unwrap();
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u32, Error> {
+    let mut value: u32 = 0;
+    for shift in (0..).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or(Error::Truncated)?;
+        *bytes = rest;
+        if shift == 28 && byte > 0x0f {
+            return Err(Error::Overflow);
+        }
+        value |= u32::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use micromath::F32Ext;
+
+    use super::{decode, encode, Decoder, Encoder, Error, Frame, MAX_FRAME_SIZE};
+    use crate::logic::measurement::{Measurement, Quantity};
+
+    fn measurement() -> Measurement {
+        Measurement {
+            co2: Some(612.),
+            temperature: Some(21.37),
+            rel_humidity: Some(45.5),
+            pressure: Some(101_320.),
+            pm2_5: Some(3.1),
+            ambient_temperature: Some(-4.25),
+            ..Default::default()
+        }
+    }
+
+    pub fn round_trips() {
+        let frame = encode(1_700_000_000, &measurement());
+        assert_eq!(
+            frame.as_slice(),
+            &[
+                0x01, 0x00, // version, key frame
+                0x80, 0xe2, 0xcf, 0xaa, 0x06, // timestamp
+                0x97, 0x82, 0x04, // co2, temperature, rel_humidity, pressure, pm2_5, ambient
+                0xc8, 0x09, // 612 ppm
+                0xb2, 0x21, // 2137 × 0.01 °C
+                0x8c, 0x47, // 4550 × 0.01 %
+                0xa8, 0x9e, 0x01, // 10132 × 10 Pa
+                0x3e, // 31 × 0.1 µg/m³
+                0xd1, 0x06, // -425 × 0.01 °C
+            ]
+        );
+        let (timestamp, decoded) = decode(&frame).unwrap();
+        assert_eq!(timestamp, 1_700_000_000);
+        for quantity in Quantity::ALL {
+            match (measurement().get(quantity), decoded.get(quantity)) {
+                (Some(expected), Some(value)) => assert!((value - expected).abs() < 0.001),
+                (expected, value) => assert_eq!(expected, value),
+            }
+        }
+
+        assert_eq!(
+            decode(&encode(0, &Measurement::default())),
+            Ok((0, Measurement::default()))
+        );
+        // As far off as it gets
+        let extreme = Measurement {
+            co2: Some(f32::MAX),
+            temperature: Some(f32::MIN),
+            ..Default::default()
+        };
+        let frame = encode(u32::MAX, &extreme);
+        assert!(frame.len() <= MAX_FRAME_SIZE);
+        let (timestamp, decoded) = decode(&frame).unwrap();
+        assert_eq!(timestamp, u32::MAX);
+        assert_eq!(decoded.co2, Some(i32::MAX as f32));
+    }
+
+    pub fn deltas() {
+        let mut encoder = Encoder::new(3);
+        let mut decoder = Decoder::new();
+        let mut next = measurement();
+        let mut frames: [Frame; 4] = Default::default();
+        for (i, frame) in frames.iter_mut().enumerate() {
+            next.co2 = next.co2.map(|co2| co2 + 3.);
+            next.temperature = next.temperature.map(|temperature| temperature - 0.02);
+            // Gone in the second frame, back in the third
+            next.pm2_5 = (i != 1).then_some(3.1);
+            *frame = encoder.encode(1_700_000_000 + 5 * i as u32, &next);
+            let (timestamp, decoded) = decoder.decode(frame).unwrap();
+            assert_eq!(timestamp, 1_700_000_000 + 5 * i as u32);
+            assert_eq!(decoded.co2, next.co2);
+            assert_eq!(decoded.pm2_5.is_some(), i != 1);
+        }
+        // A key frame every third frame
+        let kinds: heapless::Vec<u8, 4> = frames.iter().map(|frame| frame[1]).collect();
+        assert_eq!(&kinds, &[0, 1, 1, 0]);
+        assert_eq!(
+            &frames[1][..],
+            &[0x01, 0x01, 0x0a, 0x97, 0x80, 0x04, 0x06, 0x03, 0x00, 0x00, 0x00]
+        );
+        // Counting from zero after it was gone
+        assert_eq!(frames[2][10], 0x3e);
+
+        assert_eq!(Decoder::new().decode(&frames[1]), Err(Error::NoReference));
+    }
+
+    pub fn compatibility() {
+        // Version 2 with a quantity that doesn't exist yet, and a field after
+        // the values
+        let frame = [
+            0x02, 0x00, 0x05, 0x81, 0x80, 0x80, 0x80, 0x01, 0x04, 0x7f, 0x2a,
+        ];
+        let (timestamp, measurement) = decode(&frame).unwrap();
+        assert_eq!(timestamp, 5);
+        assert_eq!(
+            measurement,
+            Measurement {
+                co2: Some(2.),
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(decode(&[]), Err(Error::Truncated));
+        assert_eq!(decode(&[0x00, 0x00]), Err(Error::UnsupportedVersion(0)));
+        assert_eq!(decode(&[0x01, 0x07, 0x00]), Err(Error::UnknownKind(7)));
+        assert_eq!(decode(&[0x01, 0x00, 0x05, 0x01]), Err(Error::Truncated));
+        assert_eq!(
+            decode(&[0x01, 0x00, 0xff, 0xff, 0xff, 0xff, 0x7f]),
+            Err(Error::Overflow)
+        );
+    }
+}
@@ -0,0 +1,610 @@
+//! Line based commands for controlling the device over a serial link
+//!
+//! A command is a line of words separated by spaces. The device answers with
+//! any number of lines followed by `ok`, or with a single `error: ...` line.
+//!
+//! | Command                    | Does                                                   |
+//! |----------------------------|--------------------------------------------------------|
+//! | `help`                     | Lists the commands                                     |
+//! | `version`                  | Firmware and sensor versions                           |
+//! | `get [<setting>]`          | One setting, or all of them, as `<setting> <value>`    |
+//! | `set <setting> <value>...` | Changes a setting and saves it                         |
+//! | `recalibrate <ppm>`        | Forced recalibration of the SCD30 to a reference level |
+//! | `clean`                    | Starts cleaning the SPS30's fan                        |
+//! | `time [<time>]`            | Shows or sets the clock, Unix time or ISO 8601 in UTC  |
+//! | `log [raw]`                | Dumps the measurement log as CSV, or as codec frames   |
+//! | `metrics [influx]`         | The latest measurement for Prometheus or InfluxDB      |
+//!
+//! `log raw` gives a line of hex digits for every [`codec`](crate::logic::codec)
+//! frame, which is what was logged without any loss, in a fraction of the
+//! space of CSV. The frames are a stream, so the ones between key frames need
+//! the frames before them.
+//!
+//! `metrics` gives the lines of a Prometheus scrape, see
+//! [`prometheus`](crate::logic::prometheus), or an InfluxDB line protocol point,
+//! see [`influx`](crate::logic::influx), which end in plain line breaks.
+
+use crate::logic::{
+    codec::{Frame, MAX_FRAME_SIZE},
+    formatting::format_decimal,
+    settings::Settings,
+    time::{self, DateTime},
+};
+
+/// Longest command line, longer ones get rejected
+pub const MAX_LINE: usize = 64;
+
+pub type Line = heapless::String<MAX_LINE>;
+
+/// Reply to a command that went through
+pub const OK: &str = "ok\r\n";
+
+pub const HELP: &[&str] = &[
+    "help\r\n",
+    "version\r\n",
+    "get [<setting>]\r\n",
+    "set <setting> <value>...\r\n",
+    "recalibrate <ppm>\r\n",
+    "clean\r\n",
+    "time [<unix time>|<YYYY-MM-DDTHH:MM:SSZ>]\r\n",
+    "log [raw]\r\n",
+    "metrics [influx]\r\n",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error {
+    UnknownCommand,
+    UnknownSetting,
+    MissingArgument,
+    TooManyArguments,
+    InvalidValue,
+    LineTooLong,
+    /// Not on this build, e.g. dumping a log that's on an SD card
+    Unavailable,
+    ClockNotSet,
+    /// The device couldn't carry the command out
+    Failed,
+}
+
+impl Error {
+    /// The whole reply, e.g. "error: unknown command"
+    pub fn reply(&self) -> &'static str {
+        match self {
+            Error::UnknownCommand => "error: unknown command\r\n",
+            Error::UnknownSetting => "error: unknown setting\r\n",
+            Error::MissingArgument => "error: missing argument\r\n",
+            Error::TooManyArguments => "error: too many arguments\r\n",
+            Error::InvalidValue => "error: invalid value\r\n",
+            Error::LineTooLong => "error: line too long\r\n",
+            Error::Unavailable => "error: not available\r\n",
+            Error::ClockNotSet => "error: the clock isn't set\r\n",
+            Error::Failed => "error: failed\r\n",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Setting {
+    TemperatureOffset,
+    Co2Baseline,
+    PressureRange,
+    CleaningProbability,
+    Page,
+    Altitude,
+    Co2Alarm,
+    TemperatureGain,
+    PressureSensorHeating,
+    TemperatureWeights,
+}
+
+impl Setting {
+    pub const ALL: [Setting; 10] = [
+        Setting::TemperatureOffset,
+        Setting::Co2Baseline,
+        Setting::PressureRange,
+        Setting::CleaningProbability,
+        Setting::Page,
+        Setting::Altitude,
+        Setting::Co2Alarm,
+        Setting::TemperatureGain,
+        Setting::PressureSensorHeating,
+        Setting::TemperatureWeights,
+    ];
+
+    pub fn key(&self) -> &'static str {
+        match self {
+            Setting::TemperatureOffset => "temperature_offset",
+            Setting::Co2Baseline => "co2_baseline",
+            Setting::PressureRange => "pressure_range",
+            Setting::CleaningProbability => "cleaning_probability",
+            Setting::Page => "page",
+            Setting::Altitude => "altitude",
+            Setting::Co2Alarm => "co2_alarm",
+            Setting::TemperatureGain => "temperature_gain",
+            Setting::PressureSensorHeating => "pressure_sensor_heating",
+            Setting::TemperatureWeights => "temperature_weights",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Setting> {
+        Setting::ALL
+            .into_iter()
+            .find(|setting| setting.key() == key)
+    }
+
+    /// The reply to `get`, e.g. "pressure_range 990 1040"
+    pub fn show(&self, settings: &Settings) -> Line {
+        let mut line = Line::new();
+        line.push_str(self.key()).unwrap();
+        match self {
+            Setting::TemperatureOffset => {
+                line.push(' ').unwrap();
+                line.push_str(&format_decimal(settings.temperature_offset, 2))
+                    .unwrap();
+            }
+            Setting::Co2Baseline => ufmt::uwrite!(line, " {}", settings.co2_baseline).unwrap(),
+            Setting::PressureRange => {
+                let (min, max) = settings.pressure_range;
+                ufmt::uwrite!(line, " {} {}", min, max).unwrap();
+            }
+            Setting::CleaningProbability => {
+                ufmt::uwrite!(line, " {}", settings.cleaning_probability).unwrap()
+            }
+            Setting::Page => ufmt::uwrite!(line, " {}", settings.page).unwrap(),
+            Setting::Altitude => ufmt::uwrite!(line, " {}", settings.altitude).unwrap(),
+            Setting::Co2Alarm => {
+                let (warning, critical) = settings.co2_alarm;
+                ufmt::uwrite!(line, " {} {}", warning, critical).unwrap();
+            }
+            Setting::TemperatureGain => {
+                line.push(' ').unwrap();
+                line.push_str(&format_decimal(settings.temperature_gain, 3))
+                    .unwrap();
+            }
+            Setting::PressureSensorHeating => {
+                let (offset, gain) = settings.pressure_sensor_heating;
+                line.push(' ').unwrap();
+                line.push_str(&format_decimal(offset, 2)).unwrap();
+                line.push(' ').unwrap();
+                line.push_str(&format_decimal(gain, 3)).unwrap();
+            }
+            Setting::TemperatureWeights => {
+                let (scd30, bmp388) = settings.temperature_weights;
+                ufmt::uwrite!(line, " {} {}", scd30, bmp388).unwrap();
+            }
+        }
+        line.push_str("\r\n").unwrap();
+        line
+    }
+}
+
+/// A new value for a setting
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub enum Change {
+    TemperatureOffset(f32),
+    Co2Baseline(u16),
+    PressureRange(u16, u16),
+    CleaningProbability(u8),
+    Page(u8),
+    Altitude(i16),
+    Co2Alarm(u16, u16),
+    TemperatureGain(f32),
+    PressureSensorHeating(f32, f32),
+    TemperatureWeights(u8, u8),
+}
+
+impl Change {
+    fn parse<'a>(
+        setting: Setting,
+        args: &mut impl Iterator<Item = &'a str>,
+    ) -> Result<Self, Error> {
+        Ok(match setting {
+            Setting::TemperatureOffset => Change::TemperatureOffset(argument(args)?),
+            Setting::Co2Baseline => Change::Co2Baseline(argument(args)?),
+            Setting::PressureRange => Change::PressureRange(argument(args)?, argument(args)?),
+            Setting::CleaningProbability => Change::CleaningProbability(argument(args)?),
+            Setting::Page => Change::Page(argument(args)?),
+            Setting::Altitude => Change::Altitude(argument(args)?),
+            Setting::Co2Alarm => Change::Co2Alarm(argument(args)?, argument(args)?),
+            Setting::TemperatureGain => Change::TemperatureGain(argument(args)?),
+            Setting::PressureSensorHeating => {
+                Change::PressureSensorHeating(argument(args)?, argument(args)?)
+            }
+            Setting::TemperatureWeights => {
+                Change::TemperatureWeights(argument(args)?, argument(args)?)
+            }
+        })
+    }
+
+    pub fn setting(&self) -> Setting {
+        match self {
+            Change::TemperatureOffset(_) => Setting::TemperatureOffset,
+            Change::Co2Baseline(_) => Setting::Co2Baseline,
+            Change::PressureRange(..) => Setting::PressureRange,
+            Change::CleaningProbability(_) => Setting::CleaningProbability,
+            Change::Page(_) => Setting::Page,
+            Change::Altitude(_) => Setting::Altitude,
+            Change::Co2Alarm(..) => Setting::Co2Alarm,
+            Change::TemperatureGain(_) => Setting::TemperatureGain,
+            Change::PressureSensorHeating(..) => Setting::PressureSensorHeating,
+            Change::TemperatureWeights(..) => Setting::TemperatureWeights,
+        }
+    }
+
+    /// `settings` with the change made, or [`Error::InvalidValue`] if the
+    /// new value is out of range
+    pub fn apply(&self, settings: &Settings) -> Result<Settings, Error> {
+        let mut changed = *settings;
+        match *self {
+            Change::TemperatureOffset(offset) => changed.temperature_offset = offset,
+            Change::Co2Baseline(baseline) => changed.co2_baseline = baseline,
+            Change::PressureRange(min, max) => changed.pressure_range = (min, max),
+            Change::CleaningProbability(probability) => changed.cleaning_probability = probability,
+            Change::Page(page) => changed.page = page,
+            Change::Altitude(altitude) => changed.altitude = altitude,
+            Change::Co2Alarm(warning, critical) => changed.co2_alarm = (warning, critical),
+            Change::TemperatureGain(gain) => changed.temperature_gain = gain,
+            Change::PressureSensorHeating(offset, gain) => {
+                changed.pressure_sensor_heating = (offset, gain)
+            }
+            Change::TemperatureWeights(scd30, bmp388) => {
+                changed.temperature_weights = (scd30, bmp388)
+            }
+        }
+        // Sanitizing puts back the defaults for values that make no sense
+        if changed.sanitized() != changed {
+            return Err(Error::InvalidValue);
+        }
+        Ok(changed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub enum Command {
+    Help,
+    Version,
+    /// `None` for all of the settings
+    Get(Option<Setting>),
+    Set(Change),
+    /// Reference CO2 level, ppm
+    Recalibrate(u16),
+    Clean,
+    /// Unix time to set the clock to, `None` to show it
+    Time(Option<u32>),
+    DumpLog(LogFormat),
+    Metrics(MetricsFormat),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum LogFormat {
+    Csv,
+    /// See [`raw_frame`]
+    Raw,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum MetricsFormat {
+    Prometheus,
+    Influx,
+}
+
+/// The range that the SCD30 accepts for forced recalibration, ppm
+const RECALIBRATION_RANGE: core::ops::RangeInclusive<u16> = 400..=2000;
+
+pub fn parse(line: &str) -> Result<Command, Error> {
+    let mut words = line.split_ascii_whitespace();
+    let command = match words.next().ok_or(Error::UnknownCommand)? {
+        "help" => Command::Help,
+        "version" => Command::Version,
+        "get" => Command::Get(words.next().map(setting).transpose()?),
+        "set" => {
+            let setting = setting(words.next().ok_or(Error::MissingArgument)?)?;
+            Command::Set(Change::parse(setting, &mut words)?)
+        }
+        "recalibrate" => {
+            let reference = argument(&mut words)?;
+            if !RECALIBRATION_RANGE.contains(&reference) {
+                return Err(Error::InvalidValue);
+            }
+            Command::Recalibrate(reference)
+        }
+        "clean" => Command::Clean,
+        "time" => Command::Time(words.next().map(time).transpose()?),
+        "log" => Command::DumpLog(match words.next() {
+            None | Some("csv") => LogFormat::Csv,
+            Some("raw") => LogFormat::Raw,
+            Some(_) => return Err(Error::InvalidValue),
+        }),
+        "metrics" => Command::Metrics(match words.next() {
+            None | Some("prometheus") => MetricsFormat::Prometheus,
+            Some("influx") => MetricsFormat::Influx,
+            Some(_) => return Err(Error::InvalidValue),
+        }),
+        _ => return Err(Error::UnknownCommand),
+    };
+    if words.next().is_some() {
+        return Err(Error::TooManyArguments);
+    }
+    Ok(command)
+}
+
+fn setting(key: &str) -> Result<Setting, Error> {
+    Setting::from_key(key).ok_or(Error::UnknownSetting)
+}
+
+fn argument<'a, T: core::str::FromStr>(
+    args: &mut impl Iterator<Item = &'a str>,
+) -> Result<T, Error> {
+    args.next()
+        .ok_or(Error::MissingArgument)?
+        .parse()
+        .map_err(|_| Error::InvalidValue)
+}
+
+/// Unix time, or ISO 8601. Anything before [`time::EARLIEST`] would be taken
+/// for seconds since boot, so it isn't accepted.
+fn time(text: &str) -> Result<u32, Error> {
+    match text.parse() {
+        Ok(unix) => Some(unix),
+        Err(_) => DateTime::parse(text).and_then(|time| time.to_unix()),
+    }
+    .filter(|&unix| unix >= time::EARLIEST)
+    .ok_or(Error::InvalidValue)
+}
+
+/// `frame` as a line of hex digits
+pub fn raw_frame(frame: &[u8]) -> heapless::String<{ 2 * MAX_FRAME_SIZE + 2 }> {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut line = heapless::String::new();
+    for &byte in frame {
+        line.push(DIGITS[(byte >> 4) as usize] as char).unwrap();
+        line.push(DIGITS[(byte & 0x0f) as usize] as char).unwrap();
+    }
+    line.push_str("\r\n").unwrap();
+    line
+}
+
+/// Reads what [`raw_frame`] writes, `None` unless it's all pairs of hex
+/// digits. Whether it's a frame is up to the decoder.
+pub fn parse_raw_frame(line: &str) -> Option<Frame> {
+    let line = line.trim_end();
+    if line.len() % 2 != 0 || line.len() > 2 * MAX_FRAME_SIZE {
+        return None;
+    }
+    line.as_bytes()
+        .chunks(2)
+        .map(|digits| u8::from_str_radix(core::str::from_utf8(digits).ok()?, 16).ok())
+        .collect()
+}
+
+/// Puts received bytes together into lines
+pub struct LineReader {
+    line: Line,
+    too_long: bool,
+}
+
+impl LineReader {
+    pub const fn new() -> Self {
+        LineReader {
+            line: Line::new(),
+            too_long: false,
+        }
+    }
+
+    /// Feed in the next received byte. Gives the line once it's complete,
+    /// skipping empty ones.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Line, Error>> {
+        match byte {
+            b'\r' | b'\n' => {
+                let line = core::mem::take(&mut self.line);
+                if core::mem::take(&mut self.too_long) {
+                    Some(Err(Error::LineTooLong))
+                } else if line.trim().is_empty() {
+                    None
+                } else {
+                    Some(Ok(line))
+                }
+            }
+            // Backspace and delete, for typing into a terminal
+            0x08 | 0x7f => {
+                self.line.pop();
+                None
+            }
+            byte if byte == b' ' || byte.is_ascii_graphic() => {
+                if self.line.push(byte as char).is_err() {
+                    self.too_long = true;
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Default for LineReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{
+        parse, parse_raw_frame, raw_frame, Change, Command, Error, LineReader, LogFormat,
+        MetricsFormat, Setting, MAX_LINE,
+    };
+    use crate::logic::{
+        codec::{Decoder, Encoder, Frame, MAX_FRAME_SIZE},
+        measurement::Measurement,
+        settings::Settings,
+    };
+
+    fn measurement() -> Measurement {
+        Measurement {
+            co2: Some(612.),
+            temperature: Some(21.37),
+            pm2_5: Some(3.1),
+            ..Default::default()
+        }
+    }
+
+    pub fn commands() {
+        assert_eq!(parse("help"), Ok(Command::Help));
+        assert_eq!(parse("  version "), Ok(Command::Version));
+        assert_eq!(parse("get"), Ok(Command::Get(None)));
+        assert_eq!(
+            parse("get altitude"),
+            Ok(Command::Get(Some(Setting::Altitude)))
+        );
+        assert_eq!(parse("recalibrate 415"), Ok(Command::Recalibrate(415)));
+        assert_eq!(parse("clean"), Ok(Command::Clean));
+        assert_eq!(parse("time"), Ok(Command::Time(None)));
+        assert_eq!(
+            parse("time 1700000000"),
+            Ok(Command::Time(Some(1_700_000_000)))
+        );
+        assert_eq!(
+            parse("time 2023-11-14T22:13:20Z"),
+            Ok(Command::Time(Some(1_700_000_000)))
+        );
+        assert_eq!(parse("log"), Ok(Command::DumpLog(LogFormat::Csv)));
+        assert_eq!(parse("log raw"), Ok(Command::DumpLog(LogFormat::Raw)));
+        assert_eq!(parse("log pdf"), Err(Error::InvalidValue));
+        assert_eq!(
+            parse("metrics"),
+            Ok(Command::Metrics(MetricsFormat::Prometheus))
+        );
+        assert_eq!(
+            parse("metrics influx"),
+            Ok(Command::Metrics(MetricsFormat::Influx))
+        );
+        assert_eq!(parse("metrics graphite"), Err(Error::InvalidValue));
+
+        assert_eq!(parse(""), Err(Error::UnknownCommand));
+        assert_eq!(parse("reboot"), Err(Error::UnknownCommand));
+        assert_eq!(parse("get colour"), Err(Error::UnknownSetting));
+        assert_eq!(parse("clean now"), Err(Error::TooManyArguments));
+        assert_eq!(parse("recalibrate"), Err(Error::MissingArgument));
+        assert_eq!(parse("recalibrate 5000"), Err(Error::InvalidValue));
+        assert_eq!(parse("time yesterday"), Err(Error::InvalidValue));
+        assert_eq!(parse("time 5"), Err(Error::InvalidValue));
+        assert_eq!(parse("time 1999-12-31T23:59:59Z"), Err(Error::InvalidValue));
+        assert_eq!(
+            parse("time 2000-01-01T00:00:00Z"),
+            Ok(Command::Time(Some(946_684_800)))
+        );
+    }
+
+    pub fn settings() {
+        assert_eq!(
+            parse("set temperature_offset 2.5"),
+            Ok(Command::Set(Change::TemperatureOffset(2.5)))
+        );
+        assert_eq!(
+            parse("set pressure_range 980 1050"),
+            Ok(Command::Set(Change::PressureRange(980, 1050)))
+        );
+        assert_eq!(
+            parse("set pressure_sensor_heating 1.5 0.125"),
+            Ok(Command::Set(Change::PressureSensorHeating(1.5, 0.125)))
+        );
+        assert_eq!(parse("set co2_alarm 1200"), Err(Error::MissingArgument));
+        assert_eq!(parse("set altitude high"), Err(Error::InvalidValue));
+        assert_eq!(parse("set page -1"), Err(Error::InvalidValue));
+        assert_eq!(parse("set page 1 2"), Err(Error::TooManyArguments));
+
+        let changed = Change::Co2Alarm(1200, 2500)
+            .apply(&Settings::DEFAULT)
+            .unwrap();
+        assert_eq!(changed.co2_alarm, (1200, 2500));
+        assert_eq!(
+            Change::Co2Alarm(2500, 1200).apply(&Settings::DEFAULT),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            Change::Altitude(10_000).apply(&Settings::DEFAULT),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            Change::TemperatureWeights(0, 0).apply(&Settings::DEFAULT),
+            Err(Error::InvalidValue)
+        );
+
+        assert_eq!(
+            Setting::TemperatureOffset.show(&Settings::DEFAULT).as_str(),
+            "temperature_offset 3.72\r\n"
+        );
+        assert_eq!(
+            Setting::PressureRange.show(&Settings::DEFAULT).as_str(),
+            "pressure_range 990 1040\r\n"
+        );
+        assert_eq!(
+            Setting::PressureSensorHeating
+                .show(&Settings::DEFAULT)
+                .as_str(),
+            "pressure_sensor_heating 0.00 0.000\r\n"
+        );
+        // Every setting that can be shown can be set to what it shows
+        for setting in Setting::ALL {
+            let shown = setting.show(&changed);
+            let mut line: heapless::String<80> = heapless::String::new();
+            line.push_str("set ").unwrap();
+            line.push_str(shown.trim_end()).unwrap();
+            let Ok(Command::Set(change)) = parse(&line) else {
+                panic!("{}", line);
+            };
+            assert_eq!(change.setting(), setting);
+            assert_eq!(change.apply(&changed), Ok(changed), "{}", line);
+        }
+    }
+
+    pub fn line_reader() {
+        let mut reader = LineReader::new();
+        let mut lines: heapless::Vec<Result<heapless::String<MAX_LINE>, Error>, 4> =
+            heapless::Vec::new();
+        for &byte in b"\r\nhelq\x08p\r\n\r\n\x1bget\n" {
+            if let Some(line) = reader.push(byte) {
+                lines.push(line).unwrap();
+            }
+        }
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].as_deref(), Ok("help"));
+        assert_eq!(lines[1].as_deref(), Ok("get"));
+
+        for _ in 0..MAX_LINE + 1 {
+            assert_eq!(reader.push(b'x'), None);
+        }
+        assert_eq!(reader.push(b'\n'), Some(Err(Error::LineTooLong)));
+        assert_eq!(reader.push(b'a'), None);
+        assert_eq!(reader.push(b'\n').unwrap().as_deref(), Ok("a"));
+    }
+
+    pub fn raw_frames() {
+        assert_eq!(raw_frame(&[0x01, 0x00, 0xab]).as_str(), "0100ab\r\n");
+        assert_eq!(
+            parse_raw_frame("0100ab\r\n"),
+            Frame::from_slice(&[0x01, 0x00, 0xab]).ok()
+        );
+
+        // A stream of key and delta frames comes through
+        let mut encoder = Encoder::new(2);
+        let mut decoder = Decoder::new();
+        for i in 0..3 {
+            let frame = encoder.encode(1_700_000_000 + 120 * i, &measurement());
+            let parsed = parse_raw_frame(&raw_frame(&frame)).unwrap();
+            assert_eq!(parsed, frame);
+            let (timestamp, decoded) = decoder.decode(&parsed).unwrap();
+            assert_eq!(timestamp, 1_700_000_000 + 120 * i);
+            assert_eq!(decoded.co2, measurement().co2);
+        }
+
+        assert_eq!(parse_raw_frame("010"), None);
+        assert_eq!(parse_raw_frame("01zz"), None);
+        assert_eq!(parse_raw_frame("ok"), None);
+        let mut too_long: heapless::String<{ 2 * MAX_FRAME_SIZE + 2 }> = heapless::String::new();
+        for _ in 0..=MAX_FRAME_SIZE {
+            too_long.push_str("00").unwrap();
+        }
+        assert_eq!(parse_raw_frame(&too_long), None);
+    }
+}
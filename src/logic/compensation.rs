@@ -0,0 +1,113 @@
+//! Keeps the SCD30's pressure compensation up to date
+//!
+//! The CO2 reading of the SCD30 depends on the ambient pressure. It gets the
+//! barometer's pressure whenever that has drifted far enough from what the
+//! sensor was last given, and the configured altitude if there's no barometer
+//! or it hasn't been heard from in a while.
+
+use micromath::F32Ext;
+
+/// The range of ambient pressures that the SCD30 accepts, hPa
+const PRESSURE_RANGE: (f32, f32) = (700., 1400.);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Compensation {
+    /// Ambient pressure, hPa
+    Pressure(u16),
+    /// Altitude above sea level, m
+    Altitude(u16),
+}
+
+pub struct CompensationManager {
+    /// hPa
+    threshold: f32,
+    altitude: u16,
+    /// Seconds without a pressure reading before falling back to the altitude
+    timeout: u32,
+    current: Option<Compensation>,
+    last_pressure: Option<u32>,
+}
+
+impl CompensationManager {
+    /// Update the pressure when it changes by more than `threshold` hPa and
+    /// fall back to `altitude` when there has been no pressure for `timeout`
+    /// seconds
+    pub const fn new(threshold: f32, altitude: u16, timeout: u32) -> Self {
+        CompensationManager {
+            threshold,
+            altitude,
+            timeout,
+            current: None,
+            last_pressure: None,
+        }
+    }
+
+    /// Feed in the barometer's pressure in Pa, if there is one, at `now`
+    /// seconds since boot. Returns the compensation that the sensor should
+    /// be given, if that's different from what it has.
+    pub fn update(&mut self, now: u32, pressure: Option<f32>) -> Option<Compensation> {
+        let target = match pressure {
+            Some(pressure) => {
+                self.last_pressure = Some(now);
+                let hpa = (pressure / 100.).clamp(PRESSURE_RANGE.0, PRESSURE_RANGE.1);
+                match self.current {
+                    Some(Compensation::Pressure(current))
+                        if (hpa - current as f32).abs() <= self.threshold =>
+                    {
+                        return None;
+                    }
+                    _ => Compensation::Pressure(hpa.round() as u16),
+                }
+            }
+            None => match self.last_pressure {
+                // Wrapping, because the seconds counter is allowed to overflow
+                Some(last) if now.wrapping_sub(last) < self.timeout => return None,
+                _ => Compensation::Altitude(self.altitude),
+            },
+        };
+        if self.current == Some(target) {
+            return None;
+        }
+        self.current = Some(target);
+        Some(target)
+    }
+
+    /// What the sensor was last given
+    pub fn current(&self) -> Option<Compensation> {
+        self.current
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Compensation, CompensationManager};
+
+    pub fn pressure_threshold() {
+        let mut manager = CompensationManager::new(2., 10, 600);
+        assert_eq!(
+            manager.update(0, Some(101_320.)),
+            Some(Compensation::Pressure(1013))
+        );
+        assert_eq!(manager.update(5, Some(101_490.)), None);
+        assert_eq!(
+            manager.update(10, Some(101_510.)),
+            Some(Compensation::Pressure(1015))
+        );
+        assert_eq!(manager.update(15, Some(101_330.)), None);
+        assert_eq!(manager.current(), Some(Compensation::Pressure(1015)));
+    }
+
+    pub fn altitude_fallback() {
+        let mut manager = CompensationManager::new(2., 10, 600);
+        assert_eq!(manager.update(0, None), Some(Compensation::Altitude(10)));
+        assert_eq!(manager.update(5, None), None);
+
+        // A barometer that drops out briefly keeps the last pressure
+        assert_eq!(
+            manager.update(10, Some(100_000.)),
+            Some(Compensation::Pressure(1000))
+        );
+        assert_eq!(manager.update(300, None), None);
+        assert_eq!(manager.update(610, None), Some(Compensation::Altitude(10)));
+    }
+}
@@ -0,0 +1,91 @@
+//! Measurements as comma separated values, one file per day
+
+use crate::logic::{
+    formatting::format_decimal,
+    logger::resolution,
+    measurement::{Measurement, Quantity},
+    time::DateTime,
+};
+
+/// Long enough for the header, and for a row with every value present
+pub type Line = heapless::String<384>;
+
+/// How many decimals a value of `quantity` gets, enough for the resolution
+/// that it's logged at
+pub fn precision(quantity: Quantity) -> u8 {
+    let mut precision = 0;
+    let mut step = resolution(quantity);
+    while step < 0.999 {
+        step *= 10.;
+        precision += 1;
+    }
+    precision
+}
+
+/// The column names, ending in a line break
+pub fn header() -> Line {
+    let mut line = Line::new();
+    line.push_str("time,timestamp").unwrap();
+    for quantity in Quantity::ALL {
+        line.push(',').unwrap();
+        line.push_str(quantity.key()).unwrap();
+    }
+    line.push_str("\r\n").unwrap();
+    line
+}
+
+/// `measurement` taken at `timestamp`, Unix time, ending in a line break.
+/// Missing values are left empty.
+pub fn row(timestamp: u32, measurement: &Measurement) -> Line {
+    let mut line = Line::new();
+    line.push_str(&DateTime::from_unix(timestamp).iso8601())
+        .unwrap();
+    ufmt::uwrite!(line, ",{}", timestamp).unwrap();
+    for quantity in Quantity::ALL {
+        line.push(',').unwrap();
+        if let Some(value) = measurement.get(quantity) {
+            line.push_str(&format_decimal(value, precision(quantity)))
+                .unwrap();
+        }
+    }
+    line.push_str("\r\n").unwrap();
+    line
+}
+
+/// The 8.3 name of the file for the day of `timestamp`, e.g. "20231114.CSV"
+pub fn file_name(timestamp: u32) -> heapless::String<12> {
+    let date = DateTime::from_unix(timestamp);
+    let mut name = heapless::String::new();
+    let date_number = date.year as u32 * 10_000 + date.month as u32 * 100 + date.day as u32;
+    ufmt::uwrite!(name, "{}.CSV", date_number).unwrap();
+    name
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{file_name, header, precision, row};
+    use crate::logic::measurement::{Measurement, Quantity};
+
+    pub fn columns() {
+        assert!(header().starts_with("time,timestamp,co2,temperature,rel_humidity,"));
+        assert!(header().ends_with(",ambient_temperature\r\n"));
+        assert_eq!(precision(Quantity::Co2), 0);
+        assert_eq!(precision(Quantity::Pressure), 0);
+        assert_eq!(precision(Quantity::Pm2_5), 1);
+        assert_eq!(precision(Quantity::Temperature), 2);
+    }
+
+    pub fn rows() {
+        let measurement = Measurement {
+            co2: Some(612.4),
+            temperature: Some(21.374),
+            rel_humidity: Some(45.),
+            ..Default::default()
+        };
+        assert_eq!(
+            row(1_700_000_000, &measurement).as_str(),
+            "2023-11-14T22:13:20Z,1700000000,612,21.37,45.00,,,,,,,,,,,,,,\r\n"
+        );
+        assert_eq!(file_name(1_700_000_000).as_str(), "20231114.CSV");
+    }
+}
@@ -0,0 +1,268 @@
+//! What the flash backed stores need from the flash, and an in-memory stand-in
+//! for testing them
+
+use core::cell::RefCell;
+
+use crc_all::Crc;
+
+/// NOR flash: erasing a page sets all of its bits, and writes can only clear
+/// bits
+pub trait Flash {
+    type Error;
+
+    /// Size of the pages that get erased at once, bytes
+    const PAGE_SIZE: usize;
+    /// Writes have to start at and be a multiple of this many bytes
+    const WORD_SIZE: usize;
+
+    /// Size of the whole flash area, bytes
+    fn capacity(&self) -> usize;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error>;
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error>;
+
+    /// Erase the page that starts at `offset`
+    fn erase_page(&mut self, offset: u32) -> Result<(), Self::Error>;
+}
+
+/// CRC-32 (ISO-HDLC, the one from zip and Ethernet) for checking records
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc::<u32>::new(0x04c1_1db7, 32, 0xffff_ffff, 0xffff_ffff, true);
+    crc.update(bytes);
+    crc.finish()
+}
+
+/// Whole pages of a shared flash, so that several stores can live in the one
+/// flash controller
+pub struct Partition<'a, F> {
+    flash: &'a RefCell<F>,
+    offset: u32,
+    len: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum PartitionError<E> {
+    /// Outside of the partition, even if it's inside the flash
+    OutOfBounds,
+    Flash(E),
+}
+
+impl<'a, F: Flash> Partition<'a, F> {
+    /// `pages` pages starting at page `first`
+    pub fn new(flash: &'a RefCell<F>, first: usize, pages: usize) -> Self {
+        assert!((first + pages) * F::PAGE_SIZE <= flash.borrow().capacity());
+        Partition {
+            flash,
+            offset: (first * F::PAGE_SIZE) as u32,
+            len: pages * F::PAGE_SIZE,
+        }
+    }
+
+    fn check(&self, offset: u32, len: usize) -> Result<u32, PartitionError<F::Error>> {
+        if offset as usize + len > self.len {
+            return Err(PartitionError::OutOfBounds);
+        }
+        Ok(self.offset + offset)
+    }
+}
+
+impl<'a, F: Flash> Flash for Partition<'a, F> {
+    type Error = PartitionError<F::Error>;
+
+    const PAGE_SIZE: usize = F::PAGE_SIZE;
+    const WORD_SIZE: usize = F::WORD_SIZE;
+
+    fn capacity(&self) -> usize {
+        self.len
+    }
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let offset = self.check(offset, bytes.len())?;
+        self.flash
+            .borrow_mut()
+            .read(offset, bytes)
+            .map_err(PartitionError::Flash)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let offset = self.check(offset, bytes.len())?;
+        self.flash
+            .borrow_mut()
+            .write(offset, bytes)
+            .map_err(PartitionError::Flash)
+    }
+
+    fn erase_page(&mut self, offset: u32) -> Result<(), Self::Error> {
+        let offset = self.check(offset, F::PAGE_SIZE)?;
+        self.flash
+            .borrow_mut()
+            .erase_page(offset)
+            .map_err(PartitionError::Flash)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum MemoryFlashError {
+    OutOfBounds,
+    Unaligned,
+}
+
+/// `PAGES` pages of `PAGE_SIZE` bytes in RAM that behave like NOR flash. It
+/// counts the erases of every page, to check the wear levelling with, and
+/// the reads.
+pub struct MemoryFlash<const PAGES: usize, const PAGE_SIZE: usize> {
+    pages: [[u8; PAGE_SIZE]; PAGES],
+    erases: [u32; PAGES],
+    reads: usize,
+    /// Fail writes after this many more bytes, to simulate a power loss
+    write_budget: Option<usize>,
+}
+
+impl<const PAGES: usize, const PAGE_SIZE: usize> MemoryFlash<PAGES, PAGE_SIZE> {
+    /// Blank flash, as it comes from the factory
+    pub const fn new() -> Self {
+        MemoryFlash {
+            pages: [[0xff; PAGE_SIZE]; PAGES],
+            erases: [0; PAGES],
+            reads: 0,
+            write_budget: None,
+        }
+    }
+
+    pub fn erases(&self) -> &[u32; PAGES] {
+        &self.erases
+    }
+
+    pub fn reads(&self) -> usize {
+        self.reads
+    }
+
+    /// Only the first `bytes` of the following writes make it to the flash,
+    /// as if the power was cut during the write
+    pub fn cut_power_after(&mut self, bytes: usize) {
+        self.write_budget = Some(bytes);
+    }
+
+    pub fn restore_power(&mut self) {
+        self.write_budget = None;
+    }
+
+    /// Overwrite a byte, bypassing the NOR flash rules, to corrupt data
+    pub fn corrupt(&mut self, offset: usize, value: u8) {
+        self.pages[offset / PAGE_SIZE][offset % PAGE_SIZE] = value;
+    }
+
+    fn check(&self, offset: u32, len: usize) -> Result<usize, MemoryFlashError> {
+        let offset = offset as usize;
+        if offset + len > PAGES * PAGE_SIZE {
+            return Err(MemoryFlashError::OutOfBounds);
+        }
+        Ok(offset)
+    }
+}
+
+impl<const PAGES: usize, const PAGE_SIZE: usize> Default for MemoryFlash<PAGES, PAGE_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const PAGES: usize, const PAGE_SIZE: usize> Flash for MemoryFlash<PAGES, PAGE_SIZE> {
+    type Error = MemoryFlashError;
+
+    const PAGE_SIZE: usize = PAGE_SIZE;
+    const WORD_SIZE: usize = 4;
+
+    fn capacity(&self) -> usize {
+        PAGES * PAGE_SIZE
+    }
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let offset = self.check(offset, bytes.len())?;
+        self.reads += 1;
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            let position = offset + i;
+            *byte = self.pages[position / PAGE_SIZE][position % PAGE_SIZE];
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let offset = self.check(offset, bytes.len())?;
+        if offset % Self::WORD_SIZE != 0 || bytes.len() % Self::WORD_SIZE != 0 {
+            return Err(MemoryFlashError::Unaligned);
+        }
+        for (i, &byte) in bytes.iter().enumerate() {
+            if let Some(budget) = self.write_budget.as_mut() {
+                if *budget == 0 {
+                    return Ok(());
+                }
+                *budget -= 1;
+            }
+            let position = offset + i;
+            self.pages[position / PAGE_SIZE][position % PAGE_SIZE] &= byte;
+        }
+        Ok(())
+    }
+
+    fn erase_page(&mut self, offset: u32) -> Result<(), Self::Error> {
+        let offset = self.check(offset, PAGE_SIZE)?;
+        if offset % PAGE_SIZE != 0 {
+            return Err(MemoryFlashError::Unaligned);
+        }
+        self.pages[offset / PAGE_SIZE] = [0xff; PAGE_SIZE];
+        self.erases[offset / PAGE_SIZE] += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use core::cell::RefCell;
+
+    use super::{crc32, Flash, MemoryFlash, MemoryFlashError, Partition, PartitionError};
+
+    pub fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    pub fn nor_semantics() {
+        let mut flash: MemoryFlash<2, 64> = MemoryFlash::new();
+        let mut bytes = [0; 4];
+        flash.read(60, &mut bytes).unwrap();
+        assert_eq!(bytes, [0xff; 4]);
+        flash.write(64, &[0x0f, 0xf0, 0x00, 0xff]).unwrap();
+        // Writing can't set bits again
+        flash.write(64, &[0xf0, 0xff, 0xff, 0x0f]).unwrap();
+        flash.read(64, &mut bytes).unwrap();
+        assert_eq!(bytes, [0x00, 0xf0, 0x00, 0x0f]);
+        assert_eq!(flash.write(66, &[0; 4]), Err(MemoryFlashError::Unaligned));
+        assert_eq!(
+            flash.write(128, &[0; 4]),
+            Err(MemoryFlashError::OutOfBounds)
+        );
+
+        flash.erase_page(64).unwrap();
+        flash.read(64, &mut bytes).unwrap();
+        assert_eq!(bytes, [0xff; 4]);
+        assert_eq!(flash.erases(), &[0, 1]);
+    }
+
+    pub fn partitions() {
+        let flash: RefCell<MemoryFlash<4, 64>> = RefCell::new(MemoryFlash::new());
+        let mut first = Partition::new(&flash, 0, 1);
+        let mut second = Partition::new(&flash, 1, 3);
+        assert_eq!(second.capacity(), 192);
+        second.write(0, &[1, 2, 3, 4]).unwrap();
+        second.erase_page(64).unwrap();
+        assert_eq!(first.write(64, &[0; 4]), Err(PartitionError::OutOfBounds));
+
+        let mut bytes = [0; 4];
+        first.read(0, &mut bytes).unwrap();
+        assert_eq!(bytes, [0xff; 4]);
+        flash.borrow_mut().read(64, &mut bytes).unwrap();
+        assert_eq!(bytes, [1, 2, 3, 4]);
+        assert_eq!(flash.borrow().erases(), &[0, 0, 1, 0]);
+    }
+}
@@ -91,6 +91,10 @@ pub fn format_float_measurement(
             frac_text.push_str("0").unwrap();
         }
         ufmt::uwrite!(frac_text, "{}", frac_part).unwrap();
+    } else {
+        // Rounded like the fraction would have been, and like the CSV and
+        // JSON output
+        carry_over = value.fract() >= 0.5;
     }
 
     let mut output: heapless::String<16> = heapless::String::new();
@@ -109,8 +113,31 @@ pub fn format_float_measurement(
     output
 }
 
+/// A plain number rounded to `precision` decimals, for machine readable
+/// output. Unlike the measurement formatting, negative values work.
+pub fn format_decimal(value: f32, precision: u8) -> heapless::String<16> {
+    let mut output: heapless::String<16> = heapless::String::new();
+    let times = 10_u32.pow(precision as u32);
+    // The float to int cast saturates
+    let scaled = (value.abs() * times as f32).round() as u32;
+    if value < 0. && scaled > 0 {
+        output.push_str("-").unwrap();
+    }
+    ufmt::uwrite!(output, "{}", scaled / times).unwrap();
+    if precision > 0 {
+        let frac_part = scaled % times;
+        output.push_str(".").unwrap();
+        for _ in 0..(precision - u32_len(frac_part)) {
+            output.push_str("0").unwrap();
+        }
+        ufmt::uwrite!(output, "{}", frac_part).unwrap();
+    }
+    output
+}
+
 #[cfg(test)]
 pub mod tests {
+    use super::format_decimal;
     use super::format_float_measurement;
     use super::format_u32_measurement;
 
@@ -173,4 +200,27 @@ pub mod tests {
         let res = format_float_measurement(0.999, 2, 2, "°C");
         assert_eq!(res.as_str(), " 1.00 °C");
     }
+
+    pub fn format_float_no_fract() {
+        assert_eq!(
+            format_float_measurement(612.4, 4, 0, "ppm").as_str(),
+            " 612 ppm"
+        );
+        assert_eq!(
+            format_float_measurement(612.5, 4, 0, "ppm").as_str(),
+            " 613 ppm"
+        );
+        assert_eq!(
+            format_float_measurement(999.7, 3, 0, "ppm").as_str(),
+            "1000 ppm"
+        );
+    }
+
+    pub fn format_decimals() {
+        assert_eq!(format_decimal(21.375, 2).as_str(), "21.38");
+        assert_eq!(format_decimal(-0.05, 1).as_str(), "-0.1");
+        assert_eq!(format_decimal(-0.04, 1).as_str(), "0.0");
+        assert_eq!(format_decimal(101_325., 0).as_str(), "101325");
+        assert_eq!(format_decimal(3.04, 2).as_str(), "3.04");
+    }
 }
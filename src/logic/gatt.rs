@@ -0,0 +1,585 @@
+//! GATT server, answering the ATT requests that come in over a Bluetooth
+//! connection
+//!
+//! The attribute table is fixed:
+//!
+//! | Handle | Attribute                                               |
+//! |--------|---------------------------------------------------------|
+//! | 1      | Generic Access service                                  |
+//! | 2..=5  | Device name and appearance                              |
+//! | 6      | Environmental Sensing service                           |
+//! | 7..=15 | Temperature, humidity and pressure, each with its CCCD  |
+//! | 16     | The custom airlog service                               |
+//! | 17..=25| CO2, VOC index and particulate matter, each with its CCCD |
+//! | 26..=29| Configuration, in two parts                             |
+//!
+//! The measurements can be read, and get notified when they change if the
+//! client turns that on in their Client Characteristic Configuration
+//! descriptor. The configuration can be read and written. See
+//! [`characteristic`](crate::logic::characteristic) for the values.
+//!
+//! There's no pairing and the ATT MTU stays at its default of 23 bytes, which
+//! all the values fit in.
+
+use crate::logic::{
+    characteristic::{
+        config, parse_config, Characteristic, ConfigError, ConfigPart, Uuid, Value, AIRLOG,
+        ENVIRONMENTAL_SENSING,
+    },
+    measurement::Measurement,
+    settings::Settings,
+};
+
+/// The ATT MTU, and the most that a PDU can take
+pub const MTU: usize = 23;
+
+pub type Pdu = heapless::Vec<u8, MTU>;
+
+/// What the device is called in the Generic Access service
+pub const DEVICE_NAME: &str = "airlog";
+/// Generic Sensor
+const APPEARANCE: u16 = 0x0540;
+
+mod opcode {
+    pub const ERROR_RESPONSE: u8 = 0x01;
+    pub const EXCHANGE_MTU_REQUEST: u8 = 0x02;
+    pub const EXCHANGE_MTU_RESPONSE: u8 = 0x03;
+    pub const FIND_INFORMATION_REQUEST: u8 = 0x04;
+    pub const FIND_INFORMATION_RESPONSE: u8 = 0x05;
+    pub const FIND_BY_TYPE_VALUE_REQUEST: u8 = 0x06;
+    pub const FIND_BY_TYPE_VALUE_RESPONSE: u8 = 0x07;
+    pub const READ_BY_TYPE_REQUEST: u8 = 0x08;
+    pub const READ_BY_TYPE_RESPONSE: u8 = 0x09;
+    pub const READ_REQUEST: u8 = 0x0a;
+    pub const READ_RESPONSE: u8 = 0x0b;
+    pub const READ_BLOB_REQUEST: u8 = 0x0c;
+    pub const READ_BLOB_RESPONSE: u8 = 0x0d;
+    pub const READ_BY_GROUP_TYPE_REQUEST: u8 = 0x10;
+    pub const READ_BY_GROUP_TYPE_RESPONSE: u8 = 0x11;
+    pub const WRITE_REQUEST: u8 = 0x12;
+    pub const WRITE_RESPONSE: u8 = 0x13;
+    pub const HANDLE_VALUE_NOTIFICATION: u8 = 0x1b;
+    pub const HANDLE_VALUE_CONFIRMATION: u8 = 0x1e;
+    pub const WRITE_COMMAND: u8 = 0x52;
+    /// Set in the opcodes that don't get a response
+    pub const COMMAND_FLAG: u8 = 0x40;
+}
+
+/// ATT error codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error {
+    InvalidHandle = 0x01,
+    WriteNotPermitted = 0x03,
+    InvalidPdu = 0x04,
+    RequestNotSupported = 0x06,
+    InvalidOffset = 0x07,
+    AttributeNotFound = 0x0a,
+    InvalidAttributeValueLength = 0x0d,
+    UnsupportedGroupType = 0x10,
+    ValueNotAllowed = 0x13,
+}
+
+const PRIMARY_SERVICE: Uuid = Uuid::Short(0x2800);
+const CHARACTERISTIC: Uuid = Uuid::Short(0x2803);
+const CLIENT_CONFIGURATION: Uuid = Uuid::Short(0x2902);
+const GENERIC_ACCESS: Uuid = Uuid::Short(0x1800);
+const DEVICE_NAME_UUID: Uuid = Uuid::Short(0x2a00);
+const APPEARANCE_UUID: Uuid = Uuid::Short(0x2a01);
+
+/// Characteristic properties
+const READ: u8 = 0x02;
+const WRITE: u8 = 0x08;
+const NOTIFY: u8 = 0x10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Attribute {
+    PrimaryService(Uuid),
+    /// The declaration of the characteristic whose value is the next
+    /// attribute
+    Declaration(u8),
+    DeviceName,
+    Appearance,
+    Measured(Characteristic),
+    ClientConfiguration(Characteristic),
+    Config(ConfigPart),
+}
+
+use Attribute::*;
+
+/// Handle 1 is the first one
+const ATTRIBUTES: [Attribute; 29] = [
+    PrimaryService(GENERIC_ACCESS),
+    Declaration(READ),
+    DeviceName,
+    Declaration(READ),
+    Appearance,
+    PrimaryService(ENVIRONMENTAL_SENSING),
+    Declaration(READ | NOTIFY),
+    Measured(Characteristic::Temperature),
+    ClientConfiguration(Characteristic::Temperature),
+    Declaration(READ | NOTIFY),
+    Measured(Characteristic::Humidity),
+    ClientConfiguration(Characteristic::Humidity),
+    Declaration(READ | NOTIFY),
+    Measured(Characteristic::Pressure),
+    ClientConfiguration(Characteristic::Pressure),
+    PrimaryService(AIRLOG),
+    Declaration(READ | NOTIFY),
+    Measured(Characteristic::Co2),
+    ClientConfiguration(Characteristic::Co2),
+    Declaration(READ | NOTIFY),
+    Measured(Characteristic::VocIndex),
+    ClientConfiguration(Characteristic::VocIndex),
+    Declaration(READ | NOTIFY),
+    Measured(Characteristic::ParticulateMatter),
+    ClientConfiguration(Characteristic::ParticulateMatter),
+    Declaration(READ | WRITE),
+    Config(ConfigPart::General),
+    Declaration(READ | WRITE),
+    Config(ConfigPart::Temperature),
+];
+
+const LAST_HANDLE: u16 = ATTRIBUTES.len() as u16;
+
+fn attribute(handle: u16) -> Option<Attribute> {
+    ATTRIBUTES.get(usize::from(handle).checked_sub(1)?).copied()
+}
+
+impl Attribute {
+    /// The type of the attribute
+    fn uuid(&self) -> Uuid {
+        match self {
+            PrimaryService(_) => PRIMARY_SERVICE,
+            Declaration(_) => CHARACTERISTIC,
+            DeviceName => DEVICE_NAME_UUID,
+            Appearance => APPEARANCE_UUID,
+            Measured(characteristic) => characteristic.uuid(),
+            ClientConfiguration(_) => CLIENT_CONFIGURATION,
+            Config(part) => part.uuid(),
+        }
+    }
+}
+
+/// The last handle of the service that starts at `handle`
+fn group_end(handle: u16) -> u16 {
+    (handle + 1..=LAST_HANDLE)
+        .find(|&next| matches!(attribute(next), Some(PrimaryService(_))))
+        .map_or(LAST_HANDLE, |next| next - 1)
+}
+
+pub struct Server {
+    measurement: Measurement,
+    settings: Settings,
+    /// Whether the client wants notifications, in the order of
+    /// [`Characteristic::ALL`]
+    notify: [bool; Characteristic::ALL.len()],
+    written: Option<Settings>,
+}
+
+impl Server {
+    pub fn new(settings: Settings) -> Self {
+        Server {
+            measurement: Measurement::default(),
+            settings,
+            notify: [false; Characteristic::ALL.len()],
+            written: None,
+        }
+    }
+
+    /// The response to an ATT PDU from the client, if it gets one
+    pub fn handle(&mut self, request: &[u8]) -> Option<Pdu> {
+        let &opcode = request.first()?;
+        match self.respond(opcode, &request[1..]) {
+            Ok(response) => response,
+            // Commands don't get errors either
+            Err(_) if opcode & opcode::COMMAND_FLAG != 0 => None,
+            Err((handle, error)) => {
+                let mut pdu = Pdu::new();
+                pdu.extend_from_slice(&[opcode::ERROR_RESPONSE, opcode])
+                    .unwrap();
+                pdu.extend_from_slice(&handle.to_le_bytes()).unwrap();
+                pdu.push(error as u8).unwrap();
+                Some(pdu)
+            }
+        }
+    }
+
+    /// Takes the measurement that gets served, and returns the notifications
+    /// for the values that changed, if the client wants them
+    pub fn update(&mut self, measurement: &Measurement) -> heapless::Vec<Pdu, 6> {
+        let mut notifications = heapless::Vec::new();
+        for (index, characteristic) in Characteristic::ALL.into_iter().enumerate() {
+            let value = characteristic.value(measurement);
+            if self.notify[index] && value != characteristic.value(&self.measurement) {
+                let handle = value_handle(characteristic);
+                let mut pdu = Pdu::new();
+                pdu.push(opcode::HANDLE_VALUE_NOTIFICATION).unwrap();
+                pdu.extend_from_slice(&handle.to_le_bytes()).unwrap();
+                pdu.extend_from_slice(&value).unwrap();
+                notifications.push(pdu).unwrap();
+            }
+        }
+        self.measurement = *measurement;
+        notifications
+    }
+
+    /// Takes the settings that the configuration characteristic shows
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.settings = settings;
+    }
+
+    /// Settings that the client wrote, once
+    pub fn take_written(&mut self) -> Option<Settings> {
+        self.written.take()
+    }
+
+    /// Without bonding, nothing carries over to the next connection
+    pub fn disconnected(&mut self) {
+        self.notify = [false; Characteristic::ALL.len()];
+    }
+
+    fn read(&self, attribute: Attribute, handle: u16) -> Value {
+        let mut value = Value::new();
+        match attribute {
+            PrimaryService(uuid) => value.extend_from_slice(&uuid.bytes()).unwrap(),
+            Declaration(properties) => {
+                let uuid = attribute_uuid(handle + 1);
+                value.push(properties).unwrap();
+                value
+                    .extend_from_slice(&(handle + 1).to_le_bytes())
+                    .unwrap();
+                value.extend_from_slice(&uuid.bytes()).unwrap();
+            }
+            DeviceName => value.extend_from_slice(DEVICE_NAME.as_bytes()).unwrap(),
+            Appearance => value.extend_from_slice(&APPEARANCE.to_le_bytes()).unwrap(),
+            Measured(characteristic) => value = characteristic.value(&self.measurement),
+            ClientConfiguration(characteristic) => {
+                let notify = self.notify[index(characteristic)] as u16;
+                value.extend_from_slice(&notify.to_le_bytes()).unwrap();
+            }
+            Config(part) => value = config(&self.settings, part),
+        }
+        value
+    }
+
+    fn write(&mut self, handle: u16, value: &[u8]) -> Result<(), (u16, Error)> {
+        match attribute(handle).ok_or((handle, Error::InvalidHandle))? {
+            ClientConfiguration(characteristic) => match value {
+                &[flags, _] => self.notify[index(characteristic)] = flags & 1 != 0,
+                _ => return Err((handle, Error::InvalidAttributeValueLength)),
+            },
+            Config(part) => {
+                let settings =
+                    parse_config(&self.settings, part, value).map_err(|error| match error {
+                        ConfigError::InvalidLength => (handle, Error::InvalidAttributeValueLength),
+                        ConfigError::InvalidValue => (handle, Error::ValueNotAllowed),
+                    })?;
+                self.settings = settings;
+                self.written = Some(settings);
+            }
+            _ => return Err((handle, Error::WriteNotPermitted)),
+        }
+        Ok(())
+    }
+
+    /// The response, or the handle that the error is about and the error
+    fn respond(&mut self, opcode: u8, parameters: &[u8]) -> Result<Option<Pdu>, (u16, Error)> {
+        let handle_at = |offset: usize| {
+            parameters
+                .get(offset..offset + 2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                .ok_or((0, Error::InvalidPdu))
+        };
+        let mut pdu = Pdu::new();
+        match opcode {
+            opcode::EXCHANGE_MTU_REQUEST => {
+                handle_at(0)?;
+                pdu.push(opcode::EXCHANGE_MTU_RESPONSE).unwrap();
+                pdu.extend_from_slice(&(MTU as u16).to_le_bytes()).unwrap();
+            }
+            opcode::FIND_INFORMATION_REQUEST => {
+                let handles = range(handle_at(0)?, handle_at(2)?)?;
+                pdu.extend_from_slice(&[opcode::FIND_INFORMATION_RESPONSE, 0])
+                    .unwrap();
+                let mut format = None;
+                for handle in handles.clone() {
+                    let uuid = attribute_uuid(handle);
+                    let this = match uuid {
+                        Uuid::Short(_) => 1,
+                        Uuid::Long(_) => 2,
+                    };
+                    if *format.get_or_insert(this) != this
+                        || pdu.len() + 2 + uuid.bytes().len() > MTU
+                    {
+                        break;
+                    }
+                    pdu.extend_from_slice(&handle.to_le_bytes()).unwrap();
+                    pdu.extend_from_slice(&uuid.bytes()).unwrap();
+                }
+                pdu[1] = format.ok_or((*handles.start(), Error::AttributeNotFound))?;
+            }
+            opcode::FIND_BY_TYPE_VALUE_REQUEST => {
+                let handles = range(handle_at(0)?, handle_at(2)?)?;
+                let uuid = Uuid::Short(handle_at(4)?);
+                let value = &parameters[6..];
+                pdu.push(opcode::FIND_BY_TYPE_VALUE_RESPONSE).unwrap();
+                for handle in handles.clone() {
+                    let attribute = attribute(handle).unwrap();
+                    if attribute.uuid() != uuid || self.read(attribute, handle) != value {
+                        continue;
+                    }
+                    if pdu.len() + 4 > MTU {
+                        break;
+                    }
+                    let end = match attribute {
+                        PrimaryService(_) => group_end(handle),
+                        _ => handle,
+                    };
+                    pdu.extend_from_slice(&handle.to_le_bytes()).unwrap();
+                    pdu.extend_from_slice(&end.to_le_bytes()).unwrap();
+                }
+                if pdu.len() == 1 {
+                    return Err((*handles.start(), Error::AttributeNotFound));
+                }
+            }
+            opcode::READ_BY_TYPE_REQUEST | opcode::READ_BY_GROUP_TYPE_REQUEST => {
+                let handles = range(handle_at(0)?, handle_at(2)?)?;
+                let uuid = parse_uuid(&parameters[4..]).ok_or((0, Error::InvalidPdu))?;
+                let grouped = opcode == opcode::READ_BY_GROUP_TYPE_REQUEST;
+                if grouped && uuid != PRIMARY_SERVICE {
+                    return Err((*handles.start(), Error::UnsupportedGroupType));
+                }
+                let response = if grouped {
+                    opcode::READ_BY_GROUP_TYPE_RESPONSE
+                } else {
+                    opcode::READ_BY_TYPE_RESPONSE
+                };
+                pdu.extend_from_slice(&[response, 0]).unwrap();
+                let mut length = None;
+                for handle in handles.clone() {
+                    let attribute = attribute(handle).unwrap();
+                    if attribute.uuid() != uuid {
+                        continue;
+                    }
+                    let value = self.read(attribute, handle);
+                    let header = if grouped { 4 } else { 2 };
+                    // Every entry has to be as long as the first one, which
+                    // gets cut short if it doesn't fit
+                    let value = &value[..value.len().min(MTU - 2 - header)];
+                    if *length.get_or_insert(header + value.len()) != header + value.len()
+                        || pdu.len() + header + value.len() > MTU
+                    {
+                        break;
+                    }
+                    pdu.extend_from_slice(&handle.to_le_bytes()).unwrap();
+                    if grouped {
+                        pdu.extend_from_slice(&group_end(handle).to_le_bytes())
+                            .unwrap();
+                    }
+                    pdu.extend_from_slice(value).unwrap();
+                }
+                pdu[1] = length.ok_or((*handles.start(), Error::AttributeNotFound))? as u8;
+            }
+            opcode::READ_REQUEST | opcode::READ_BLOB_REQUEST => {
+                let handle = handle_at(0)?;
+                let (response, offset) = if opcode == opcode::READ_BLOB_REQUEST {
+                    (opcode::READ_BLOB_RESPONSE, usize::from(handle_at(2)?))
+                } else {
+                    (opcode::READ_RESPONSE, 0)
+                };
+                let attribute = attribute(handle).ok_or((handle, Error::InvalidHandle))?;
+                let value = self.read(attribute, handle);
+                let value = value.get(offset..).ok_or((handle, Error::InvalidOffset))?;
+                pdu.push(response).unwrap();
+                pdu.extend_from_slice(&value[..value.len().min(MTU - 1)])
+                    .unwrap();
+            }
+            opcode::WRITE_REQUEST => {
+                self.write(handle_at(0)?, &parameters[2..])?;
+                pdu.push(opcode::WRITE_RESPONSE).unwrap();
+            }
+            opcode::WRITE_COMMAND => {
+                self.write(handle_at(0)?, &parameters[2..])?;
+                return Ok(None);
+            }
+            _ if opcode & opcode::COMMAND_FLAG != 0 => return Ok(None),
+            // Confirmations only come for indications, which aren't sent
+            opcode::HANDLE_VALUE_CONFIRMATION => return Ok(None),
+            _ => return Err((0, Error::RequestNotSupported)),
+        }
+        Ok(Some(pdu))
+    }
+}
+
+/// The handles of a request that exist, or an error if the request's range
+/// isn't valid
+fn range(start: u16, end: u16) -> Result<core::ops::RangeInclusive<u16>, (u16, Error)> {
+    if start == 0 || start > end {
+        return Err((start, Error::InvalidHandle));
+    }
+    if start > LAST_HANDLE {
+        return Err((start, Error::AttributeNotFound));
+    }
+    Ok(start..=end.min(LAST_HANDLE))
+}
+
+fn parse_uuid(bytes: &[u8]) -> Option<Uuid> {
+    match bytes.len() {
+        2 => Some(Uuid::Short(u16::from_le_bytes(bytes.try_into().unwrap()))),
+        16 => Some(Uuid::Long(u128::from_le_bytes(bytes.try_into().unwrap()))),
+        _ => None,
+    }
+}
+
+/// The type of the attribute at `handle`, which has to exist
+fn attribute_uuid(handle: u16) -> Uuid {
+    attribute(handle).unwrap().uuid()
+}
+
+fn index(characteristic: Characteristic) -> usize {
+    Characteristic::ALL
+        .iter()
+        .position(|&other| other == characteristic)
+        .unwrap()
+}
+
+fn value_handle(characteristic: Characteristic) -> u16 {
+    let index = ATTRIBUTES
+        .iter()
+        .position(|&attribute| attribute == Measured(characteristic))
+        .unwrap();
+    index as u16 + 1
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Server, DEVICE_NAME};
+    use crate::logic::{
+        characteristic::{config, ConfigPart},
+        measurement::Measurement,
+        settings::Settings,
+    };
+
+    fn measurement() -> Measurement {
+        Measurement {
+            co2: Some(612.),
+            temperature: Some(21.37),
+            ..Default::default()
+        }
+    }
+
+    pub fn discovery() {
+        let mut server = Server::new(Settings::DEFAULT);
+        let mut request = |request: &[u8]| server.handle(request).unwrap();
+
+        assert_eq!(&request(&[0x02, 0x00, 0x02]), &[0x03, 23, 0]);
+        // Primary services
+        assert_eq!(
+            &request(&[0x10, 0x01, 0x00, 0xff, 0xff, 0x00, 0x28]),
+            &[0x11, 6, 1, 0, 5, 0, 0x00, 0x18, 6, 0, 15, 0, 0x1a, 0x18]
+        );
+        let custom = request(&[0x10, 16, 0x00, 0xff, 0xff, 0x00, 0x28]);
+        assert_eq!(&custom[..6], &[0x11, 20, 16, 0, 29, 0]);
+        assert_eq!(custom.len(), 22);
+        assert_eq!(
+            &request(&[0x10, 30, 0x00, 0xff, 0xff, 0x00, 0x28]),
+            &[0x01, 0x10, 30, 0, 0x0a]
+        );
+        assert_eq!(
+            &request(&[0x06, 0x01, 0x00, 0xff, 0xff, 0x00, 0x28, 0x1a, 0x18]),
+            &[0x07, 6, 0, 15, 0]
+        );
+        // Characteristics of the Environmental Sensing service
+        assert_eq!(
+            &request(&[0x08, 6, 0, 15, 0, 0x03, 0x28]),
+            &[
+                0x09, 7, 7, 0, 0x12, 8, 0, 0x6e, 0x2a, 10, 0, 0x12, 11, 0, 0x6f, 0x2a, 13, 0, 0x12,
+                14, 0, 0x6d, 0x2a
+            ]
+        );
+        // Descriptors of the temperature
+        assert_eq!(&request(&[0x04, 9, 0, 9, 0]), &[0x05, 1, 9, 0, 0x02, 0x29]);
+        assert_eq!(&request(&[0x04, 0, 0, 9, 0]), &[0x01, 0x04, 0, 0, 0x01]);
+        // Reads
+        assert_eq!(&request(&[0x0a, 3, 0])[1..], DEVICE_NAME.as_bytes());
+        assert_eq!(&request(&[0x0c, 3, 0, 4, 0]), &[0x0d, b'o', b'g']);
+        assert_eq!(&request(&[0x0a, 99, 0]), &[0x01, 0x0a, 99, 0, 0x01]);
+        assert_eq!(&request(&[0x0a, 8, 0]), &[0x0b, 0x00, 0x80]);
+        // Unknown requests get an error, unknown commands nothing
+        assert_eq!(&request(&[0x20]), &[0x01, 0x20, 0, 0, 0x06]);
+        assert_eq!(server.handle(&[0x60]), None);
+    }
+
+    pub fn notifications() {
+        let mut server = Server::new(Settings::DEFAULT);
+        assert!(server.update(&measurement()).is_empty());
+        // Turn them on for the CO2
+        assert_eq!(server.handle(&[0x12, 19, 0, 1, 0]).unwrap(), [0x13]);
+        assert_eq!(server.handle(&[0x0a, 19, 0]).unwrap(), [0x0b, 1, 0]);
+        assert_eq!(
+            server.handle(&[0x12, 19, 0, 1]).unwrap(),
+            [0x01, 0x12, 19, 0, 0x0d]
+        );
+        assert_eq!(server.handle(&[0x0a, 18, 0]).unwrap(), [0x0b, 0x64, 0x02]);
+
+        // Only changes get notified
+        assert!(server.update(&measurement()).is_empty());
+        let changed = Measurement {
+            co2: Some(700.),
+            temperature: Some(22.),
+            ..measurement()
+        };
+        let notifications = server.update(&changed);
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0], [0x1b, 18, 0, 0xbc, 0x02]);
+
+        server.disconnected();
+        assert!(server.update(&measurement()).is_empty());
+    }
+
+    pub fn configuration() {
+        let mut server = Server::new(Settings::DEFAULT);
+        let read = server.handle(&[0x0a, 27, 0]).unwrap();
+        assert_eq!(&read[1..], &config(&Settings::DEFAULT, ConfigPart::General));
+        // Measurements can't be written
+        assert_eq!(
+            server.handle(&[0x12, 18, 0, 0, 0]).unwrap(),
+            [0x01, 0x12, 18, 0, 0x03]
+        );
+
+        let settings = Settings {
+            altitude: 250,
+            ..Settings::DEFAULT
+        };
+        let mut request = heapless::Vec::<u8, 23>::from_slice(&[0x12, 27, 0]).unwrap();
+        request
+            .extend_from_slice(&config(&settings, ConfigPart::General))
+            .unwrap();
+        assert_eq!(server.handle(&request).unwrap(), [0x13]);
+        assert_eq!(server.take_written(), Some(settings));
+        assert_eq!(server.take_written(), None);
+        assert_eq!(
+            &server.handle(&[0x0a, 27, 0]).unwrap()[1..],
+            &config(&settings, ConfigPart::General)
+        );
+
+        // A critical CO2 level that the settings would replace
+        let last = request.len() - 1;
+        request[last] = 0xff;
+        request[last - 1] = 0xff;
+        assert_eq!(server.handle(&request).unwrap(), [0x01, 0x12, 27, 0, 0x13]);
+        assert_eq!(server.take_written(), None);
+
+        // The self-heating is in the other part, and writing it keeps the rest
+        let changed = Settings {
+            temperature_gain: 0.25,
+            ..settings
+        };
+        let mut request = heapless::Vec::<u8, 23>::from_slice(&[0x12, 29, 0]).unwrap();
+        request
+            .extend_from_slice(&config(&changed, ConfigPart::Temperature))
+            .unwrap();
+        assert_eq!(server.handle(&request).unwrap(), [0x13]);
+        assert_eq!(server.take_written(), Some(changed));
+    }
+}
@@ -0,0 +1,157 @@
+//! Rolling in-RAM history of a measured quantity
+
+/// Readings get averaged over buckets of this many seconds
+pub const BUCKET_SECONDS: u32 = 5 * 60;
+
+/// 24 hours worth of 5 minute buckets
+pub type DayHistory = History<288>;
+
+/// A ring buffer of bucket averages. Buckets during which no readings were
+/// added are kept as gaps, so that the time axis stays intact.
+pub struct History<const N: usize> {
+    // NAN marks a gap
+    buckets: [f32; N],
+    // Index of the oldest bucket
+    start: usize,
+    len: usize,
+    // The bucket that readings are currently added to
+    bucket_start: Option<u32>,
+    sum: f32,
+    count: u32,
+}
+
+impl<const N: usize> History<N> {
+    pub const fn new() -> Self {
+        History {
+            buckets: [f32::NAN; N],
+            start: 0,
+            len: 0,
+            bucket_start: None,
+            sum: 0.,
+            count: 0,
+        }
+    }
+
+    /// Add a reading taken at `now` seconds since boot
+    pub fn add(&mut self, now: u32, value: f32) {
+        let bucket_start = *self.bucket_start.get_or_insert(now);
+        // Wrapping, because the seconds counter is allowed to overflow
+        let elapsed = now.wrapping_sub(bucket_start);
+        if elapsed >= BUCKET_SECONDS {
+            let mean = if self.count > 0 {
+                self.sum / self.count as f32
+            } else {
+                f32::NAN
+            };
+            self.push(mean);
+            // Leave gaps for the buckets without any readings
+            let skipped = elapsed / BUCKET_SECONDS - 1;
+            for _ in 0..(skipped as usize).min(N) {
+                self.push(f32::NAN);
+            }
+            self.bucket_start =
+                Some(bucket_start.wrapping_add(elapsed / BUCKET_SECONDS * BUCKET_SECONDS));
+            self.sum = 0.;
+            self.count = 0;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn push(&mut self, value: f32) {
+        if self.len < N {
+            self.buckets[(self.start + self.len) % N] = value;
+            self.len += 1;
+        } else {
+            self.buckets[self.start] = value;
+            self.start = (self.start + 1) % N;
+        }
+    }
+
+    /// Number of completed buckets
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Average of the bucket that's still being filled
+    pub fn current(&self) -> Option<f32> {
+        if self.count > 0 {
+            Some(self.sum / self.count as f32)
+        } else {
+            None
+        }
+    }
+
+    /// The `count` most recent completed buckets, oldest first. If there are
+    /// fewer buckets than that, the missing ones at the start are `None`, same
+    /// as the gaps.
+    pub fn recent(&self, count: usize) -> impl Iterator<Item = Option<f32>> + '_ {
+        let count = count.min(N);
+        let missing = count.saturating_sub(self.len);
+        let skip = self.len.saturating_sub(count);
+        core::iter::repeat(None)
+            .take(missing)
+            .chain((skip..self.len).map(move |i| {
+                let value = self.buckets[(self.start + i) % N];
+                if value.is_nan() {
+                    None
+                } else {
+                    Some(value)
+                }
+            }))
+    }
+
+    /// Smallest and largest of the `count` most recent bucket averages
+    pub fn min_max(&self, count: usize) -> Option<(f32, f32)> {
+        self.recent(count)
+            .flatten()
+            .fold(None, |acc, value| match acc {
+                None => Some((value, value)),
+                Some((min, max)) => Some((min.min(value), max.max(value))),
+            })
+    }
+}
+
+impl<const N: usize> Default for History<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{History, BUCKET_SECONDS};
+
+    pub fn averages_buckets() {
+        let mut history: History<4> = History::new();
+        history.add(0, 400.);
+        history.add(100, 500.);
+        assert!(history.is_empty());
+        assert_eq!(history.current(), Some(450.));
+        history.add(BUCKET_SECONDS, 600.);
+        assert_eq!(history.len(), 1);
+        let values: heapless::Vec<Option<f32>, 4> = history.recent(2).collect();
+        assert_eq!(values.as_slice(), &[None, Some(450.)]);
+    }
+
+    pub fn keeps_gaps_and_wraps() {
+        let mut history: History<4> = History::new();
+        history.add(0, 1.);
+        history.add(BUCKET_SECONDS, 2.);
+        // Nothing gets added for two buckets
+        history.add(4 * BUCKET_SECONDS, 3.);
+        history.add(5 * BUCKET_SECONDS, 4.);
+        history.add(6 * BUCKET_SECONDS, 5.);
+        let values: heapless::Vec<Option<f32>, 4> = history.recent(4).collect();
+        assert_eq!(values.as_slice(), &[None, None, Some(3.), Some(4.)]);
+        assert_eq!(history.min_max(4), Some((3., 4.)));
+    }
+}
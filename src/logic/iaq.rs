@@ -0,0 +1,160 @@
+//! An overall indoor air quality verdict from CO2, VOC and PM2.5
+//!
+//! Every quantity gets a [`Category`] by comparing it against the limits of
+//! its [`Rule`], and the overall category is the worst of them.
+
+use crate::logic::measurement::{Measurement, Quantity};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub enum Category {
+    Excellent,
+    Good,
+    Moderate,
+    Poor,
+    Unhealthy,
+}
+
+impl Category {
+    const ALL: [Category; 5] = [
+        Category::Excellent,
+        Category::Good,
+        Category::Moderate,
+        Category::Poor,
+        Category::Unhealthy,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Excellent => "Excellent",
+            Category::Good => "Good",
+            Category::Moderate => "Moderate",
+            Category::Poor => "Poor",
+            Category::Unhealthy => "Unhealthy",
+        }
+    }
+
+    /// Green through red
+    pub fn color(&self) -> (u8, u8, u8) {
+        match self {
+            Category::Excellent => (0, 255, 0),
+            Category::Good => (128, 255, 0),
+            Category::Moderate => (255, 255, 0),
+            Category::Poor => (255, 128, 0),
+            Category::Unhealthy => (255, 0, 0),
+        }
+    }
+}
+
+/// Upper limits of the categories excellent, good, moderate and poor for
+/// `quantity`. Anything above the last limit is unhealthy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rule {
+    pub quantity: Quantity,
+    pub limits: [f32; 4],
+}
+
+impl Rule {
+    pub fn categorize(&self, value: f32) -> Category {
+        let exceeded = self.limits.iter().filter(|&&limit| value > limit).count();
+        Category::ALL[exceeded]
+    }
+}
+
+pub const DEFAULT_RULES: [Rule; 3] = [
+    // The limits of the categories I to III of DIN EN 16798-1 for an outdoor
+    // level of 400 ppm, the Pettenkofer limit of 1000 ppm falls into good
+    Rule {
+        quantity: Quantity::Co2,
+        limits: [950., 1200., 1750., 2500.],
+    },
+    // 100 is the average of the last 24 hours by definition of the index
+    Rule {
+        quantity: Quantity::VocIndex,
+        limits: [100., 150., 250., 400.],
+    },
+    // WHO annual and 24 hour guidelines, then the EPA daily standard
+    Rule {
+        quantity: Quantity::Pm2_5,
+        limits: [5., 15., 35., 55.],
+    },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Assessment {
+    pub category: Category,
+    /// The quantity that's in the worst category. The one with the earlier
+    /// rule wins ties.
+    pub worst: Quantity,
+}
+
+/// Assess the quantities of `measurement` that there are rules for. Gives
+/// `None` if none of them have been measured yet.
+pub fn assess(rules: &[Rule], measurement: &Measurement) -> Option<Assessment> {
+    rules
+        .iter()
+        .filter_map(|rule| {
+            let value = measurement.get(rule.quantity)?;
+            Some(Assessment {
+                category: rule.categorize(value),
+                worst: rule.quantity,
+            })
+        })
+        .fold(None, |worst: Option<Assessment>, assessment| match worst {
+            Some(worst) if worst.category >= assessment.category => Some(worst),
+            _ => Some(assessment),
+        })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{assess, Category, Rule, DEFAULT_RULES};
+    use crate::logic::measurement::{Measurement, Quantity};
+
+    pub fn categorize() {
+        let rule = DEFAULT_RULES[0];
+        assert_eq!(rule.categorize(420.), Category::Excellent);
+        assert_eq!(rule.categorize(950.), Category::Excellent);
+        assert_eq!(rule.categorize(1000.), Category::Good);
+        assert_eq!(rule.categorize(2000.), Category::Poor);
+        assert_eq!(rule.categorize(5000.), Category::Unhealthy);
+    }
+
+    pub fn worst_quantity_wins() {
+        let measurement = Measurement {
+            co2: Some(1300.),
+            voc_index: Some(300.),
+            pm2_5: Some(3.),
+            ..Default::default()
+        };
+        let assessment = assess(&DEFAULT_RULES, &measurement).unwrap();
+        assert_eq!(assessment.category, Category::Poor);
+        assert_eq!(assessment.worst, Quantity::VocIndex);
+
+        // Ties go to the first rule
+        let measurement = Measurement {
+            co2: Some(1300.),
+            voc_index: Some(200.),
+            ..Default::default()
+        };
+        let assessment = assess(&DEFAULT_RULES, &measurement).unwrap();
+        assert_eq!(assessment.category, Category::Moderate);
+        assert_eq!(assessment.worst, Quantity::Co2);
+
+        assert_eq!(assess(&DEFAULT_RULES, &Measurement::default()), None);
+    }
+
+    pub fn custom_rules() {
+        let rules = [Rule {
+            quantity: Quantity::Pm10,
+            limits: [10., 20., 30., 40.],
+        }];
+        let measurement = Measurement {
+            co2: Some(5000.),
+            pm10: Some(25.),
+            ..Default::default()
+        };
+        let assessment = assess(&rules, &measurement).unwrap();
+        assert_eq!(assessment.category, Category::Moderate);
+        assert_eq!(assessment.worst, Quantity::Pm10);
+    }
+}
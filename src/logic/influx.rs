@@ -0,0 +1,119 @@
+//! Measurements in the InfluxDB line protocol, one line per measurement
+//!
+//! See <https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/>.
+
+use crate::logic::{
+    csv::precision,
+    formatting::format_decimal,
+    measurement::{Measurement, Quantity},
+};
+
+/// What the points are filed under
+pub const MEASUREMENT: &str = "airlog";
+
+/// Long enough for a line with every value present and a few short tags
+pub type Line = heapless::String<512>;
+
+/// `measurement` as a point with `tags`, e.g. `[("device", id)]`, and a field
+/// for every value that's present, ending in a line break. The timestamp is
+/// Unix time, and goes out in nanoseconds, the protocol's default precision.
+/// Without one, the server stamps the point when it gets it. `None` if there
+/// are no values, because a point needs at least one field, or if the line
+/// doesn't fit.
+pub fn line(
+    tags: &[(&str, &str)],
+    timestamp: Option<u32>,
+    measurement: &Measurement,
+) -> Option<Line> {
+    let mut line = Line::new();
+    push_escaped(&mut line, MEASUREMENT, &[',', ' '])?;
+    for (key, value) in tags {
+        line.push(',').ok()?;
+        push_escaped(&mut line, key, &[',', '=', ' '])?;
+        line.push('=').ok()?;
+        push_escaped(&mut line, value, &[',', '=', ' '])?;
+    }
+    let mut separator = ' ';
+    for quantity in Quantity::ALL {
+        if let Some(value) = measurement.get(quantity) {
+            line.push(separator).ok()?;
+            line.push_str(quantity.key()).ok()?;
+            line.push('=').ok()?;
+            line.push_str(&format_decimal(value, precision(quantity)))
+                .ok()?;
+            separator = ',';
+        }
+    }
+    if separator == ' ' {
+        return None;
+    }
+    if let Some(timestamp) = timestamp {
+        ufmt::uwrite!(line, " {}000000000", timestamp).ok()?;
+    }
+    line.push('\n').ok()?;
+    Some(line)
+}
+
+/// With a backslash before each of the `special` characters
+fn push_escaped(line: &mut Line, text: &str, special: &[char]) -> Option<()> {
+    for c in text.chars() {
+        if special.contains(&c) {
+            line.push('\\').ok()?;
+        }
+        line.push(c).ok()?;
+    }
+    Some(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::line;
+    use crate::logic::measurement::{Measurement, Quantity};
+
+    pub fn lines() {
+        let measurement = Measurement {
+            co2: Some(612.4),
+            temperature: Some(21.374),
+            pm2_5: Some(3.14),
+            ..Default::default()
+        };
+        assert_eq!(
+            line(&[], Some(1_700_000_000), &measurement)
+                .unwrap()
+                .as_str(),
+            "airlog co2=612,temperature=21.37,pm2_5=3.1 1700000000000000000\n"
+        );
+        assert_eq!(
+            line(&[("device", "f1e2d3c4b5a69788")], None, &measurement)
+                .unwrap()
+                .as_str(),
+            "airlog,device=f1e2d3c4b5a69788 co2=612,temperature=21.37,pm2_5=3.1\n"
+        );
+        assert_eq!(line(&[], Some(0), &Measurement::default()), None);
+
+        let mut full = Measurement::default();
+        for quantity in Quantity::ALL {
+            full.set(quantity, Some(-99_999.));
+        }
+        assert!(line(&[("device", "f1e2d3c4b5a69788")], Some(u32::MAX), &full).is_some());
+        let long = [("room", "a very long name for a room"); 4];
+        assert_eq!(line(&long, Some(u32::MAX), &full), None);
+    }
+
+    pub fn escaping() {
+        let measurement = Measurement {
+            voc_index: Some(100.),
+            ..Default::default()
+        };
+        assert_eq!(
+            line(
+                &[("room name", "living room"), ("floor", "1,5"), ("a=b", "c")],
+                Some(1_700_000_000),
+                &measurement
+            )
+            .unwrap()
+            .as_str(),
+            "airlog,room\\ name=living\\ room,floor=1\\,5,a\\=b=c voc_index=100 1700000000000000000\n"
+        );
+    }
+}
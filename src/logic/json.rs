@@ -0,0 +1,60 @@
+//! Measurements as JSON objects, one per line
+
+use crate::logic::{
+    csv::precision,
+    formatting::format_decimal,
+    measurement::{Measurement, Quantity},
+    time::DateTime,
+};
+
+/// Long enough for an object with every value present
+pub type Line = heapless::String<512>;
+
+/// `measurement` taken at `timestamp`, Unix time, with the same keys and
+/// precision as the CSV columns, ending in a line break. Missing values are
+/// `null`.
+pub fn object(timestamp: u32, measurement: &Measurement) -> Line {
+    let mut line = Line::new();
+    line.push_str("{\"time\":\"").unwrap();
+    line.push_str(&DateTime::from_unix(timestamp).iso8601())
+        .unwrap();
+    ufmt::uwrite!(line, "\",\"timestamp\":{}", timestamp).unwrap();
+    for quantity in Quantity::ALL {
+        ufmt::uwrite!(line, ",\"{}\":", quantity.key()).unwrap();
+        match measurement.get(quantity) {
+            Some(value) => line
+                .push_str(&format_decimal(value, precision(quantity)))
+                .unwrap(),
+            None => line.push_str("null").unwrap(),
+        }
+    }
+    line.push_str("}\r\n").unwrap();
+    line
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::object;
+    use crate::logic::measurement::{Measurement, Quantity};
+
+    pub fn objects() {
+        let measurement = Measurement {
+            co2: Some(612.4),
+            temperature: Some(-3.5),
+            ..Default::default()
+        };
+        let line = object(1_700_000_000, &measurement);
+        assert!(line.starts_with(
+            "{\"time\":\"2023-11-14T22:13:20Z\",\"timestamp\":1700000000,\
+             \"co2\":612,\"temperature\":-3.50,\"rel_humidity\":null,"
+        ));
+        assert!(line.ends_with(",\"ambient_temperature\":null}\r\n"));
+
+        // Everything fits even with every value present
+        let mut full = Measurement::default();
+        for quantity in Quantity::ALL {
+            full.set(quantity, Some(-99_999.));
+        }
+        assert!(object(u32::MAX, &full).ends_with(":-99999.00}\r\n"));
+    }
+}
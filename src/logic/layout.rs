@@ -0,0 +1,609 @@
+//! Declarative description of what goes where on a character display
+//!
+//! A [`Page`] is a list of [`Field`]s. Pages get rendered into an in-memory
+//! [`FrameBuffer`] and a [`Screen`] keeps track of what's already on the
+//! display, so that only the characters that changed have to be sent to it.
+
+use micromath::F32Ext;
+
+use crate::logic::aqi::{Index, Scale};
+use crate::logic::barometer::{zambretti, Tendency};
+use crate::logic::charset::GlyphSet;
+use crate::logic::formatting::format_float_measurement_optional;
+use crate::logic::history::DayHistory;
+use crate::logic::iaq::Assessment;
+use crate::logic::measurement::{Measurement, Quantity};
+use crate::logic::psychro::mould_risk;
+use crate::logic::sparkline::sparkline;
+use crate::logic::stats::{Period, Statistic, Stats};
+use crate::logic::time::DateTime;
+use crate::logic::trend::Advice;
+
+/// Where pages get their values from
+pub trait Source {
+    fn reading(&self, quantity: Quantity) -> Option<f32>;
+
+    /// Recent history of `quantity`, if it's being kept
+    fn history(&self, _quantity: Quantity) -> Option<&DayHistory> {
+        None
+    }
+
+    /// Statistics of `quantity` over `period`, if they're being kept
+    fn stats(&self, _quantity: Quantity, _period: Period) -> Option<Stats> {
+        None
+    }
+
+    fn air_quality(&self, _scale: Scale) -> Option<Index> {
+        None
+    }
+
+    fn indoor_air_quality(&self) -> Option<Assessment> {
+        None
+    }
+
+    fn ventilation(&self) -> Option<Advice> {
+        None
+    }
+
+    fn pressure_tendency(&self) -> Option<Tendency> {
+        None
+    }
+
+    /// Unix time, if the clock has been set
+    fn time(&self) -> Option<u32> {
+        None
+    }
+}
+
+impl Source for Measurement {
+    fn reading(&self, quantity: Quantity) -> Option<f32> {
+        self.get(quantity)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Content {
+    /// A value from the measurement snapshot, formatted with `precision`
+    /// decimal places and followed by `unit`
+    Reading {
+        quantity: Quantity,
+        precision: u8,
+        unit: &'static str,
+    },
+    /// A statistic of `quantity` over `period`, formatted like a reading
+    Statistic {
+        quantity: Quantity,
+        period: Period,
+        statistic: Statistic,
+        precision: u8,
+        unit: &'static str,
+    },
+    /// The value of an air quality index
+    AirQualityIndex(Scale),
+    /// The short category name of an air quality index
+    AirQualityCategory(Scale),
+    /// The overall indoor air quality category
+    IndoorAirQuality,
+    /// The quantity that determined the indoor air quality category
+    WorstPollutant,
+    /// An arrow showing whether CO2 is rising, steady or falling
+    TrendArrow,
+    /// How fast CO2 is changing, ppm per minute
+    Co2Rate,
+    /// What to do about ventilation
+    VentilationAdvice,
+    /// Risk of mould growing on cold walls, from the ambient temperature and
+    /// humidity
+    MouldRisk,
+    /// Whether the pressure is rising or falling, and how fast
+    PressureTendency,
+    /// Zambretti forecast from the sea level pressure and its tendency
+    WeatherForecast,
+    /// The date in UTC, e.g. "2023-11-14"
+    Date,
+    /// The time of day in UTC, e.g. "22:13:20"
+    TimeOfDay,
+    /// Fixed text
+    Text(&'static str),
+    /// A bar graph of the last `span` history buckets of `quantity`, one
+    /// character per bar. Needs the [`GlyphSet::Bars`] glyphs on HD44780
+    /// displays.
+    Sparkline { quantity: Quantity, span: u16 },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Field {
+    pub col: u8,
+    pub row: u8,
+    /// Text that doesn't fit in this many characters gets cut off
+    pub width: u8,
+    pub align: Align,
+    /// Gets prepended to the content
+    pub label: &'static str,
+    pub content: Content,
+}
+
+impl Field {
+    /// A right aligned reading without a label
+    pub const fn reading(
+        col: u8,
+        row: u8,
+        width: u8,
+        quantity: Quantity,
+        precision: u8,
+        unit: &'static str,
+    ) -> Self {
+        Field {
+            col,
+            row,
+            width,
+            align: Align::Right,
+            label: "",
+            content: Content::Reading {
+                quantity,
+                precision,
+                unit,
+            },
+        }
+    }
+}
+
+pub struct Page {
+    pub name: &'static str,
+    pub fields: &'static [Field],
+}
+
+impl Page {
+    /// The first sparkline on the page, as `(field, quantity, span)`
+    pub fn sparkline(&self) -> Option<(&Field, Quantity, u16)> {
+        self.fields.iter().find_map(|field| match field.content {
+            Content::Sparkline { quantity, span } => Some((field, quantity, span)),
+            _ => None,
+        })
+    }
+
+    /// The custom glyphs that the page needs on HD44780 displays
+    pub fn glyph_set(&self) -> GlyphSet {
+        if self.sparkline().is_some() {
+            GlyphSet::Bars
+        } else {
+            GlyphSet::Symbols
+        }
+    }
+}
+
+/// The characters that should be on the display
+pub struct FrameBuffer<const COLS: usize, const ROWS: usize> {
+    cells: [[char; COLS]; ROWS],
+}
+
+/// Frame buffer for the common 16x2 displays
+pub type FrameBuffer1602 = FrameBuffer<16, 2>;
+/// Frame buffer for the common 20x4 displays
+pub type FrameBuffer2004 = FrameBuffer<20, 4>;
+
+impl<const COLS: usize, const ROWS: usize> FrameBuffer<COLS, ROWS> {
+    pub const fn new() -> Self {
+        FrameBuffer {
+            cells: [[' '; COLS]; ROWS],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cells = [[' '; COLS]; ROWS];
+    }
+
+    /// Write at most `width` characters of `text` starting from the given
+    /// position. Anything that falls outside of the frame is dropped.
+    pub fn write_str(&mut self, col: usize, row: usize, text: &str, width: usize) {
+        let Some(cells) = self.cells.get_mut(row) else {
+            return;
+        };
+        let end = COLS.min(col.saturating_add(width));
+        for (cell, c) in cells.iter_mut().take(end).skip(col).zip(text.chars()) {
+            *cell = c;
+        }
+    }
+
+    /// Replace everything on `row` with `text`, e.g. to show a banner over a
+    /// page
+    pub fn overwrite_row(&mut self, row: usize, text: &str) {
+        if let Some(cells) = self.cells.get_mut(row) {
+            *cells = [' '; COLS];
+        }
+        self.write_str(0, row, text, COLS);
+    }
+
+    pub fn get(&self, col: usize, row: usize) -> Option<char> {
+        self.cells
+            .get(row)
+            .and_then(|cells| cells.get(col))
+            .copied()
+    }
+
+    pub fn row(&self, row: usize) -> &[char] {
+        &self.cells[row]
+    }
+
+    pub fn render_page(&mut self, page: &Page, source: &impl Source) {
+        self.clear();
+        for field in page.fields {
+            self.render_field(field, source);
+        }
+    }
+
+    pub fn render_field(&mut self, field: &Field, source: &impl Source) {
+        // Room for a full row of 3 byte block elements
+        let mut text: heapless::String<96> = heapless::String::new();
+        // Overly long labels just get cut off
+        text.push_str(field.label).ok();
+        match field.content {
+            Content::Reading {
+                quantity,
+                precision,
+                unit,
+            } => {
+                // Pad to a single digit so that a missing value is shown as "-"
+                let value = source.reading(quantity);
+                let value_text = format_float_measurement_optional(value, 1, precision, unit);
+                text.push_str(&value_text).ok();
+            }
+            Content::Statistic {
+                quantity,
+                period,
+                statistic,
+                precision,
+                unit,
+            } => {
+                let value = source
+                    .stats(quantity, period)
+                    .and_then(|stats| stats.get(statistic));
+                let value_text = format_float_measurement_optional(value, 1, precision, unit);
+                text.push_str(&value_text).ok();
+            }
+            Content::AirQualityIndex(scale) => {
+                let value = source.air_quality(scale).map(|index| index.value as f32);
+                let value_text = format_float_measurement_optional(value, 1, 0, "");
+                text.push_str(&value_text).ok();
+            }
+            Content::AirQualityCategory(scale) => {
+                if let Some(index) = source.air_quality(scale) {
+                    text.push_str(index.category.short_name).ok();
+                }
+            }
+            Content::IndoorAirQuality => {
+                let name = source.indoor_air_quality().map(|iaq| iaq.category.name());
+                text.push_str(name.unwrap_or("-")).ok();
+            }
+            Content::WorstPollutant => {
+                let name = source.indoor_air_quality().map(|iaq| iaq.worst.name());
+                text.push_str(name.unwrap_or("-")).ok();
+            }
+            Content::TrendArrow => {
+                if let Some(advice) = source.ventilation() {
+                    text.push(advice.direction.arrow()).ok();
+                }
+            }
+            Content::Co2Rate => {
+                // The arrow shows the sign
+                let rate = source.ventilation().map(|advice| advice.slope.abs());
+                let rate_text = format_float_measurement_optional(rate, 1, 0, "/min");
+                text.push_str(&rate_text).ok();
+            }
+            Content::VentilationAdvice => {
+                if let Some(advice) = source.ventilation() {
+                    text.push_str(&advice.message()).ok();
+                }
+            }
+            Content::MouldRisk => {
+                let temperature = source
+                    .reading(Quantity::AmbientTemperature)
+                    .or_else(|| source.reading(Quantity::Temperature));
+                let risk = temperature
+                    .zip(source.reading(Quantity::RelHumidity))
+                    .map(|(temperature, rel_humidity)| mould_risk(temperature, rel_humidity));
+                text.push_str(risk.map_or("-", |risk| risk.name())).ok();
+            }
+            Content::PressureTendency => {
+                let name = source.pressure_tendency().map(|tendency| tendency.name());
+                text.push_str(name.unwrap_or("-")).ok();
+            }
+            Content::WeatherForecast => {
+                let forecast = source
+                    .reading(Quantity::SeaLevelPressure)
+                    .zip(source.pressure_tendency())
+                    .map(|(pressure, tendency)| zambretti(pressure, tendency.direction));
+                text.push_str(forecast.map_or("-", |forecast| forecast.short_text()))
+                    .ok();
+            }
+            Content::Date => {
+                let date = source.time().map(|time| DateTime::from_unix(time).date());
+                text.push_str(date.as_deref().unwrap_or("-")).ok();
+            }
+            Content::TimeOfDay => {
+                let time = source
+                    .time()
+                    .map(|time| DateTime::from_unix(time).time_of_day());
+                text.push_str(time.as_deref().unwrap_or("-")).ok();
+            }
+            Content::Text(content) => {
+                text.push_str(content).ok();
+            }
+            Content::Sparkline { quantity, span } => {
+                let width = (field.width as usize).saturating_sub(field.label.chars().count());
+                if let Some(history) = source.history(quantity) {
+                    let bars: heapless::String<64> = sparkline(history, span as usize, width);
+                    text.push_str(&bars).ok();
+                }
+            }
+        }
+
+        let width = field.width as usize;
+        let padding = width.saturating_sub(text.chars().count());
+        let col = match field.align {
+            Align::Left => field.col as usize,
+            Align::Right => field.col as usize + padding,
+        };
+        self.write_str(col, field.row as usize, &text, width - padding);
+    }
+}
+
+impl<const COLS: usize, const ROWS: usize> Default for FrameBuffer<COLS, ROWS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Keeps track of what's shown on a display
+pub struct Screen<const COLS: usize, const ROWS: usize> {
+    pub frame: FrameBuffer<COLS, ROWS>,
+    shown: FrameBuffer<COLS, ROWS>,
+    invalid: bool,
+}
+
+impl<const COLS: usize, const ROWS: usize> Screen<COLS, ROWS> {
+    /// We don't know what's on the display initially, so the first flush
+    /// sends everything
+    pub const fn new() -> Self {
+        Screen {
+            frame: FrameBuffer::new(),
+            shown: FrameBuffer::new(),
+            invalid: true,
+        }
+    }
+
+    /// Make the next flush send everything, e.g. after the display has been
+    /// cleared
+    pub fn invalidate(&mut self) {
+        self.invalid = true;
+    }
+
+    pub fn render_page(&mut self, page: &Page, source: &impl Source) {
+        self.frame.render_page(page, source);
+    }
+
+    /// Call `write(col, row, text)` for every run of characters that differs
+    /// from what's shown on the display
+    pub fn flush<E>(
+        &mut self,
+        mut write: impl FnMut(u8, u8, &str) -> Result<(), E>,
+    ) -> Result<(), E> {
+        for row in 0..ROWS {
+            let mut run: heapless::String<80> = heapless::String::new();
+            let mut run_start = 0;
+            for col in 0..COLS {
+                let c = self.frame.cells[row][col];
+                let changed = self.invalid || c != self.shown.cells[row][col];
+                if changed && run.is_empty() {
+                    run_start = col;
+                }
+                if changed && run.push(c).is_ok() {
+                    continue;
+                }
+                if !run.is_empty() {
+                    write(run_start as u8, row as u8, &run)?;
+                    run.clear();
+                }
+                // Only happens if a run doesn't fit in the string
+                if changed {
+                    run_start = col;
+                    run.push(c).ok();
+                }
+            }
+            if !run.is_empty() {
+                write(run_start as u8, row as u8, &run)?;
+            }
+        }
+        self.shown.cells = self.frame.cells;
+        self.invalid = false;
+        Ok(())
+    }
+}
+
+impl<const COLS: usize, const ROWS: usize> Default for Screen<COLS, ROWS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Align, Content, Field, FrameBuffer1602, Page, Screen, Source};
+    use crate::logic::charset::GlyphSet;
+    use crate::logic::history::{DayHistory, BUCKET_SECONDS};
+    use crate::logic::measurement::{Measurement, Quantity};
+
+    const PAGE: Page = Page {
+        name: "test",
+        fields: &[
+            Field::reading(0, 0, 8, Quantity::Co2, 0, "ppm"),
+            Field::reading(9, 0, 7, Quantity::VocIndex, 0, "voc"),
+            Field {
+                col: 0,
+                row: 1,
+                width: 16,
+                align: Align::Left,
+                label: "T ",
+                content: Content::Reading {
+                    quantity: Quantity::Temperature,
+                    precision: 1,
+                    unit: "°C",
+                },
+            },
+        ],
+    };
+
+    fn row_text(frame: &FrameBuffer1602, row: usize) -> heapless::String<64> {
+        frame.row(row).iter().collect()
+    }
+
+    pub fn render_page() {
+        let measurement = Measurement {
+            co2: Some(612.),
+            voc_index: Some(98.),
+            temperature: Some(21.54),
+            ..Default::default()
+        };
+        let mut frame = FrameBuffer1602::new();
+        frame.render_page(&PAGE, &measurement);
+        assert_eq!(row_text(&frame, 0).as_str(), " 612 ppm  98 voc");
+        assert_eq!(row_text(&frame, 1).as_str(), "T 21.5 °C       ");
+    }
+
+    pub fn render_missing_and_truncated() {
+        const NARROW_PAGE: Page = Page {
+            name: "narrow",
+            fields: &[
+                Field::reading(0, 0, 5, Quantity::Co2, 0, "ppm"),
+                Field::reading(6, 0, 5, Quantity::Pm10, 0, "ug"),
+            ],
+        };
+        let measurement = Measurement {
+            co2: Some(1234.),
+            ..Default::default()
+        };
+        let mut frame = FrameBuffer1602::new();
+        frame.render_page(&NARROW_PAGE, &measurement);
+        assert_eq!(row_text(&frame, 0).as_str(), "1234   - ug     ");
+    }
+
+    pub fn flush_only_changes() {
+        let mut measurement = Measurement {
+            co2: Some(612.),
+            voc_index: Some(98.),
+            temperature: Some(21.54),
+            ..Default::default()
+        };
+        let mut screen: Screen<16, 2> = Screen::new();
+        screen.render_page(&PAGE, &measurement);
+        let mut writes = 0;
+        screen
+            .flush(|_, _, text| {
+                writes += 1;
+                assert_eq!(text.chars().count(), 16);
+                Ok::<(), ()>(())
+            })
+            .unwrap();
+        assert_eq!(writes, 2);
+
+        measurement.co2 = Some(613.);
+        screen.render_page(&PAGE, &measurement);
+        let mut writes = 0;
+        screen
+            .flush(|col, row, text| {
+                writes += 1;
+                assert_eq!((col, row, text), (3, 0, "3"));
+                Ok::<(), ()>(())
+            })
+            .unwrap();
+        assert_eq!(writes, 1);
+    }
+
+    pub fn render_sparkline() {
+        struct WithHistory(DayHistory);
+
+        impl Source for WithHistory {
+            fn reading(&self, _quantity: Quantity) -> Option<f32> {
+                None
+            }
+
+            fn history(&self, quantity: Quantity) -> Option<&DayHistory> {
+                (quantity == Quantity::Co2).then_some(&self.0)
+            }
+        }
+
+        const HISTORY_PAGE: Page = Page {
+            name: "history",
+            fields: &[Field {
+                col: 0,
+                row: 1,
+                width: 16,
+                align: Align::Left,
+                label: "24h ",
+                content: Content::Sparkline {
+                    quantity: Quantity::Co2,
+                    span: 12,
+                },
+            }],
+        };
+        let mut source = WithHistory(DayHistory::new());
+        for (i, value) in [400., 500., 600., 700.].into_iter().enumerate() {
+            source.0.add(i as u32 * BUCKET_SECONDS, value);
+        }
+        source.0.add(4 * BUCKET_SECONDS, 0.);
+
+        assert_eq!(HISTORY_PAGE.glyph_set(), GlyphSet::Bars);
+        assert_eq!(PAGE.glyph_set(), GlyphSet::Symbols);
+        let mut frame = FrameBuffer1602::new();
+        frame.render_page(&HISTORY_PAGE, &source);
+        assert_eq!(row_text(&frame, 1).as_str(), "24h         ▁▃▆█");
+    }
+
+    pub fn render_time() {
+        struct WithTime(Option<u32>);
+
+        impl Source for WithTime {
+            fn reading(&self, _quantity: Quantity) -> Option<f32> {
+                None
+            }
+
+            fn time(&self) -> Option<u32> {
+                self.0
+            }
+        }
+
+        const TIME_PAGE: Page = Page {
+            name: "time",
+            fields: &[
+                Field {
+                    col: 0,
+                    row: 0,
+                    width: 16,
+                    align: Align::Left,
+                    label: "",
+                    content: Content::Date,
+                },
+                Field {
+                    col: 0,
+                    row: 1,
+                    width: 16,
+                    align: Align::Left,
+                    label: "",
+                    content: Content::TimeOfDay,
+                },
+            ],
+        };
+        let mut frame = FrameBuffer1602::new();
+        frame.render_page(&TIME_PAGE, &WithTime(Some(1_700_000_000)));
+        assert_eq!(row_text(&frame, 0).as_str(), "2023-11-14      ");
+        assert_eq!(row_text(&frame, 1).as_str(), "22:13:20        ");
+        frame.render_page(&TIME_PAGE, &WithTime(None));
+        assert_eq!(row_text(&frame, 0).as_str(), "-               ");
+    }
+}
@@ -0,0 +1,254 @@
+//! The Bluetooth LE link layer PDUs that set up and change a connection,
+//! taken apart without the radio
+//!
+//! Times are in µs. A central that asks for parameters that a connection
+//! can't be kept up with, e.g. fewer than two data channels, gets nothing
+//! parsed, so that the [link layer](crate::peripherals::ble) can refuse it
+//! rather than trip over it later.
+
+/// The shortest connection interval there is
+pub const MIN_INTERVAL: u32 = 7500;
+/// Data channels 0 to 36 in a channel map
+const DATA_CHANNELS: u64 = (1 << 37) - 1;
+/// Sleep clock accuracy of the central, ppm, by the SCA field of CONNECT_IND
+const CENTRAL_ACCURACY: [u32; 8] = [500, 250, 150, 100, 75, 50, 30, 20];
+
+/// The parameters of a CONNECT_IND
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct ConnectRequest {
+    /// The central's address and ours, least significant byte first
+    pub initiator: [u8; 6],
+    pub advertiser: [u8; 6],
+    pub access_address: u32,
+    pub crc_init: u32,
+    /// Of the transmit window that the first packet comes in, which starts
+    /// 1.25 ms plus the offset after the end of the CONNECT_IND
+    pub window_size: u32,
+    pub window_offset: u32,
+    pub interval: u32,
+    pub latency: u16,
+    /// Supervision timeout
+    pub timeout: u32,
+    /// Used data channels, bit n for channel n
+    pub channel_map: u64,
+    pub hop: u8,
+    /// ppm
+    pub central_accuracy: u32,
+}
+
+impl ConnectRequest {
+    pub const PAYLOAD_SIZE: usize = 34;
+
+    /// From the payload of a CONNECT_IND: InitA, AdvA, then the LLData of
+    /// AA 4, CRCInit 3, WinSize 1, WinOffset 2, Interval 2, Latency 2,
+    /// Timeout 2, ChM 5 and Hop and SCA 1 bytes, all little endian
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        let payload: &[u8; Self::PAYLOAD_SIZE] = payload.try_into().ok()?;
+        let data = &payload[12..];
+        let u16_at = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+        let request = ConnectRequest {
+            initiator: payload[0..6].try_into().unwrap(),
+            advertiser: payload[6..12].try_into().unwrap(),
+            access_address: u32::from_le_bytes(data[0..4].try_into().unwrap()),
+            crc_init: u32::from_le_bytes([data[4], data[5], data[6], 0]),
+            window_size: u32::from(data[7]) * 1250,
+            window_offset: u32::from(u16_at(8)) * 1250,
+            interval: u32::from(u16_at(10)) * 1250,
+            latency: u16_at(12),
+            timeout: u32::from(u16_at(14)) * 10_000,
+            channel_map: channel_map(&data[16..21]),
+            hop: data[21] & 0x1f,
+            central_accuracy: CENTRAL_ACCURACY[usize::from(data[21] >> 5)],
+        };
+        (request.interval >= MIN_INTERVAL
+            && is_usable(request.channel_map)
+            && (5..=16).contains(&request.hop))
+        .then_some(request)
+    }
+}
+
+/// The parameters of an LL_CONNECTION_UPDATE_IND, which take effect at the
+/// connection event with the `instant` as its counter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct ConnectionUpdate {
+    /// Of the transmit window, which starts the offset after where the
+    /// connection event before the instant would have been
+    pub window_size: u32,
+    pub window_offset: u32,
+    pub interval: u32,
+    pub latency: u16,
+    pub timeout: u32,
+    pub instant: u16,
+}
+
+impl ConnectionUpdate {
+    /// From the control PDU, opcode included
+    pub fn parse(pdu: &[u8]) -> Option<Self> {
+        let pdu: &[u8; 12] = pdu.try_into().ok()?;
+        let u16_at = |offset: usize| u16::from_le_bytes([pdu[offset], pdu[offset + 1]]);
+        let update = ConnectionUpdate {
+            window_size: u32::from(pdu[1]) * 1250,
+            window_offset: u32::from(u16_at(2)) * 1250,
+            interval: u32::from(u16_at(4)) * 1250,
+            latency: u16_at(6),
+            timeout: u32::from(u16_at(8)) * 10_000,
+            instant: u16_at(10),
+        };
+        (update.interval >= MIN_INTERVAL).then_some(update)
+    }
+}
+
+/// The parameters of an LL_CHANNEL_MAP_IND
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct ChannelMapUpdate {
+    pub channel_map: u64,
+    pub instant: u16,
+}
+
+impl ChannelMapUpdate {
+    /// From the control PDU, opcode included
+    pub fn parse(pdu: &[u8]) -> Option<Self> {
+        let pdu: &[u8; 8] = pdu.try_into().ok()?;
+        let update = ChannelMapUpdate {
+            channel_map: channel_map(&pdu[1..6]),
+            instant: u16::from_le_bytes([pdu[6], pdu[7]]),
+        };
+        is_usable(update.channel_map).then_some(update)
+    }
+}
+
+fn channel_map(bytes: &[u8]) -> u64 {
+    let mut map = [0; 8];
+    map[..5].copy_from_slice(bytes);
+    u64::from_le_bytes(map) & DATA_CHANNELS
+}
+
+/// Channel selection needs at least two channels to hop between
+fn is_usable(channel_map: u64) -> bool {
+    channel_map.count_ones() >= 2
+}
+
+/// Channel selection algorithm #1: the data channel for the unmapped
+/// channel, which has to be replaced if it isn't in `channel_map`. The map
+/// has to be one that parsing let through.
+pub fn data_channel(unmapped: u8, channel_map: u64) -> u8 {
+    if channel_map & 1 << unmapped != 0 {
+        return unmapped;
+    }
+    let index = unmapped % channel_map.count_ones() as u8;
+    (0..37)
+        .filter(|channel| channel_map & 1 << channel != 0)
+        .nth(usize::from(index))
+        .unwrap()
+}
+
+/// MHz above 2400
+pub fn frequency(data_channel: u8) -> u8 {
+    if data_channel <= 10 {
+        4 + 2 * data_channel
+    } else {
+        6 + 2 * data_channel
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{data_channel, frequency, ChannelMapUpdate, ConnectRequest, ConnectionUpdate};
+
+    /// A CONNECT_IND payload as it comes off the air, after the 2 byte
+    /// header
+    const CONNECT_IND: [u8; 34] = [
+        0x5a, 0x4b, 0x3c, 0x2d, 0x1e, 0x4f, // InitA
+        0x88, 0x97, 0xa6, 0xb5, 0xc4, 0xd3, // AdvA
+        0x1c, 0x4c, 0x65, 0x50, // AA
+        0x5a, 0x3b, 0x9d, // CRCInit
+        0x02, // WinSize
+        0x05, 0x00, // WinOffset
+        0x18, 0x00, // Interval
+        0x00, 0x00, // Latency
+        0xf4, 0x01, // Timeout
+        0xff, 0xff, 0x3f, 0xf0, 0x1f, // ChM, without channels 22 to 27
+        0xa7, // Hop 7, SCA 50 ppm
+    ];
+
+    pub fn connect_requests() {
+        assert_eq!(
+            ConnectRequest::parse(&CONNECT_IND),
+            Some(ConnectRequest {
+                initiator: [0x5a, 0x4b, 0x3c, 0x2d, 0x1e, 0x4f],
+                advertiser: [0x88, 0x97, 0xa6, 0xb5, 0xc4, 0xd3],
+                access_address: 0x5065_4c1c,
+                crc_init: 0x9d_3b5a,
+                window_size: 2500,
+                window_offset: 6250,
+                interval: 30_000,
+                latency: 0,
+                timeout: 5_000_000,
+                channel_map: 0x1f_f03f_ffff,
+                hop: 7,
+                central_accuracy: 50,
+            })
+        );
+
+        assert_eq!(ConnectRequest::parse(&CONNECT_IND[..33]), None);
+        let mut one_channel = CONNECT_IND;
+        one_channel[28..33].copy_from_slice(&[0x00, 0x00, 0x00, 0x10, 0x00]);
+        assert_eq!(ConnectRequest::parse(&one_channel), None);
+        let mut too_fast = CONNECT_IND;
+        too_fast[22] = 0x05;
+        assert_eq!(ConnectRequest::parse(&too_fast), None);
+        let mut no_hop = CONNECT_IND;
+        no_hop[33] = 0xa0;
+        assert_eq!(ConnectRequest::parse(&no_hop), None);
+    }
+
+    pub fn updates() {
+        let update = [
+            0x00, 0x01, 0x02, 0x00, 0x28, 0x00, 0x00, 0x00, 0x2c, 0x01, 0x34, 0x12,
+        ];
+        assert_eq!(
+            ConnectionUpdate::parse(&update),
+            Some(ConnectionUpdate {
+                window_size: 1250,
+                window_offset: 2500,
+                interval: 50_000,
+                latency: 0,
+                timeout: 3_000_000,
+                instant: 0x1234,
+            })
+        );
+        let mut stopped = update;
+        stopped[4] = 0;
+        assert_eq!(ConnectionUpdate::parse(&stopped), None);
+        assert_eq!(ConnectionUpdate::parse(&update[..11]), None);
+
+        assert_eq!(
+            ChannelMapUpdate::parse(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00]),
+            Some(ChannelMapUpdate {
+                channel_map: 0b11,
+                instant: 16,
+            })
+        );
+        assert_eq!(
+            ChannelMapUpdate::parse(&[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00]),
+            None
+        );
+        // Channels 37 to 39 aren't data channels
+        assert_eq!(
+            ChannelMapUpdate::parse(&[0x01, 0x00, 0x00, 0x00, 0x00, 0xe0, 0x10, 0x00]),
+            None
+        );
+    }
+
+    pub fn channels() {
+        let map = 0x1f_f03f_ffff;
+        assert_eq!(data_channel(7, map), 7);
+        // Channel 23 is unused, so it becomes the used one at 23 % 31 = 23
+        assert_eq!(data_channel(23, map), 29);
+        assert_eq!(data_channel(5, 0b1_0000_0001), 8);
+        assert_eq!(frequency(0), 4);
+        assert_eq!(frequency(10), 24);
+        assert_eq!(frequency(11), 28);
+        assert_eq!(frequency(36), 78);
+    }
+}
@@ -0,0 +1,485 @@
+//! Measurement log in a ring of flash pages
+//!
+//! Records are a fixed [`RECORD_SIZE`] bytes and get appended one after the
+//! other. When the log reaches a new page, the page gets erased first, which
+//! drops the oldest page's worth of records. Every record carries a sequence
+//! number and a CRC, so after a reboot the newest record is the valid one
+//! with the highest sequence number, and a record that was only half written
+//! when the power went out gets skipped. Pages get filled from their first
+//! slot on, so a page without a record in its first slot is empty.
+//!
+//! A record is laid out as
+//!
+//! | Bytes  | Contents                                           |
+//! |--------|----------------------------------------------------|
+//! | 0..4   | Sequence number                                    |
+//! | 4..8   | Timestamp, see [`EARLIEST`]                        |
+//! | 8      | Format version                                     |
+//! | 9      | Number of quantities that follow                   |
+//! | 10..   | The quantities in the order of [`Quantity::ALL`]   |
+//! | 60..64 | CRC-32 of bytes 0..60                              |
+//!
+//! with everything little endian. Each quantity is an `i16` in steps of its
+//! [`resolution`], with `i16::MIN` for a missing value. Quantities that get
+//! added later go to the end, and there's room for 25 of them.
+//!
+//! [`EARLIEST`]: crate::logic::time::EARLIEST
+
+use micromath::F32Ext;
+
+use crate::logic::{
+    flash::{crc32, Flash},
+    measurement::{Measurement, Quantity},
+};
+
+pub const RECORD_SIZE: usize = 64;
+const VERSION: u8 = 1;
+const VALUES_OFFSET: usize = 10;
+const CRC_OFFSET: usize = RECORD_SIZE - 4;
+const MAX_QUANTITIES: usize = (CRC_OFFSET - VALUES_OFFSET) / 2;
+const MISSING: i16 = i16::MIN;
+/// How many empty slots or pages [`Records`] goes past before it gives up
+/// for the time being
+const MAX_SKIP: usize = 64;
+
+/// The smallest step that a logged value of `quantity` can take
+pub fn resolution(quantity: Quantity) -> f32 {
+    match quantity {
+        Quantity::Co2 | Quantity::VocIndex => 1.,
+        Quantity::Pressure | Quantity::SeaLevelPressure => 10.,
+        Quantity::Pm1_0 | Quantity::Pm2_5 | Quantity::Pm4_0 | Quantity::Pm10 => 0.1,
+        _ => 0.01,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Record {
+    pub sequence: u32,
+    /// Seconds
+    pub timestamp: u32,
+    pub measurement: Measurement,
+}
+
+impl Record {
+    /// The record as it's stored in the flash
+    pub fn encode(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+        bytes[0..4].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[8] = VERSION;
+        bytes[9] = Quantity::ALL.len() as u8;
+        for (i, quantity) in Quantity::ALL.into_iter().enumerate() {
+            let value = match self.measurement.get(quantity) {
+                // The float to int cast saturates
+                Some(value) => ((value / resolution(quantity)).round() as i16).max(MISSING + 1),
+                None => MISSING,
+            };
+            let offset = VALUES_OFFSET + 2 * i;
+            bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+        }
+        let crc = crc32(&bytes[..CRC_OFFSET]);
+        bytes[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// `None` unless the record checks out
+    pub fn decode(bytes: &[u8; RECORD_SIZE]) -> Option<Record> {
+        let crc = u32::from_le_bytes(bytes[CRC_OFFSET..].try_into().unwrap());
+        if crc != crc32(&bytes[..CRC_OFFSET]) {
+            return None;
+        }
+        let count = (bytes[9] as usize).min(MAX_QUANTITIES);
+        let mut measurement = Measurement::default();
+        // Quantities that the record has but that aren't known here are left
+        // out, and the ones it doesn't have stay `None`
+        for (i, quantity) in Quantity::ALL.into_iter().enumerate().take(count) {
+            let offset = VALUES_OFFSET + 2 * i;
+            let value = i16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+            let value = (value != MISSING).then(|| value as f32 * resolution(quantity));
+            measurement.set(quantity, value);
+        }
+        Some(Record {
+            sequence: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            timestamp: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            measurement,
+        })
+    }
+}
+
+pub struct Logger<F> {
+    flash: F,
+    /// The slot that the next record goes to
+    next: usize,
+    sequence: u32,
+}
+
+impl<F: Flash> Logger<F> {
+    /// Keeps the log in all of `flash`, which has to be at least two pages,
+    /// so that erasing a page to make room leaves some records behind
+    pub fn new(flash: F) -> Self {
+        assert!(F::PAGE_SIZE % RECORD_SIZE == 0 && RECORD_SIZE % F::WORD_SIZE == 0);
+        assert!(flash.capacity() / F::PAGE_SIZE >= 2);
+        Logger {
+            flash,
+            next: 0,
+            sequence: 0,
+        }
+    }
+
+    /// How many records the log holds before it starts dropping old ones,
+    /// give or take a page
+    pub fn capacity(&self) -> usize {
+        self.flash.capacity() / RECORD_SIZE
+    }
+
+    fn slots_per_page(&self) -> usize {
+        F::PAGE_SIZE / RECORD_SIZE
+    }
+
+    fn read_slot(&mut self, slot: usize) -> Result<[u8; RECORD_SIZE], F::Error> {
+        let mut bytes = [0; RECORD_SIZE];
+        self.flash.read((slot * RECORD_SIZE) as u32, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// The slot and the sequence number of the newest record in `slots`
+    fn newest(
+        &mut self,
+        slots: impl Iterator<Item = usize>,
+    ) -> Result<Option<(usize, u32)>, F::Error> {
+        let mut newest: Option<(usize, u32)> = None;
+        for slot in slots {
+            let Some(record) = Record::decode(&self.read_slot(slot)?) else {
+                continue;
+            };
+            if newest.map_or(true, |(_, sequence)| record.sequence > sequence) {
+                newest = Some((slot, record.sequence));
+            }
+        }
+        Ok(newest)
+    }
+
+    /// Find where the log left off, has to come before appending to a log
+    /// that's already in the flash
+    pub fn mount(&mut self) -> Result<(), F::Error> {
+        // The newest page is the one that starts with the newest record, only
+        // that one has to be gone through slot by slot
+        let slots_per_page = self.slots_per_page();
+        let pages = self.capacity() / slots_per_page;
+        let newest = match self.newest((0..pages).map(|page| page * slots_per_page))? {
+            Some((first, _)) => self.newest(first..first + slots_per_page)?,
+            None => None,
+        };
+        (self.next, self.sequence) = match newest {
+            Some((slot, sequence)) => ((slot + 1) % self.capacity(), sequence.wrapping_add(1)),
+            None => (0, 0),
+        };
+        Ok(())
+    }
+
+    /// Log `measurement`, taken at `timestamp` seconds
+    pub fn append(&mut self, timestamp: u32, measurement: &Measurement) -> Result<(), F::Error> {
+        // Skip over slots that have something in them, e.g. a half written
+        // record, unless it's time to erase them anyway
+        let mut slot = self.next;
+        while slot % self.slots_per_page() != 0 && self.read_slot(slot)? != [0xff; RECORD_SIZE] {
+            slot = (slot + 1) % self.capacity();
+        }
+        if slot % self.slots_per_page() == 0 {
+            self.flash.erase_page((slot * RECORD_SIZE) as u32)?;
+        }
+
+        let record = Record {
+            sequence: self.sequence,
+            timestamp,
+            measurement: *measurement,
+        };
+        self.flash
+            .write((slot * RECORD_SIZE) as u32, &record.encode())?;
+        self.next = (slot + 1) % self.capacity();
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(())
+    }
+
+    /// The logged records, oldest first
+    pub fn records(&mut self) -> Records<'_, F> {
+        self.records_from(self.start())
+    }
+
+    /// Where [`Logger::records`] starts
+    pub fn start(&self) -> Position {
+        Position {
+            slot: self.next,
+            remaining: self.capacity(),
+        }
+    }
+
+    /// The records from where an earlier read got to, see
+    /// [`Records::position`]. Appending in the meantime might have dropped
+    /// some of them.
+    pub fn records_from(&mut self, position: Position) -> Records<'_, F> {
+        Records {
+            logger: self,
+            slot: position.slot,
+            remaining: position.remaining,
+        }
+    }
+
+    /// How many records the log holds, which means reading all of it
+    pub fn count(&mut self) -> Result<usize, F::Error> {
+        let mut records = self.records();
+        let mut count = 0;
+        while !records.is_done() {
+            if records.next().transpose()?.is_some() {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Throw away all of the records
+    pub fn clear(&mut self) -> Result<(), F::Error> {
+        for page in 0..self.flash.capacity() / F::PAGE_SIZE {
+            self.flash.erase_page((page * F::PAGE_SIZE) as u32)?;
+        }
+        self.next = 0;
+        Ok(())
+    }
+
+    pub fn release(self) -> F {
+        self.flash
+    }
+}
+
+/// How far a read of the log has got, so that it can be carried on later,
+/// e.g. to send a long log a bit at a time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    slot: usize,
+    remaining: usize,
+}
+
+/// Goes around the ring once, starting right after the newest record. It
+/// ends early rather than going through more than [`MAX_SKIP`] empty slots
+/// or pages at a time, see [`Records::is_done`].
+pub struct Records<'a, F> {
+    logger: &'a mut Logger<F>,
+    slot: usize,
+    remaining: usize,
+}
+
+impl<'a, F: Flash> Records<'a, F> {
+    /// Where the next record comes from
+    pub fn position(&self) -> Position {
+        Position {
+            slot: self.slot,
+            remaining: self.remaining,
+        }
+    }
+
+    /// Whether it went all the way around. If it ended before that, there
+    /// was too much empty flash in a row and it can carry on from its
+    /// [`Records::position`].
+    pub fn is_done(&self) -> bool {
+        self.remaining == 0
+    }
+
+    fn advance(&mut self, slots: usize) {
+        let slots = slots.min(self.remaining);
+        self.slot = (self.slot + slots) % self.logger.capacity();
+        self.remaining -= slots;
+    }
+}
+
+impl<'a, F: Flash> Iterator for Records<'a, F> {
+    type Item = Result<Record, F::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for _ in 0..MAX_SKIP {
+            if self.is_done() {
+                break;
+            }
+            let slot = self.slot;
+            self.advance(1);
+            match self.logger.read_slot(slot) {
+                Ok(bytes) => {
+                    if let Some(record) = Record::decode(&bytes) {
+                        return Some(Ok(record));
+                    }
+                }
+                Err(error) => return Some(Err(error)),
+            }
+            if slot % self.logger.slots_per_page() == 0 {
+                self.advance(self.logger.slots_per_page() - 1);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use micromath::F32Ext;
+
+    use super::{Logger, Record, RECORD_SIZE};
+    use crate::logic::{
+        flash::MemoryFlash,
+        measurement::{Measurement, Quantity},
+    };
+
+    fn measurement(co2: f32) -> Measurement {
+        Measurement {
+            co2: Some(co2),
+            temperature: Some(21.37),
+            pressure: Some(101_325.),
+            pm2_5: Some(3.14),
+            ..Default::default()
+        }
+    }
+
+    pub fn encoding() {
+        let record = Record {
+            sequence: 7,
+            timestamp: 1_700_000_000,
+            measurement: measurement(612.4),
+        };
+        let decoded = Record::decode(&record.encode()).unwrap();
+        assert_eq!(decoded.sequence, 7);
+        assert_eq!(decoded.timestamp, 1_700_000_000);
+        let expected = [
+            (Quantity::Co2, Some(612.)),
+            (Quantity::Temperature, Some(21.37)),
+            (Quantity::Pressure, Some(101_330.)),
+            (Quantity::Pm2_5, Some(3.1)),
+            (Quantity::VocIndex, None),
+        ];
+        for (quantity, value) in expected {
+            let decoded = decoded.measurement.get(quantity);
+            match (decoded, value) {
+                (Some(decoded), Some(value)) => {
+                    assert!((decoded - value).abs() < 0.01, "{:?}", quantity)
+                }
+                _ => assert_eq!(decoded, value),
+            }
+        }
+
+        let mut bytes = record.encode();
+        bytes[12] ^= 1;
+        assert_eq!(Record::decode(&bytes), None);
+    }
+
+    pub fn append_and_read_back() {
+        let mut logger = Logger::new(MemoryFlash::<2, 256>::new());
+        logger.mount().unwrap();
+        assert_eq!(logger.records().count(), 0);
+        for i in 0..3 {
+            logger
+                .append(i * 60, &measurement(400. + i as f32))
+                .unwrap();
+        }
+
+        // Carries on after a reboot
+        let mut logger = Logger::new(logger.release());
+        logger.mount().unwrap();
+        logger.append(180, &measurement(403.)).unwrap();
+        let timestamps: heapless::Vec<u32, 8> = logger
+            .records()
+            .map(|record| record.unwrap().timestamp)
+            .collect();
+        assert_eq!(timestamps, [0, 60, 120, 180]);
+    }
+
+    pub fn resumes() {
+        let mut logger = Logger::new(MemoryFlash::<3, 256>::new());
+        logger.mount().unwrap();
+        // Starting the page with 12 and 13 in it dropped 0 to 3
+        for i in 0..14 {
+            logger.append(i, &measurement(400.)).unwrap();
+        }
+        let mut records = logger.records();
+        let first: heapless::Vec<u32, 4> = records
+            .by_ref()
+            .take(4)
+            .map(|record| record.unwrap().timestamp)
+            .collect();
+        assert_eq!(first, [4, 5, 6, 7]);
+        let position = records.position();
+
+        // What's appended goes behind the position
+        logger.append(14, &measurement(400.)).unwrap();
+        let rest: heapless::Vec<u32, 12> = logger
+            .records_from(position)
+            .map(|record| record.unwrap().timestamp)
+            .collect();
+        assert_eq!(rest, [8, 9, 10, 11, 12, 13]);
+        assert_eq!(logger.records_from(logger.start()).count(), 11);
+    }
+
+    pub fn empty_stretches() {
+        // Two records to a page
+        let mut logger = Logger::new(MemoryFlash::<70, 128>::new());
+        logger.mount().unwrap();
+        logger.append(0, &measurement(400.)).unwrap();
+        logger.append(1, &measurement(400.)).unwrap();
+
+        // Only the first slots and then the newest page get read
+        let flash = logger.release();
+        let reads = flash.reads();
+        let mut logger = Logger::new(flash);
+        logger.mount().unwrap();
+        let flash = logger.release();
+        assert_eq!(flash.reads() - reads, 70 + 2);
+
+        // The 69 empty pages after the records are too many to skip at once
+        let mut logger = Logger::new(flash);
+        logger.mount().unwrap();
+        let mut records = logger.records();
+        assert!(records.next().is_none());
+        assert!(!records.is_done());
+        let position = records.position();
+        let timestamps: heapless::Vec<u32, 2> = logger
+            .records_from(position)
+            .map(|record| record.unwrap().timestamp)
+            .collect();
+        assert_eq!(timestamps, [0, 1]);
+        assert_eq!(logger.count().unwrap(), 2);
+    }
+
+    pub fn wraps_around() {
+        let mut logger = Logger::new(MemoryFlash::<3, 256>::new());
+        logger.mount().unwrap();
+        assert_eq!(logger.capacity(), 12);
+        for i in 0..30 {
+            logger.append(i, &measurement(400.)).unwrap();
+        }
+        // Starting the page with 28 and 29 in it dropped 16 to 19
+        let timestamps: heapless::Vec<u32, 12> = logger
+            .records()
+            .map(|record| record.unwrap().timestamp)
+            .collect();
+        assert_eq!(timestamps, [20, 21, 22, 23, 24, 25, 26, 27, 28, 29]);
+        assert_eq!(logger.release().erases(), &[3, 3, 2]);
+    }
+
+    pub fn power_loss() {
+        let mut logger = Logger::new(MemoryFlash::<2, 256>::new());
+        logger.mount().unwrap();
+        logger.append(0, &measurement(400.)).unwrap();
+        let mut flash = logger.release();
+        flash.cut_power_after(RECORD_SIZE / 2);
+        let mut logger = Logger::new(flash);
+        logger.mount().unwrap();
+        logger.append(1, &measurement(400.)).unwrap();
+
+        let mut flash = logger.release();
+        flash.restore_power();
+        let mut logger = Logger::new(flash);
+        logger.mount().unwrap();
+        logger.append(2, &measurement(400.)).unwrap();
+        let timestamps: heapless::Vec<u32, 8> = logger
+            .records()
+            .map(|record| record.unwrap().timestamp)
+            .collect();
+        assert_eq!(timestamps, [0, 2]);
+    }
+}
@@ -0,0 +1,177 @@
+/// A snapshot of everything the device measures. A field stays `None` until the
+/// corresponding sensor has produced a reading (or if the sensor is missing).
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    /// CO2 concentration from the SCD30, ppm
+    pub co2: Option<f32>,
+    /// Temperature from the SCD30, °C
+    pub temperature: Option<f32>,
+    /// Relative humidity from the SCD30, brought to the ambient temperature
+    /// if there is one, %
+    pub rel_humidity: Option<f32>,
+    /// Sensirion VOC index from the SGP40, 0 to 500
+    pub voc_index: Option<f32>,
+    /// Atmospheric pressure from the BMP388, Pa
+    pub pressure: Option<f32>,
+    /// Temperature from the BMP388, °C
+    pub pressure_sensor_temperature: Option<f32>,
+    /// Temperature of the nrf52840 die, °C
+    pub builtin_temperature: Option<f32>,
+    /// PM1.0 mass concentration from the SPS30, µg/m³
+    pub pm1_0: Option<f32>,
+    /// PM2.5 mass concentration from the SPS30, µg/m³
+    pub pm2_5: Option<f32>,
+    /// PM4.0 mass concentration from the SPS30, µg/m³
+    pub pm4_0: Option<f32>,
+    /// PM10 mass concentration from the SPS30, µg/m³
+    pub pm10: Option<f32>,
+    /// Dew point derived from the ambient temperature and corrected humidity, °C
+    pub dew_point: Option<f32>,
+    /// Absolute humidity derived from the ambient temperature and corrected humidity, g/m³
+    pub absolute_humidity: Option<f32>,
+    /// NWS heat index derived from the ambient temperature and corrected humidity, °C
+    pub heat_index: Option<f32>,
+    /// Humidex derived from the ambient temperature and corrected humidity
+    pub humidex: Option<f32>,
+    /// BMP388 pressure reduced to sea level, Pa
+    pub sea_level_pressure: Option<f32>,
+    /// Room temperature, corrected for the self-heating of the enclosure, °C
+    pub ambient_temperature: Option<f32>,
+}
+
+/// Names a single value in a [`Measurement`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Quantity {
+    Co2,
+    Temperature,
+    RelHumidity,
+    VocIndex,
+    Pressure,
+    PressureSensorTemperature,
+    BuiltinTemperature,
+    Pm1_0,
+    Pm2_5,
+    Pm4_0,
+    Pm10,
+    DewPoint,
+    AbsoluteHumidity,
+    HeatIndex,
+    Humidex,
+    SeaLevelPressure,
+    AmbientTemperature,
+}
+
+impl Quantity {
+    pub const ALL: [Quantity; 17] = [
+        Quantity::Co2,
+        Quantity::Temperature,
+        Quantity::RelHumidity,
+        Quantity::VocIndex,
+        Quantity::Pressure,
+        Quantity::PressureSensorTemperature,
+        Quantity::BuiltinTemperature,
+        Quantity::Pm1_0,
+        Quantity::Pm2_5,
+        Quantity::Pm4_0,
+        Quantity::Pm10,
+        Quantity::DewPoint,
+        Quantity::AbsoluteHumidity,
+        Quantity::HeatIndex,
+        Quantity::Humidex,
+        Quantity::SeaLevelPressure,
+        Quantity::AmbientTemperature,
+    ];
+
+    /// Short name for displays
+    pub fn name(&self) -> &'static str {
+        match self {
+            Quantity::Co2 => "CO2",
+            Quantity::Temperature => "Temp",
+            Quantity::RelHumidity => "RH",
+            Quantity::VocIndex => "VOC",
+            Quantity::Pressure => "Pressure",
+            Quantity::PressureSensorTemperature => "BMP temp",
+            Quantity::BuiltinTemperature => "CPU temp",
+            Quantity::Pm1_0 => "PM1.0",
+            Quantity::Pm2_5 => "PM2.5",
+            Quantity::Pm4_0 => "PM4.0",
+            Quantity::Pm10 => "PM10",
+            Quantity::DewPoint => "Dew pt",
+            Quantity::AbsoluteHumidity => "Abs hum",
+            Quantity::HeatIndex => "HI",
+            Quantity::Humidex => "Humidex",
+            Quantity::SeaLevelPressure => "Sea lvl",
+            Quantity::AmbientTemperature => "Ambient",
+        }
+    }
+
+    /// Identifier for machine readable output, e.g. column names
+    pub fn key(&self) -> &'static str {
+        match self {
+            Quantity::Co2 => "co2",
+            Quantity::Temperature => "temperature",
+            Quantity::RelHumidity => "rel_humidity",
+            Quantity::VocIndex => "voc_index",
+            Quantity::Pressure => "pressure",
+            Quantity::PressureSensorTemperature => "pressure_sensor_temperature",
+            Quantity::BuiltinTemperature => "builtin_temperature",
+            Quantity::Pm1_0 => "pm1_0",
+            Quantity::Pm2_5 => "pm2_5",
+            Quantity::Pm4_0 => "pm4_0",
+            Quantity::Pm10 => "pm10",
+            Quantity::DewPoint => "dew_point",
+            Quantity::AbsoluteHumidity => "absolute_humidity",
+            Quantity::HeatIndex => "heat_index",
+            Quantity::Humidex => "humidex",
+            Quantity::SeaLevelPressure => "sea_level_pressure",
+            Quantity::AmbientTemperature => "ambient_temperature",
+        }
+    }
+}
+
+impl Measurement {
+    pub fn get(&self, quantity: Quantity) -> Option<f32> {
+        match quantity {
+            Quantity::Co2 => self.co2,
+            Quantity::Temperature => self.temperature,
+            Quantity::RelHumidity => self.rel_humidity,
+            Quantity::VocIndex => self.voc_index,
+            Quantity::Pressure => self.pressure,
+            Quantity::PressureSensorTemperature => self.pressure_sensor_temperature,
+            Quantity::BuiltinTemperature => self.builtin_temperature,
+            Quantity::Pm1_0 => self.pm1_0,
+            Quantity::Pm2_5 => self.pm2_5,
+            Quantity::Pm4_0 => self.pm4_0,
+            Quantity::Pm10 => self.pm10,
+            Quantity::DewPoint => self.dew_point,
+            Quantity::AbsoluteHumidity => self.absolute_humidity,
+            Quantity::HeatIndex => self.heat_index,
+            Quantity::Humidex => self.humidex,
+            Quantity::SeaLevelPressure => self.sea_level_pressure,
+            Quantity::AmbientTemperature => self.ambient_temperature,
+        }
+    }
+
+    pub fn set(&mut self, quantity: Quantity, value: Option<f32>) {
+        let field = match quantity {
+            Quantity::Co2 => &mut self.co2,
+            Quantity::Temperature => &mut self.temperature,
+            Quantity::RelHumidity => &mut self.rel_humidity,
+            Quantity::VocIndex => &mut self.voc_index,
+            Quantity::Pressure => &mut self.pressure,
+            Quantity::PressureSensorTemperature => &mut self.pressure_sensor_temperature,
+            Quantity::BuiltinTemperature => &mut self.builtin_temperature,
+            Quantity::Pm1_0 => &mut self.pm1_0,
+            Quantity::Pm2_5 => &mut self.pm2_5,
+            Quantity::Pm4_0 => &mut self.pm4_0,
+            Quantity::Pm10 => &mut self.pm10,
+            Quantity::DewPoint => &mut self.dew_point,
+            Quantity::AbsoluteHumidity => &mut self.absolute_humidity,
+            Quantity::HeatIndex => &mut self.heat_index,
+            Quantity::Humidex => &mut self.humidex,
+            Quantity::SeaLevelPressure => &mut self.sea_level_pressure,
+            Quantity::AmbientTemperature => &mut self.ambient_temperature,
+        };
+        *field = value;
+    }
+}
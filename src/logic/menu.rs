@@ -0,0 +1,192 @@
+//! A menu that's driven by a single button
+//!
+//! A long press opens the menu. Short presses step through the items and a
+//! long press picks one.
+
+use crate::logic::calibration::{Calibration, State};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Item {
+    Calibrate,
+    ResetStats,
+    Exit,
+}
+
+impl Item {
+    const ALL: [Item; 3] = [Item::Calibrate, Item::ResetStats, Item::Exit];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Item::Calibrate => "Calibrate CO2",
+            Item::ResetStats => "Reset stats",
+            Item::Exit => "Exit",
+        }
+    }
+}
+
+/// What the firmware has to do as a result of a press or a reading
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub enum Action {
+    ResetStats,
+    /// Force the CO2 sensor's calibration to `reference` ppm. Its readings
+    /// were `correction` ppm off.
+    Recalibrate {
+        reference: u16,
+        correction: f32,
+    },
+}
+
+enum Screen {
+    Closed,
+    /// Showing the item at the given index
+    Open(usize),
+    /// Going through the CO2 calibration
+    Calibrating,
+}
+
+pub struct Menu {
+    screen: Screen,
+    /// Kept outside of [`Screen`], which would otherwise be mostly padding
+    calibration: Calibration,
+}
+
+impl Menu {
+    pub const fn new() -> Self {
+        Menu {
+            screen: Screen::Closed,
+            calibration: Calibration::new(),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        !matches!(self.screen, Screen::Closed)
+    }
+
+    /// A long press while closed opens the menu, short presses are left to
+    /// the caller
+    pub fn press(&mut self, long: bool) -> Option<Action> {
+        match &mut self.screen {
+            Screen::Closed => {
+                if long {
+                    self.screen = Screen::Open(0);
+                }
+                None
+            }
+            Screen::Open(i) if !long => {
+                *i = (*i + 1) % Item::ALL.len();
+                None
+            }
+            Screen::Open(i) => match Item::ALL[*i] {
+                Item::Calibrate => {
+                    self.screen = Screen::Calibrating;
+                    self.calibration = Calibration::new();
+                    None
+                }
+                Item::ResetStats => {
+                    self.screen = Screen::Closed;
+                    Some(Action::ResetStats)
+                }
+                Item::Exit => {
+                    self.screen = Screen::Closed;
+                    None
+                }
+            },
+            Screen::Calibrating => {
+                match (self.calibration.state(), long) {
+                    (State::Reference(_), false) => self.calibration.next_reference(),
+                    (State::Reference(_), true) => self.calibration.confirm(),
+                    // Cancel while settling, dismiss the result otherwise
+                    _ => self.screen = Screen::Closed,
+                }
+                None
+            }
+        }
+    }
+
+    /// Feed in a CO2 reading taken at `now` seconds since boot
+    pub fn update(&mut self, now: u32, co2: f32) -> Option<Action> {
+        if !matches!(self.screen, Screen::Calibrating) {
+            return None;
+        }
+        let reference = self.calibration.update(now, co2)?;
+        match self.calibration.state() {
+            State::Done { correction, .. } => Some(Action::Recalibrate {
+                reference,
+                correction,
+            }),
+            _ => None,
+        }
+    }
+
+    /// What to show on a 16x2 display while the menu is open
+    pub fn lines(&self) -> Option<[heapless::String<16>; 2]> {
+        match self.screen {
+            Screen::Closed => None,
+            Screen::Open(i) => {
+                let mut lines = [heapless::String::new(), heapless::String::new()];
+                lines[0].push_str(Item::ALL[i].name()).ok();
+                lines[1].push_str("Press:>  Hold:OK").ok();
+                Some(lines)
+            }
+            Screen::Calibrating => Some(self.calibration.lines()),
+        }
+    }
+}
+
+impl Default for Menu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Action, Menu, Screen};
+    use crate::logic::calibration::State;
+
+    pub fn navigate() {
+        let mut menu = Menu::new();
+        assert_eq!(menu.press(false), None);
+        assert!(!menu.is_open());
+        assert_eq!(menu.press(true), None);
+        assert_eq!(menu.lines().unwrap()[0].as_str(), "Calibrate CO2");
+        menu.press(false);
+        assert_eq!(menu.lines().unwrap()[0].as_str(), "Reset stats");
+        assert_eq!(menu.press(true), Some(Action::ResetStats));
+        assert!(!menu.is_open());
+
+        menu.press(true);
+        menu.press(false);
+        menu.press(false);
+        assert_eq!(menu.lines().unwrap()[0].as_str(), "Exit");
+        assert_eq!(menu.press(true), None);
+        assert!(!menu.is_open());
+    }
+
+    pub fn calibrate() {
+        let mut menu = Menu::new();
+        menu.press(true);
+        menu.press(true);
+        // Outdoor air
+        menu.press(true);
+        assert!(matches!(menu.screen, Screen::Calibrating));
+        assert!(matches!(
+            menu.calibration.state(),
+            State::Settling { reference: 420, .. }
+        ));
+        let mut action = None;
+        for i in 0..100 {
+            action = action.or(menu.update(i * 10, 430.));
+        }
+        assert_eq!(
+            action,
+            Some(Action::Recalibrate {
+                reference: 420,
+                correction: -10.
+            })
+        );
+        assert_eq!(menu.lines().unwrap()[1].as_str(), "Corr. -10ppm");
+        menu.press(false);
+        assert!(!menu.is_open());
+    }
+}
@@ -1,2 +1,33 @@
+pub mod alarm;
+pub mod aqi;
+pub mod barometer;
+pub mod bthome;
+pub mod calibration;
+pub mod characteristic;
+pub mod charset;
+pub mod codec;
 pub mod colormap;
+pub mod command;
+pub mod compensation;
+pub mod csv;
+pub mod flash;
 pub mod formatting;
+pub mod gatt;
+pub mod history;
+pub mod iaq;
+pub mod influx;
+pub mod json;
+pub mod layout;
+pub mod link;
+pub mod logger;
+pub mod measurement;
+pub mod menu;
+pub mod prometheus;
+pub mod psychro;
+pub mod settings;
+pub mod sparkline;
+pub mod stats;
+pub mod storage;
+pub mod thermal;
+pub mod time;
+pub mod trend;
@@ -0,0 +1,203 @@
+//! Measurements in the Prometheus text exposition format
+//!
+//! Every value that's present is a gauge of its own, named after its key and
+//! its unit the way Prometheus likes it, e.g. `airlog_temperature_celsius`.
+//! See <https://prometheus.io/docs/instrumenting/exposition_formats/>.
+
+use crate::logic::{
+    csv::precision,
+    formatting::format_decimal,
+    measurement::{Measurement, Quantity},
+};
+
+/// What the metric names start with
+pub const NAMESPACE: &str = "airlog";
+
+/// Long enough for a metric with its comments and a few short labels
+pub type Metric = heapless::String<320>;
+
+/// The base unit in metric names, `None` for numbers without a unit
+fn unit(quantity: Quantity) -> Option<&'static str> {
+    match quantity {
+        Quantity::Co2 => Some("ppm"),
+        Quantity::RelHumidity => Some("percent"),
+        Quantity::VocIndex | Quantity::Humidex => None,
+        Quantity::Pressure | Quantity::SeaLevelPressure => Some("pascals"),
+        Quantity::Pm1_0 | Quantity::Pm2_5 | Quantity::Pm4_0 | Quantity::Pm10 => {
+            Some("micrograms_per_cubic_metre")
+        }
+        Quantity::AbsoluteHumidity => Some("grams_per_cubic_metre"),
+        Quantity::Temperature
+        | Quantity::PressureSensorTemperature
+        | Quantity::BuiltinTemperature
+        | Quantity::DewPoint
+        | Quantity::HeatIndex
+        | Quantity::AmbientTemperature => Some("celsius"),
+    }
+}
+
+/// What goes after `# HELP`
+fn help(quantity: Quantity) -> &'static str {
+    match quantity {
+        Quantity::Co2 => "CO2 concentration from the SCD30",
+        Quantity::Temperature => "Temperature from the SCD30",
+        Quantity::RelHumidity => "Relative humidity",
+        Quantity::VocIndex => "Sensirion VOC index from the SGP40",
+        Quantity::Pressure => "Atmospheric pressure from the BMP388",
+        Quantity::PressureSensorTemperature => "Temperature from the BMP388",
+        Quantity::BuiltinTemperature => "Temperature of the nrf52840 die",
+        Quantity::Pm1_0 => "PM1.0 mass concentration from the SPS30",
+        Quantity::Pm2_5 => "PM2.5 mass concentration from the SPS30",
+        Quantity::Pm4_0 => "PM4.0 mass concentration from the SPS30",
+        Quantity::Pm10 => "PM10 mass concentration from the SPS30",
+        Quantity::DewPoint => "Dew point",
+        Quantity::AbsoluteHumidity => "Absolute humidity",
+        Quantity::HeatIndex => "NWS heat index",
+        Quantity::Humidex => "Humidex",
+        Quantity::SeaLevelPressure => "Atmospheric pressure reduced to sea level",
+        Quantity::AmbientTemperature => "Room temperature, corrected for self-heating",
+    }
+}
+
+/// The `# HELP` and `# TYPE` lines and the sample of `quantity` with
+/// `labels`, e.g. `[("device", id)]`, each ending in a line break. The
+/// timestamp is Unix time, and goes out in milliseconds. Without one,
+/// Prometheus uses the time of the scrape. `None` if the value isn't
+/// present, or if it doesn't fit.
+pub fn metric(
+    quantity: Quantity,
+    labels: &[(&str, &str)],
+    timestamp: Option<u32>,
+    measurement: &Measurement,
+) -> Option<Metric> {
+    let value = measurement.get(quantity)?;
+    let mut name: heapless::String<64> = heapless::String::new();
+    name.push_str(NAMESPACE).ok()?;
+    name.push('_').ok()?;
+    name.push_str(quantity.key()).ok()?;
+    if let Some(unit) = unit(quantity) {
+        name.push('_').ok()?;
+        name.push_str(unit).ok()?;
+    }
+
+    let mut metric = Metric::new();
+    ufmt::uwrite!(metric, "# HELP {} {}\n", name.as_str(), help(quantity)).ok()?;
+    ufmt::uwrite!(metric, "# TYPE {} gauge\n", name.as_str()).ok()?;
+    metric.push_str(&name).ok()?;
+    for (i, (label, value)) in labels.iter().enumerate() {
+        metric.push(if i == 0 { '{' } else { ',' }).ok()?;
+        metric.push_str(label).ok()?;
+        metric.push_str("=\"").ok()?;
+        for c in value.chars() {
+            match c {
+                '\\' => metric.push_str("\\\\"),
+                '"' => metric.push_str("\\\""),
+                '\n' => metric.push_str("\\n"),
+                c => metric.push(c),
+            }
+            .ok()?;
+        }
+        metric.push('"').ok()?;
+    }
+    if !labels.is_empty() {
+        metric.push('}').ok()?;
+    }
+    metric.push(' ').ok()?;
+    metric
+        .push_str(&format_decimal(value, precision(quantity)))
+        .ok()?;
+    if let Some(timestamp) = timestamp {
+        ufmt::uwrite!(metric, " {}000", timestamp).ok()?;
+    }
+    metric.push('\n').ok()?;
+    Some(metric)
+}
+
+/// The metrics of all the values that are present, which together make up a
+/// scrape. They go out one by one, since all of them at once would take a
+/// few kB.
+pub fn metrics<'a>(
+    labels: &'a [(&'a str, &'a str)],
+    timestamp: Option<u32>,
+    measurement: &'a Measurement,
+) -> impl Iterator<Item = Metric> + 'a {
+    Quantity::ALL
+        .into_iter()
+        .filter_map(move |quantity| metric(quantity, labels, timestamp, measurement))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{metric, metrics};
+    use crate::logic::measurement::{Measurement, Quantity};
+
+    pub fn expositions() {
+        let measurement = Measurement {
+            co2: Some(612.4),
+            ambient_temperature: Some(-4.256),
+            voc_index: Some(100.),
+            ..Default::default()
+        };
+        let mut scrape: heapless::String<1024> = heapless::String::new();
+        for metric in metrics(&[("device", "f1e2d3c4b5a69788")], None, &measurement) {
+            scrape.push_str(&metric).unwrap();
+        }
+        assert_eq!(
+            scrape.as_str(),
+            "# HELP airlog_co2_ppm CO2 concentration from the SCD30\n\
+             # TYPE airlog_co2_ppm gauge\n\
+             airlog_co2_ppm{device=\"f1e2d3c4b5a69788\"} 612\n\
+             # HELP airlog_voc_index Sensirion VOC index from the SGP40\n\
+             # TYPE airlog_voc_index gauge\n\
+             airlog_voc_index{device=\"f1e2d3c4b5a69788\"} 100\n\
+             # HELP airlog_ambient_temperature_celsius Room temperature, corrected for self-heating\n\
+             # TYPE airlog_ambient_temperature_celsius gauge\n\
+             airlog_ambient_temperature_celsius{device=\"f1e2d3c4b5a69788\"} -4.26\n"
+        );
+
+        let pressure = Measurement {
+            pressure: Some(101_325.),
+            ..Default::default()
+        };
+        assert_eq!(
+            metric(Quantity::Pressure, &[], Some(1_700_000_000), &pressure)
+                .unwrap()
+                .as_str(),
+            "# HELP airlog_pressure_pascals Atmospheric pressure from the BMP388\n\
+             # TYPE airlog_pressure_pascals gauge\n\
+             airlog_pressure_pascals 101325 1700000000000\n"
+        );
+        assert_eq!(metrics(&[], None, &Measurement::default()).count(), 0);
+
+        let mut full = Measurement::default();
+        for quantity in Quantity::ALL {
+            full.set(quantity, Some(-99_999.));
+        }
+        assert_eq!(
+            metrics(&[("device", "f1e2d3c4b5a69788")], Some(u32::MAX), &full).count(),
+            Quantity::ALL.len()
+        );
+    }
+
+    pub fn label_escaping() {
+        let measurement = Measurement {
+            pm2_5: Some(3.14),
+            ..Default::default()
+        };
+        assert_eq!(
+            metric(
+                Quantity::Pm2_5,
+                &[("room", "kid's \"den\""), ("path", "c:\\air\nlog")],
+                None,
+                &measurement
+            )
+            .unwrap()
+            .lines()
+            .last(),
+            Some(
+                "airlog_pm2_5_micrograms_per_cubic_metre\
+                 {room=\"kid's \\\"den\\\"\",path=\"c:\\\\air\\nlog\"} 3.1"
+            )
+        );
+    }
+}
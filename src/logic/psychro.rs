@@ -0,0 +1,171 @@
+//! Quantities derived from air temperature and relative humidity
+//!
+//! Temperatures are in °C and relative humidities in %.
+
+use micromath::F32Ext;
+
+use crate::logic::measurement::Measurement;
+
+// Magnus formula coefficients for water, valid from -45 to 60 °C
+const MAGNUS_A: f32 = 17.62;
+const MAGNUS_B: f32 = 243.12;
+const MAGNUS_C: f32 = 6.112;
+
+/// How much colder than the air the coldest wall of a room is assumed to be
+/// when judging mould risk, e.g. around window frames and thermal bridges
+pub const COLD_SURFACE_OFFSET: f32 = 3.;
+
+/// Saturation vapour pressure over water, hPa
+pub fn saturation_vapour_pressure(temperature: f32) -> f32 {
+    MAGNUS_C * (MAGNUS_A * temperature / (MAGNUS_B + temperature)).exp()
+}
+
+/// Magnus formula
+pub fn dew_point(temperature: f32, rel_humidity: f32) -> f32 {
+    let gamma =
+        (rel_humidity / 100.).max(0.001).ln() + MAGNUS_A * temperature / (MAGNUS_B + temperature);
+    MAGNUS_B * gamma / (MAGNUS_A - gamma)
+}
+
+/// Mass of water vapour per volume of air, g/m³
+pub fn absolute_humidity(temperature: f32, rel_humidity: f32) -> f32 {
+    // 216.7 is 100 Pa/hPa * 1000 g/kg divided by the specific gas constant of
+    // water vapour, 461.5 J/(kg K)
+    let vapour_pressure = rel_humidity / 100. * saturation_vapour_pressure(temperature);
+    216.7 * vapour_pressure / (273.15 + temperature)
+}
+
+/// The Canadian humidex, unitless but meant to be read as °C
+pub fn humidex(temperature: f32, rel_humidity: f32) -> f32 {
+    let dew_point = dew_point(temperature, rel_humidity) + 273.15;
+    let vapour_pressure = 6.11 * (5417.753 * (1. / 273.16 - 1. / dew_point)).exp();
+    temperature + 0.5555 * (vapour_pressure - 10.)
+}
+
+/// Apparent temperature according to the US National Weather Service, °C
+pub fn heat_index(temperature: f32, rel_humidity: f32) -> f32 {
+    // The regression is in °F
+    let t = temperature * 1.8 + 32.;
+    let rh = rel_humidity;
+    let simple = 0.5 * (t + 61. + (t - 68.) * 1.2 + rh * 0.094);
+    let heat_index = if (simple + t) / 2. < 80. {
+        simple
+    } else {
+        let mut heat_index = -42.379 + 2.049_015 * t + 10.143_33 * rh
+            - 0.224_755_4 * t * rh
+            - 0.006_837_83 * t * t
+            - 0.054_817_17 * rh * rh
+            + 0.001_228_74 * t * t * rh
+            + 0.000_852_82 * t * rh * rh
+            - 0.000_001_99 * t * t * rh * rh;
+        if rh < 13. && (80. ..=112.).contains(&t) {
+            heat_index -= (13. - rh) / 4. * ((17. - (t - 95.).abs()) / 17.).sqrt();
+        } else if rh > 85. && (80. ..=87.).contains(&t) {
+            heat_index += (rh - 85.) / 10. * (87. - t) / 5.;
+        }
+        heat_index
+    };
+    (heat_index - 32.) / 1.8
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub enum MouldRisk {
+    Low,
+    Moderate,
+    High,
+}
+
+impl MouldRisk {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MouldRisk::Low => "Low",
+            MouldRisk::Moderate => "Moderate",
+            MouldRisk::High => "High",
+        }
+    }
+}
+
+/// Relative humidity of air at `temperature` once it's brought to `target`
+/// without gaining or losing any water
+pub fn rel_humidity_at(temperature: f32, rel_humidity: f32, target: f32) -> f32 {
+    let humidity =
+        rel_humidity * saturation_vapour_pressure(temperature) / saturation_vapour_pressure(target);
+    humidity.min(100.)
+}
+
+/// Relative humidity at a surface that's `offset` °C colder than the air
+pub fn surface_humidity(temperature: f32, rel_humidity: f32, offset: f32) -> f32 {
+    rel_humidity_at(temperature, rel_humidity, temperature - offset)
+}
+
+/// Mould starts growing when surfaces stay above 80% relative humidity for
+/// a few days, so that's high risk on the coldest walls. Above 70% is
+/// getting close.
+pub fn mould_risk(temperature: f32, rel_humidity: f32) -> MouldRisk {
+    let humidity = surface_humidity(temperature, rel_humidity, COLD_SURFACE_OFFSET);
+    if humidity >= 80. {
+        MouldRisk::High
+    } else if humidity >= 70. {
+        MouldRisk::Moderate
+    } else {
+        MouldRisk::Low
+    }
+}
+
+/// Fill in the derived quantities of `measurement` from its ambient
+/// temperature, or the SCD30's if there's none, and relative humidity
+pub fn derive(measurement: &mut Measurement) {
+    let temperature = measurement.ambient_temperature.or(measurement.temperature);
+    if let (Some(temperature), Some(rel_humidity)) = (temperature, measurement.rel_humidity) {
+        measurement.dew_point = Some(dew_point(temperature, rel_humidity));
+        measurement.absolute_humidity = Some(absolute_humidity(temperature, rel_humidity));
+        measurement.humidex = Some(humidex(temperature, rel_humidity));
+        measurement.heat_index = Some(heat_index(temperature, rel_humidity));
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{absolute_humidity, derive, dew_point, heat_index, humidex, mould_risk, MouldRisk};
+    use crate::logic::measurement::Measurement;
+    use micromath::F32Ext;
+
+    fn assert_close(value: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (value - expected).abs() < tolerance,
+            "{} != {}",
+            value,
+            expected
+        );
+    }
+
+    pub fn dew_point_and_absolute_humidity() {
+        assert_close(dew_point(20., 50.), 9.26, 0.05);
+        assert_close(dew_point(20., 100.), 20., 0.05);
+        assert_close(absolute_humidity(20., 50.), 8.62, 0.05);
+    }
+
+    pub fn apparent_temperature() {
+        assert_close(humidex(30., 60.), 38.8, 0.2);
+        // 90 °F and 70% is 106 °F in the NWS table
+        assert_close(heat_index(32.22, 70.), 41.07, 0.2);
+        // Below 80 °F the simple formula is close to the temperature
+        assert_close(heat_index(20., 50.), 19.4, 0.5);
+    }
+
+    pub fn mould() {
+        // 20 °C and 50% is about 60% on a 17 °C wall
+        assert_eq!(mould_risk(20., 50.), MouldRisk::Low);
+        assert_eq!(mould_risk(20., 60.), MouldRisk::Moderate);
+        assert_eq!(mould_risk(20., 70.), MouldRisk::High);
+
+        let mut measurement = Measurement {
+            temperature: Some(20.),
+            rel_humidity: Some(50.),
+            ..Default::default()
+        };
+        derive(&mut measurement);
+        assert_close(measurement.dew_point.unwrap(), 9.26, 0.05);
+        assert!(measurement.heat_index.is_some());
+    }
+}
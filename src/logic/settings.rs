@@ -0,0 +1,419 @@
+//! Settings that survive a reboot
+//!
+//! Every save appends a fixed size record to a ring of slots that spans all
+//! of the given flash pages, so the pages wear evenly. A page gets erased
+//! when the ring comes back around to it, by which time the newest record is
+//! in another page. The newest record that checks out is the one that counts,
+//! so a save that gets cut short by a power loss leaves the previous settings
+//! in place.
+//!
+//! A record is laid out as
+//!
+//! | Bytes  | Contents                                    |
+//! |--------|---------------------------------------------|
+//! | 0..2   | [`MAGIC`]                                   |
+//! | 2      | Format version                              |
+//! | 3      | Length of the payload                       |
+//! | 4..8   | Sequence number, counting up with each save |
+//! | 8..60  | Payload, zero padded                        |
+//! | 60..64 | CRC-32 of bytes 0..60                       |
+//!
+//! with everything little endian. New versions of the format may only add
+//! fields to the end of the payload. Fields that an older record doesn't
+//! have get their default values, and fields that a newer record has on top
+//! of the known ones are ignored.
+
+use crate::logic::flash::{crc32, Flash};
+
+const MAGIC: u16 = 0x5354;
+const VERSION: u8 = 3;
+const RECORD_SIZE: usize = 64;
+const HEADER_SIZE: usize = 8;
+const CRC_OFFSET: usize = RECORD_SIZE - 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct Settings {
+    /// How much warmer than the room the SCD30 reads, °C
+    pub temperature_offset: f32,
+    /// CO2 level at the bottom of the CO2 LED's scale, ppm
+    pub co2_baseline: u16,
+    /// Sea level pressures at the ends of the pressure LED's scale, hPa
+    pub pressure_range: (u16, u16),
+    /// Chance of cleaning the SPS30's fan on startup, %
+    pub cleaning_probability: u8,
+    /// The display page to start on
+    pub page: u8,
+    /// Metres above sea level of where the device is installed
+    pub altitude: i16,
+    /// CO2 levels that raise the warning and the critical alarm, ppm
+    pub co2_alarm: (u16, u16),
+    /// How much more the SCD30 heats up for every °C that the board is warmer
+    /// than it, °C/°C
+    pub temperature_gain: f32,
+    /// The offset, °C, and the gain, °C/°C, of the BMP388's self-heating, in
+    /// the same way as the SCD30's
+    pub pressure_sensor_heating: (f32, f32),
+    /// How much the SCD30 and the BMP388 count towards the ambient
+    /// temperature, relative to each other. Zero leaves a sensor out.
+    pub temperature_weights: (u8, u8),
+}
+
+impl Settings {
+    pub const DEFAULT: Settings = Settings {
+        // The SCD30 reads 3.72 °C too warm in its usual enclosure
+        temperature_offset: 3.72,
+        // The outdoor level when the device was built
+        co2_baseline: 424,
+        // The lowest and highest sea level pressures recorded locally over
+        // three years
+        pressure_range: (990, 1040),
+        // Often enough for when the sensor doesn't run long enough to clean
+        // itself, which it does after a week
+        cleaning_probability: 20,
+        // "gases, pressure and particles"
+        page: 2,
+        altitude: 10,
+        co2_alarm: (1500, 3000),
+        // Neither sensor's self-heating has been measured against the spread
+        // yet, and the BMP388 is only logged
+        temperature_gain: 0.,
+        pressure_sensor_heating: (0., 0.),
+        temperature_weights: (1, 0),
+    };
+
+    pub const PAYLOAD_SIZE: usize = 32;
+
+    /// The fields in the order they're declared, little endian, into the first
+    /// [`PAYLOAD_SIZE`](Settings::PAYLOAD_SIZE) bytes of `payload`
+    pub fn encode(&self, payload: &mut [u8]) {
+        payload[0..4].copy_from_slice(&self.temperature_offset.to_le_bytes());
+        payload[4..6].copy_from_slice(&self.co2_baseline.to_le_bytes());
+        payload[6..8].copy_from_slice(&self.pressure_range.0.to_le_bytes());
+        payload[8..10].copy_from_slice(&self.pressure_range.1.to_le_bytes());
+        payload[10] = self.cleaning_probability;
+        payload[11] = self.page;
+        payload[12..14].copy_from_slice(&self.altitude.to_le_bytes());
+        payload[14..16].copy_from_slice(&self.co2_alarm.0.to_le_bytes());
+        payload[16..18].copy_from_slice(&self.co2_alarm.1.to_le_bytes());
+        payload[18..22].copy_from_slice(&self.temperature_gain.to_le_bytes());
+        payload[22..26].copy_from_slice(&self.pressure_sensor_heating.0.to_le_bytes());
+        payload[26..30].copy_from_slice(&self.pressure_sensor_heating.1.to_le_bytes());
+        payload[30] = self.temperature_weights.0;
+        payload[31] = self.temperature_weights.1;
+    }
+
+    /// Takes whatever fields `payload` is long enough for, and the defaults
+    /// for the rest
+    pub fn decode(payload: &[u8]) -> Settings {
+        let field = |range: core::ops::Range<usize>| payload.get(range);
+        let mut settings = Settings::DEFAULT;
+        if let Some(bytes) = field(0..4) {
+            settings.temperature_offset = f32::from_le_bytes(bytes.try_into().unwrap());
+        }
+        if let Some(bytes) = field(4..6) {
+            settings.co2_baseline = u16::from_le_bytes(bytes.try_into().unwrap());
+        }
+        if let (Some(min), Some(max)) = (field(6..8), field(8..10)) {
+            settings.pressure_range = (
+                u16::from_le_bytes(min.try_into().unwrap()),
+                u16::from_le_bytes(max.try_into().unwrap()),
+            );
+        }
+        if let Some(&probability) = payload.get(10) {
+            settings.cleaning_probability = probability;
+        }
+        if let Some(&page) = payload.get(11) {
+            settings.page = page;
+        }
+        if let Some(bytes) = field(12..14) {
+            settings.altitude = i16::from_le_bytes(bytes.try_into().unwrap());
+        }
+        if let (Some(warning), Some(critical)) = (field(14..16), field(16..18)) {
+            settings.co2_alarm = (
+                u16::from_le_bytes(warning.try_into().unwrap()),
+                u16::from_le_bytes(critical.try_into().unwrap()),
+            );
+        }
+        if let Some(bytes) = field(18..22) {
+            settings.temperature_gain = f32::from_le_bytes(bytes.try_into().unwrap());
+        }
+        if let (Some(offset), Some(gain)) = (field(22..26), field(26..30)) {
+            settings.pressure_sensor_heating = (
+                f32::from_le_bytes(offset.try_into().unwrap()),
+                f32::from_le_bytes(gain.try_into().unwrap()),
+            );
+        }
+        if let (Some(&scd30), Some(&bmp388)) = (payload.get(30), payload.get(31)) {
+            settings.temperature_weights = (scd30, bmp388);
+        }
+        settings.sanitized()
+    }
+
+    /// Replaces the values that make no sense with the defaults
+    pub fn sanitized(mut self) -> Settings {
+        let default = Settings::DEFAULT;
+        // NaN isn't in any range
+        let offset = |offset: f32| (-10. ..=10.).contains(&offset);
+        let gain = |gain: f32| (-1. ..=1.).contains(&gain);
+        if !offset(self.temperature_offset) {
+            self.temperature_offset = default.temperature_offset;
+        }
+        if !(300..=1000).contains(&self.co2_baseline) {
+            self.co2_baseline = default.co2_baseline;
+        }
+        let (min, max) = self.pressure_range;
+        if min >= max || min < 800 || max > 1200 {
+            self.pressure_range = default.pressure_range;
+        }
+        if self.cleaning_probability > 100 {
+            self.cleaning_probability = default.cleaning_probability;
+        }
+        // Dead Sea shore to Everest
+        if !(-500..=9000).contains(&self.altitude) {
+            self.altitude = default.altitude;
+        }
+        let (warning, critical) = self.co2_alarm;
+        if warning >= critical || warning < 600 || critical > 10_000 {
+            self.co2_alarm = default.co2_alarm;
+        }
+        if !gain(self.temperature_gain) {
+            self.temperature_gain = default.temperature_gain;
+        }
+        let (pressure_sensor_offset, pressure_sensor_gain) = self.pressure_sensor_heating;
+        if !offset(pressure_sensor_offset) || !gain(pressure_sensor_gain) {
+            self.pressure_sensor_heating = default.pressure_sensor_heating;
+        }
+        if self.temperature_weights == (0, 0) {
+            self.temperature_weights = default.temperature_weights;
+        }
+        self
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+pub struct SettingsStore<F> {
+    flash: F,
+    /// The slot that the next save goes to
+    next: usize,
+    sequence: u32,
+}
+
+impl<F: Flash> SettingsStore<F> {
+    /// Keeps the settings in all of `flash`, which has to be at least two
+    /// pages, so that there's always a page with the newest record in it
+    /// while the other one gets erased
+    pub fn new(flash: F) -> Self {
+        assert!(F::PAGE_SIZE % RECORD_SIZE == 0 && RECORD_SIZE % F::WORD_SIZE == 0);
+        assert!(flash.capacity() / F::PAGE_SIZE >= 2);
+        SettingsStore {
+            flash,
+            next: 0,
+            sequence: 0,
+        }
+    }
+
+    fn slots(&self) -> usize {
+        self.flash.capacity() / F::PAGE_SIZE * (F::PAGE_SIZE / RECORD_SIZE)
+    }
+
+    fn read_slot(&mut self, slot: usize) -> Result<[u8; RECORD_SIZE], F::Error> {
+        let mut record = [0; RECORD_SIZE];
+        self.flash.read((slot * RECORD_SIZE) as u32, &mut record)?;
+        Ok(record)
+    }
+
+    /// The newest saved settings, or the defaults if there are none, e.g. on
+    /// blank or corrupted flash
+    pub fn load(&mut self) -> Result<Settings, F::Error> {
+        let mut newest: Option<(usize, u32, Settings)> = None;
+        for slot in 0..self.slots() {
+            let record = self.read_slot(slot)?;
+            let Some((sequence, settings)) = parse(&record) else {
+                continue;
+            };
+            if newest.map_or(true, |(_, newest, _)| sequence > newest) {
+                newest = Some((slot, sequence, settings));
+            }
+        }
+        Ok(match newest {
+            Some((slot, sequence, settings)) => {
+                self.next = (slot + 1) % self.slots();
+                self.sequence = sequence.wrapping_add(1);
+                settings
+            }
+            None => {
+                self.next = 0;
+                self.sequence = 0;
+                Settings::DEFAULT
+            }
+        })
+    }
+
+    /// Should come after a [`SettingsStore::load`], so that the ring carries
+    /// on from the newest record
+    pub fn save(&mut self, settings: &Settings) -> Result<(), F::Error> {
+        let slots_per_page = F::PAGE_SIZE / RECORD_SIZE;
+        // Skip over slots that have something in them, e.g. a half written
+        // record, unless it's time to erase them anyway
+        let mut slot = self.next;
+        while slot % slots_per_page != 0 && self.read_slot(slot)? != [0xff; RECORD_SIZE] {
+            slot = (slot + 1) % self.slots();
+        }
+        if slot % slots_per_page == 0 {
+            self.flash.erase_page((slot * RECORD_SIZE) as u32)?;
+        }
+
+        let mut record = [0; RECORD_SIZE];
+        record[0..2].copy_from_slice(&MAGIC.to_le_bytes());
+        record[2] = VERSION;
+        record[3] = Settings::PAYLOAD_SIZE as u8;
+        record[4..8].copy_from_slice(&self.sequence.to_le_bytes());
+        settings.encode(&mut record[HEADER_SIZE..HEADER_SIZE + Settings::PAYLOAD_SIZE]);
+        let crc = crc32(&record[..CRC_OFFSET]);
+        record[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+        self.flash.write((slot * RECORD_SIZE) as u32, &record)?;
+
+        self.next = (slot + 1) % self.slots();
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(())
+    }
+
+    pub fn release(self) -> F {
+        self.flash
+    }
+}
+
+/// The sequence number and the settings of a record that checks out
+fn parse(record: &[u8; RECORD_SIZE]) -> Option<(u32, Settings)> {
+    let magic = u16::from_le_bytes([record[0], record[1]]);
+    let crc = u32::from_le_bytes(record[CRC_OFFSET..].try_into().unwrap());
+    if magic != MAGIC || crc != crc32(&record[..CRC_OFFSET]) {
+        return None;
+    }
+    let len = (record[3] as usize).min(CRC_OFFSET - HEADER_SIZE);
+    let sequence = u32::from_le_bytes(record[4..8].try_into().unwrap());
+    Some((
+        sequence,
+        Settings::decode(&record[HEADER_SIZE..HEADER_SIZE + len]),
+    ))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Settings, SettingsStore, RECORD_SIZE};
+    use crate::logic::flash::MemoryFlash;
+
+    const CHANGED: Settings = Settings {
+        temperature_offset: 2.5,
+        co2_baseline: 410,
+        pressure_range: (980, 1050),
+        cleaning_probability: 50,
+        page: 5,
+        altitude: 250,
+        co2_alarm: (1200, 2500),
+        temperature_gain: 0.25,
+        pressure_sensor_heating: (1.5, 0.125),
+        temperature_weights: (2, 1),
+    };
+
+    pub fn blank_flash() {
+        let mut store = SettingsStore::new(MemoryFlash::<2, 256>::new());
+        assert_eq!(store.load(), Ok(Settings::DEFAULT));
+    }
+
+    pub fn round_trip() {
+        let mut store = SettingsStore::new(MemoryFlash::<2, 256>::new());
+        store.load().unwrap();
+        store.save(&CHANGED).unwrap();
+        let mut settings = CHANGED;
+        settings.page = 6;
+        store.save(&settings).unwrap();
+
+        let mut store = SettingsStore::new(store.release());
+        assert_eq!(store.load(), Ok(settings));
+    }
+
+    pub fn corruption() {
+        let mut store = SettingsStore::new(MemoryFlash::<2, 256>::new());
+        store.load().unwrap();
+        store.save(&CHANGED).unwrap();
+        let mut settings = CHANGED;
+        settings.altitude = 300;
+        store.save(&settings).unwrap();
+
+        // A flipped bit in the newest record brings back the one before
+        let mut flash = store.release();
+        flash.corrupt(RECORD_SIZE + 10, 0x00);
+        let mut store = SettingsStore::new(flash);
+        assert_eq!(store.load(), Ok(CHANGED));
+
+        // So does losing power halfway through a save
+        store.save(&settings).unwrap();
+        let mut flash = store.release();
+        flash.cut_power_after(RECORD_SIZE / 2);
+        let mut store = SettingsStore::new(flash);
+        store.load().unwrap();
+        store.save(&Settings::DEFAULT).unwrap();
+        let mut flash = store.release();
+        flash.restore_power();
+        let mut store = SettingsStore::new(flash);
+        assert_eq!(store.load(), Ok(settings));
+
+        // And the next save steps over the half written record
+        store.save(&CHANGED).unwrap();
+        let mut store = SettingsStore::new(store.release());
+        assert_eq!(store.load(), Ok(CHANGED));
+
+        // Nonsense values that still check out get their defaults
+        let mut nonsense = CHANGED;
+        nonsense.pressure_range = (1050, 980);
+        nonsense.cleaning_probability = 200;
+        nonsense.temperature_gain = f32::NAN;
+        nonsense.temperature_weights = (0, 0);
+        let mut store = SettingsStore::new(MemoryFlash::<2, 256>::new());
+        store.save(&nonsense).unwrap();
+        let loaded = store.load().unwrap();
+        assert_eq!(loaded.pressure_range, Settings::DEFAULT.pressure_range);
+        assert_eq!(loaded.cleaning_probability, 20);
+        assert_eq!(loaded.altitude, 250);
+        assert_eq!(loaded.temperature_gain, Settings::DEFAULT.temperature_gain);
+        assert_eq!(
+            loaded.temperature_weights,
+            Settings::DEFAULT.temperature_weights
+        );
+    }
+
+    pub fn older_and_newer_records() {
+        // A record from when there only were the first two fields
+        let older = Settings::decode(&[0, 0, 32, 64, 0x9a, 0x01]);
+        assert_eq!(older.temperature_offset, 2.5);
+        assert_eq!(older.co2_baseline, 410);
+        assert_eq!(older.altitude, Settings::DEFAULT.altitude);
+        assert_eq!(older.co2_alarm, Settings::DEFAULT.co2_alarm);
+        assert_eq!(older.temperature_gain, Settings::DEFAULT.temperature_gain);
+
+        // A record with fields that are yet to be invented
+        let mut payload = [0xaa; 40];
+        CHANGED.encode(&mut payload);
+        assert_eq!(Settings::decode(&payload), CHANGED);
+    }
+
+    pub fn wear_levelling() {
+        let mut store = SettingsStore::new(MemoryFlash::<4, 256>::new());
+        store.load().unwrap();
+        let mut settings = CHANGED;
+        for i in 0..100 {
+            settings.page = i;
+            store.save(&settings).unwrap();
+        }
+        let mut store = SettingsStore::new(store.release());
+        assert_eq!(store.load(), Ok(settings));
+        // 100 saves of 4 records per page go around the 16 slots 6.25 times
+        assert_eq!(store.release().erases(), &[7, 6, 6, 6]);
+    }
+}
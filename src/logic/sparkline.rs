@@ -0,0 +1,97 @@
+//! Bar graphs of a [`History`] made of the block elements in [`BARS`]
+
+use crate::logic::charset::BARS;
+use crate::logic::history::History;
+
+/// Render the last `span` buckets of `history` as `width` bars, oldest on the
+/// left. Each bar shows the average of the buckets that fall into it. The bars
+/// are scaled so that the lowest value in the graph gets the lowest bar and
+/// the highest one the full bar. Columns without any data are left blank.
+pub fn sparkline<const N: usize, const W: usize>(
+    history: &History<N>,
+    span: usize,
+    width: usize,
+) -> heapless::String<W> {
+    let mut text = heapless::String::new();
+    let mut columns = [None; 64];
+    let width = width.min(W / 3).min(columns.len());
+    if width == 0 {
+        return text;
+    }
+    let span = span.min(N).max(width);
+
+    let mut sums = [(0f32, 0u16); 64];
+    for (i, value) in history.recent(span).enumerate() {
+        if let Some(value) = value {
+            let sum = &mut sums[i * width / span];
+            sum.0 += value;
+            sum.1 += 1;
+        }
+    }
+    for (column, (sum, count)) in columns.iter_mut().zip(sums).take(width) {
+        if count > 0 {
+            *column = Some(sum / count as f32);
+        }
+    }
+
+    let range = columns[..width]
+        .iter()
+        .flatten()
+        .fold(None, |acc: Option<(f32, f32)>, &value| match acc {
+            None => Some((value, value)),
+            Some((min, max)) => Some((min.min(value), max.max(value))),
+        });
+    for column in &columns[..width] {
+        let c = match (column, range) {
+            (Some(value), Some((min, max))) => BARS[level(*value, min, max)],
+            _ => ' ',
+        };
+        // Block elements take 3 bytes, which was accounted for above
+        text.push(c).ok();
+    }
+    text
+}
+
+/// Index into [`BARS`] for `value`. A flat graph is drawn at half height.
+fn level(value: f32, min: f32, max: f32) -> usize {
+    let top = BARS.len() - 1;
+    if max - min < f32::EPSILON {
+        return top / 2;
+    }
+    let level = (value - min) / (max - min) * top as f32 + 0.5;
+    (level as usize).min(top)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::sparkline;
+    use crate::logic::history::{History, BUCKET_SECONDS};
+
+    fn filled(values: &[f32]) -> History<16> {
+        let mut history = History::new();
+        for (i, &value) in values.iter().enumerate() {
+            history.add(i as u32 * BUCKET_SECONDS, value);
+        }
+        // Close the last bucket
+        history.add(values.len() as u32 * BUCKET_SECONDS, 0.);
+        history
+    }
+
+    pub fn scales_to_range() {
+        let history = filled(&[400., 500., 600., 700., 800., 900., 1000., 1100.]);
+        let text: heapless::String<48> = sparkline(&history, 8, 8);
+        assert_eq!(text.as_str(), "▁▂▃▄▅▆▇█");
+    }
+
+    pub fn groups_buckets_and_leaves_gaps() {
+        let history = filled(&[400., 400., 800., 800.]);
+        // Two buckets per column, and the first two columns are before the
+        // history begins
+        let text: heapless::String<48> = sparkline(&history, 8, 4);
+        assert_eq!(text.as_str(), "  ▁█");
+
+        let history = filled(&[500., 500.]);
+        let text: heapless::String<48> = sparkline(&history, 2, 2);
+        assert_eq!(text.as_str(), "▄▄");
+    }
+}
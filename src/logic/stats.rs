@@ -0,0 +1,330 @@
+//! Running min/max/mean/standard deviation over a few fixed time windows
+//!
+//! Everything is kept in fixed size arrays. The rolling windows are split into
+//! buckets, each with its own [`Stats`], and the oldest bucket gets dropped
+//! when a new one starts. That makes the windows a bit ragged: the last hour
+//! covers between 55 and 60 minutes, the last 24 hours between 23 and 24 hours.
+
+use micromath::F32Ext;
+
+use crate::logic::measurement::{Measurement, Quantity};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Period {
+    LastHour,
+    LastDay,
+    SinceBoot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Statistic {
+    Min,
+    Max,
+    Mean,
+    StdDev,
+}
+
+/// Summary of a series of values, updated one value at a time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    count: u32,
+    min: f32,
+    max: f32,
+    mean: f32,
+    // Sum of squared differences from the mean
+    m2: f32,
+}
+
+impl Stats {
+    pub const fn new() -> Self {
+        Stats {
+            count: 0,
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            mean: 0.,
+            m2: 0.,
+        }
+    }
+
+    /// Uses Welford's algorithm, which doesn't lose precision the way summing
+    /// up squares does
+    pub fn add(&mut self, value: f32) {
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    /// Combine with the stats of another series, as if all of its values had
+    /// been added to this one
+    pub fn merge(&mut self, other: &Stats) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = *other;
+            return;
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        let weight = other.count as f32 / count as f32;
+        self.m2 += other.m2 + delta * delta * self.count as f32 * weight;
+        self.mean += delta * weight;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.count = count;
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn min(&self) -> Option<f32> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    pub fn max(&self) -> Option<f32> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    pub fn mean(&self) -> Option<f32> {
+        (self.count > 0).then_some(self.mean)
+    }
+
+    /// Population standard deviation
+    pub fn std_dev(&self) -> Option<f32> {
+        (self.count > 0).then(|| (self.m2 / self.count as f32).max(0.).sqrt())
+    }
+
+    pub fn get(&self, statistic: Statistic) -> Option<f32> {
+        match statistic {
+            Statistic::Min => self.min(),
+            Statistic::Max => self.max(),
+            Statistic::Mean => self.mean(),
+            Statistic::StdDev => self.std_dev(),
+        }
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Stats over the last `N` buckets of `bucket_seconds` each
+pub struct Window<const N: usize> {
+    buckets: [Stats; N],
+    bucket_seconds: u32,
+    current: usize,
+    bucket_start: Option<u32>,
+}
+
+impl<const N: usize> Window<N> {
+    pub const fn new(bucket_seconds: u32) -> Self {
+        Window {
+            buckets: [Stats::new(); N],
+            bucket_seconds,
+            current: 0,
+            bucket_start: None,
+        }
+    }
+
+    /// Add a value measured at `now` seconds since boot
+    pub fn add(&mut self, now: u32, value: f32) {
+        let bucket_start = *self.bucket_start.get_or_insert(now);
+        // Wrapping, because the seconds counter is allowed to overflow
+        let elapsed = now.wrapping_sub(bucket_start);
+        let steps = elapsed / self.bucket_seconds;
+        if steps > 0 {
+            // Buckets that we skipped over stay empty
+            for _ in 0..(steps as usize).min(N) {
+                self.current = (self.current + 1) % N;
+                self.buckets[self.current] = Stats::new();
+            }
+            self.bucket_start = Some(bucket_start.wrapping_add(steps * self.bucket_seconds));
+        }
+        self.buckets[self.current].add(value);
+    }
+
+    /// Stats over the whole window, as of the last added value
+    pub fn stats(&self) -> Stats {
+        self.buckets.iter().fold(Stats::new(), |mut stats, bucket| {
+            stats.merge(bucket);
+            stats
+        })
+    }
+
+    pub fn reset(&mut self) {
+        self.buckets = [Stats::new(); N];
+        self.bucket_start = None;
+    }
+}
+
+/// All the windows of a single quantity
+pub struct QuantityStats {
+    last_hour: Window<12>,
+    last_day: Window<24>,
+    since_boot: Stats,
+}
+
+impl QuantityStats {
+    pub const fn new() -> Self {
+        QuantityStats {
+            last_hour: Window::new(5 * 60),
+            last_day: Window::new(60 * 60),
+            since_boot: Stats::new(),
+        }
+    }
+
+    pub fn add(&mut self, now: u32, value: f32) {
+        self.last_hour.add(now, value);
+        self.last_day.add(now, value);
+        self.since_boot.add(value);
+    }
+
+    pub fn get(&self, period: Period) -> Stats {
+        match period {
+            Period::LastHour => self.last_hour.stats(),
+            Period::LastDay => self.last_day.stats(),
+            Period::SinceBoot => self.since_boot,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl Default for QuantityStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Stats of every quantity in a [`Measurement`]
+pub struct MeasurementStats {
+    quantities: [QuantityStats; Quantity::ALL.len()],
+}
+
+impl MeasurementStats {
+    pub const fn new() -> Self {
+        const EMPTY: QuantityStats = QuantityStats::new();
+        MeasurementStats {
+            quantities: [EMPTY; Quantity::ALL.len()],
+        }
+    }
+
+    pub fn add(&mut self, now: u32, quantity: Quantity, value: f32) {
+        self.quantities[quantity as usize].add(now, value);
+    }
+
+    /// Add every value that's present in `measurement`
+    pub fn add_measurement(&mut self, now: u32, measurement: &Measurement) {
+        for quantity in Quantity::ALL {
+            if let Some(value) = measurement.get(quantity) {
+                self.add(now, quantity, value);
+            }
+        }
+    }
+
+    pub fn get(&self, quantity: Quantity, period: Period) -> Stats {
+        self.quantities[quantity as usize].get(period)
+    }
+
+    /// Start over with all windows of all quantities
+    pub fn reset(&mut self) {
+        for stats in &mut self.quantities {
+            stats.reset();
+        }
+    }
+}
+
+impl Default for MeasurementStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{MeasurementStats, Period, Stats, Window};
+    use crate::logic::measurement::{Measurement, Quantity};
+
+    fn assert_close(value: Option<f32>, expected: f32) {
+        let value = value.unwrap();
+        assert!(
+            value > expected - 1e-3 && value < expected + 1e-3,
+            "{} != {}",
+            value,
+            expected
+        );
+    }
+
+    pub fn stats_of_series() {
+        let mut stats = Stats::new();
+        assert_eq!(stats.mean(), None);
+        for value in [2., 4., 4., 4., 5., 5., 7., 9.] {
+            stats.add(value);
+        }
+        assert_eq!(stats.min(), Some(2.));
+        assert_eq!(stats.max(), Some(9.));
+        assert_close(stats.mean(), 5.);
+        assert_close(stats.std_dev(), 2.);
+    }
+
+    pub fn merge_matches_single_series() {
+        let mut first = Stats::new();
+        let mut second = Stats::new();
+        let mut all = Stats::new();
+        for value in [2., 4., 4., 4.] {
+            first.add(value);
+            all.add(value);
+        }
+        for value in [5., 5., 7., 9.] {
+            second.add(value);
+            all.add(value);
+        }
+        first.merge(&second);
+        assert_eq!(first.count(), all.count());
+        assert_eq!(first.min(), all.min());
+        assert_eq!(first.max(), all.max());
+        assert_close(first.mean(), 5.);
+        assert_close(first.std_dev(), 2.);
+    }
+
+    pub fn window_drops_old_buckets() {
+        let mut window: Window<3> = Window::new(10);
+        window.add(0, 100.);
+        window.add(15, 1.);
+        window.add(25, 3.);
+        assert_eq!(window.stats().max(), Some(100.));
+        // The bucket with 100 falls out
+        window.add(30, 2.);
+        assert_eq!(window.stats().max(), Some(3.));
+        assert_close(window.stats().mean(), 2.);
+        // Long gaps empty the whole window
+        window.add(1000, 5.);
+        assert_eq!(window.stats().count(), 1);
+    }
+
+    pub fn measurement_stats_and_reset() {
+        let mut stats = MeasurementStats::new();
+        let measurement = Measurement {
+            co2: Some(800.),
+            ..Default::default()
+        };
+        stats.add_measurement(0, &measurement);
+        stats.add(5, Quantity::Co2, 400.);
+        assert_close(stats.get(Quantity::Co2, Period::LastHour).mean(), 600.);
+        assert_eq!(
+            stats.get(Quantity::Co2, Period::SinceBoot).max(),
+            Some(800.)
+        );
+        assert_eq!(stats.get(Quantity::Pm10, Period::LastDay).count(), 0);
+        stats.reset();
+        assert_eq!(stats.get(Quantity::Co2, Period::SinceBoot).count(), 0);
+    }
+}
@@ -0,0 +1,90 @@
+//! Where logged measurements go
+//!
+//! The measurement log can live in the internal flash, in an external SPI NOR
+//! flash, both through [`Logger`], or on a microSD card as CSV files. The
+//! firmware only appends to it.
+
+use crate::logic::{flash::Flash, logger::Logger, measurement::Measurement};
+
+pub trait Storage {
+    type Error;
+
+    /// Log `measurement`, taken at `timestamp` seconds
+    fn append(&mut self, timestamp: u32, measurement: &Measurement) -> Result<(), Self::Error>;
+}
+
+impl<F: Flash> Storage for Logger<F> {
+    type Error = F::Error;
+
+    fn append(&mut self, timestamp: u32, measurement: &Measurement) -> Result<(), Self::Error> {
+        Logger::append(self, timestamp, measurement)
+    }
+}
+
+/// Keeps the last `N` measurements in RAM, for testing
+pub struct MemoryStorage<const N: usize> {
+    records: heapless::Deque<(u32, Measurement), N>,
+}
+
+impl<const N: usize> MemoryStorage<N> {
+    pub const fn new() -> Self {
+        MemoryStorage {
+            records: heapless::Deque::new(),
+        }
+    }
+
+    /// Timestamps and measurements, oldest first
+    pub fn records(&self) -> impl Iterator<Item = &(u32, Measurement)> {
+        self.records.iter()
+    }
+}
+
+impl<const N: usize> Default for MemoryStorage<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Storage for MemoryStorage<N> {
+    type Error = core::convert::Infallible;
+
+    fn append(&mut self, timestamp: u32, measurement: &Measurement) -> Result<(), Self::Error> {
+        if self.records.is_full() {
+            self.records.pop_front();
+        }
+        self.records.push_back((timestamp, *measurement)).ok();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{MemoryStorage, Storage};
+    use crate::logic::{flash::MemoryFlash, logger::Logger, measurement::Measurement};
+
+    /// Logs the same way whatever the storage
+    fn log_minutes<S: Storage>(storage: &mut S, minutes: u32) {
+        for minute in 0..minutes {
+            let measurement = Measurement {
+                co2: Some(400. + minute as f32),
+                ..Default::default()
+            };
+            assert!(storage.append(minute * 60, &measurement).is_ok());
+        }
+    }
+
+    pub fn backends() {
+        let mut memory: MemoryStorage<3> = MemoryStorage::new();
+        log_minutes(&mut memory, 5);
+        let timestamps: heapless::Vec<u32, 3> = memory.records().map(|record| record.0).collect();
+        assert_eq!(timestamps, [120, 180, 240]);
+
+        let mut logger = Logger::new(MemoryFlash::<2, 256>::new());
+        log_minutes(&mut logger, 5);
+        let co2: heapless::Vec<f32, 5> = logger
+            .records()
+            .map(|record| record.unwrap().measurement.co2.unwrap())
+            .collect();
+        assert_eq!(co2, [400., 401., 402., 403., 404.]);
+    }
+}
@@ -0,0 +1,180 @@
+//! Ambient temperature from sensors that sit in a warm enclosure
+//!
+//! Everything in the enclosure reads warmer than the room, and the
+//! microcontroller die is the warmest of all. How far a sensor is off is
+//! modelled as `offset + gain * spread`, where the spread is how much warmer
+//! the die is than the sensor, i.e. how hard the enclosure is heating up. The
+//! ambient temperature is the weighted mean of what the corrected sensors
+//! say. The models and the weights come from the [`Settings`].
+
+use crate::logic::settings::Settings;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SelfHeating {
+    /// °C
+    pub offset: f32,
+    /// °C per °C of spread
+    pub gain: f32,
+}
+
+impl SelfHeating {
+    pub const NONE: SelfHeating = SelfHeating {
+        offset: 0.,
+        gain: 0.,
+    };
+
+    /// How much warmer than the room a sensor reading `temperature` is, given
+    /// the `board` temperature
+    pub fn heating(&self, temperature: f32, board: f32) -> f32 {
+        self.offset + self.gain * (board - temperature)
+    }
+
+    pub fn ambient(&self, temperature: f32, board: f32) -> f32 {
+        temperature - self.heating(temperature, board)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sensor {
+    pub name: &'static str,
+    pub model: SelfHeating,
+    /// How much the sensor counts towards the ambient temperature, relative
+    /// to the others. Zero leaves it out.
+    pub weight: f32,
+}
+
+pub struct TemperatureFusion<const N: usize> {
+    sensors: [Sensor; N],
+}
+
+impl<const N: usize> TemperatureFusion<N> {
+    pub const fn new(sensors: [Sensor; N]) -> Self {
+        TemperatureFusion { sensors }
+    }
+
+    /// The weighted mean of the corrected `readings`, given in the same order
+    /// as the sensors. Gives `None` if none of the weighted sensors have a
+    /// reading.
+    pub fn ambient(&self, readings: [Option<f32>; N], board: f32) -> Option<f32> {
+        let (sum, weights) = self.sensors.iter().zip(readings).fold(
+            (0., 0.),
+            |(sum, weights), (sensor, reading)| match reading {
+                Some(reading) if sensor.weight > 0. => (
+                    sum + sensor.weight * sensor.model.ambient(reading, board),
+                    weights + sensor.weight,
+                ),
+                _ => (sum, weights),
+            },
+        );
+        (weights > 0.).then(|| sum / weights)
+    }
+
+    /// The estimated self-heating of every sensor that has a reading
+    pub fn heating(&self, readings: [Option<f32>; N], board: f32) -> [Option<f32>; N] {
+        let mut heating = [None; N];
+        for ((heating, sensor), reading) in heating.iter_mut().zip(&self.sensors).zip(readings) {
+            *heating = reading.map(|reading| sensor.model.heating(reading, board));
+        }
+        heating
+    }
+
+    /// Switch to other models, e.g. after the settings changed
+    pub fn set_sensors(&mut self, sensors: [Sensor; N]) {
+        self.sensors = sensors;
+    }
+
+    pub fn sensors(&self) -> &[Sensor; N] {
+        &self.sensors
+    }
+}
+
+/// The SCD30 and the BMP388, in the order that their readings get passed to
+/// the fusion, as `settings` has them
+pub fn configured_sensors(settings: &Settings) -> [Sensor; 2] {
+    let (pressure_sensor_offset, pressure_sensor_gain) = settings.pressure_sensor_heating;
+    [
+        Sensor {
+            name: "SCD30",
+            model: SelfHeating {
+                offset: settings.temperature_offset,
+                gain: settings.temperature_gain,
+            },
+            weight: settings.temperature_weights.0 as f32,
+        },
+        Sensor {
+            name: "BMP388",
+            model: SelfHeating {
+                offset: pressure_sensor_offset,
+                gain: pressure_sensor_gain,
+            },
+            weight: settings.temperature_weights.1 as f32,
+        },
+    ]
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{configured_sensors, SelfHeating, Sensor, TemperatureFusion};
+    use crate::logic::settings::Settings;
+    use micromath::F32Ext;
+
+    fn assert_close(value: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (value - expected).abs() < tolerance,
+            "{} != {}",
+            value,
+            expected
+        );
+    }
+
+    const SENSORS: [Sensor; 2] = [
+        Sensor {
+            name: "SCD30",
+            model: SelfHeating {
+                offset: 1.,
+                gain: 0.5,
+            },
+            weight: 2.,
+        },
+        Sensor {
+            name: "BMP388",
+            model: SelfHeating::NONE,
+            weight: 1.,
+        },
+    ];
+
+    pub fn configured() {
+        let fusion = TemperatureFusion::new(SENSORS);
+        // 25 - 1 - 0.5 * 4 = 22 and 23, weighted 2:1
+        let ambient = fusion.ambient([Some(25.), Some(23.)], 29.).unwrap();
+        assert_close(ambient, 67. / 3., 0.001);
+        assert_close(fusion.ambient([Some(25.), None], 29.).unwrap(), 22., 0.001);
+        assert_eq!(fusion.ambient([None, None], 29.), None);
+        assert_eq!(fusion.heating([Some(25.), None], 29.), [Some(3.), None]);
+    }
+
+    pub fn from_settings() {
+        let settings = Settings {
+            temperature_offset: 1.,
+            temperature_gain: 0.25,
+            ..Settings::DEFAULT
+        };
+        let fusion = TemperatureFusion::new(configured_sensors(&settings));
+        // Without a spread there's only the offset, the BMP388 doesn't count
+        let ambient = fusion.ambient([Some(25.), Some(30.)], 25.).unwrap();
+        assert_close(ambient, 24., 0.001);
+        // The board running 4 °C warmer than the SCD30 makes for another 1 °C
+        let ambient = fusion.ambient([Some(25.), Some(30.)], 29.).unwrap();
+        assert_close(ambient, 23., 0.001);
+
+        // 23 and 28 - 0.5 - 0.5 * 1, weighted 1:1
+        let settings = Settings {
+            pressure_sensor_heating: (0.5, 0.5),
+            temperature_weights: (1, 1),
+            ..settings
+        };
+        let fusion = TemperatureFusion::new(configured_sensors(&settings));
+        let ambient = fusion.ambient([Some(25.), Some(28.)], 29.).unwrap();
+        assert_close(ambient, 25., 0.001);
+    }
+}
@@ -0,0 +1,291 @@
+//! Wall-clock time, and calendar dates and times of day from Unix time, in
+//! UTC
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12
+    pub month: u8,
+    /// 1 to 31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// `timestamp` is seconds since 1970-01-01 00:00:00 UTC
+    pub fn from_unix(timestamp: u32) -> Self {
+        let days = timestamp / 86_400;
+        let seconds = timestamp % 86_400;
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+
+    /// `None` if any of the fields is out of range, or the date is before
+    /// 1970 or past what fits in a `u32`
+    pub fn to_unix(&self) -> Option<u32> {
+        let days_in_month = match self.month {
+            2 if is_leap_year(self.year) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            1..=12 => 31,
+            _ => return None,
+        };
+        if self.year < 1970
+            || !(1..=days_in_month).contains(&self.day)
+            || self.hour > 23
+            || self.minute > 59
+            || self.second > 59
+        {
+            return None;
+        }
+        let days = days_from_civil(self.year, self.month, self.day);
+        let seconds = self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64;
+        (days as u64 * 86_400 + seconds).try_into().ok()
+    }
+
+    /// Reads what [`DateTime::iso8601`] writes, where the "Z" is optional.
+    /// `None` unless it's a valid date and time.
+    pub fn parse(text: &str) -> Option<DateTime> {
+        let text = text.strip_suffix('Z').unwrap_or(text);
+        let bytes = text.as_bytes();
+        if bytes.len() != 19
+            || bytes[4] != b'-'
+            || bytes[7] != b'-'
+            || bytes[10] != b'T'
+            || bytes[13] != b':'
+            || bytes[16] != b':'
+        {
+            return None;
+        }
+        let number = |range: core::ops::Range<usize>| -> Option<u16> {
+            let digits = text.get(range)?;
+            if !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+                return None;
+            }
+            digits.parse().ok()
+        };
+        let time = DateTime {
+            year: number(0..4)?,
+            month: number(5..7)? as u8,
+            day: number(8..10)? as u8,
+            hour: number(11..13)? as u8,
+            minute: number(14..16)? as u8,
+            second: number(17..19)? as u8,
+        };
+        time.to_unix().map(|_| time)
+    }
+
+    /// ISO 8601, e.g. "2023-11-14T22:13:20Z"
+    pub fn iso8601(&self) -> heapless::String<20> {
+        let mut text = heapless::String::new();
+        text.push_str(&self.date()).ok();
+        text.push('T').ok();
+        text.push_str(&self.time_of_day()).ok();
+        text.push('Z').ok();
+        text
+    }
+
+    /// e.g. "2023-11-14"
+    pub fn date(&self) -> heapless::String<10> {
+        let mut text = heapless::String::new();
+        push_padded(&mut text, self.year as u32, 4);
+        text.push('-').ok();
+        push_padded(&mut text, self.month as u32, 2);
+        text.push('-').ok();
+        push_padded(&mut text, self.day as u32, 2);
+        text
+    }
+
+    /// e.g. "22:13:20"
+    pub fn time_of_day(&self) -> heapless::String<8> {
+        let mut text = heapless::String::new();
+        push_padded(&mut text, self.hour as u32, 2);
+        text.push(':').ok();
+        push_padded(&mut text, self.minute as u32, 2);
+        text.push(':').ok();
+        push_padded(&mut text, self.second as u32, 2);
+        text
+    }
+}
+
+fn push_padded<const N: usize>(text: &mut heapless::String<N>, value: u32, width: usize) {
+    let mut digits: heapless::String<10> = heapless::String::new();
+    ufmt::uwrite!(digits, "{}", value).ok();
+    for _ in digits.len()..width {
+        text.push('0').ok();
+    }
+    text.push_str(&digits).ok();
+}
+
+/// Timestamps before 2000-01-01 are seconds since boot rather than Unix time,
+/// for things that happened while the clock wasn't set
+pub const EARLIEST: u32 = 946_684_800;
+
+/// Keeps Unix time by counting the ticks of a free running hardware counter,
+/// once it has been told what time it is
+pub struct Clock {
+    /// Ticks per second
+    frequency: u32,
+    /// The counter wraps around after this many ticks
+    period: u64,
+    last_counter: u32,
+    ticks: u64,
+    /// Unix time at a tick count
+    reference: Option<(u32, u64)>,
+}
+
+impl Clock {
+    /// For a counter that's `bits` wide and ticks `frequency` times a second
+    pub const fn new(frequency: u32, bits: u32) -> Self {
+        Clock {
+            frequency,
+            period: 1 << bits,
+            last_counter: 0,
+            ticks: 0,
+            reference: None,
+        }
+    }
+
+    /// Feed in the counter's current value. Has to happen at least once
+    /// before the counter wraps around, so that no wrap gets missed.
+    pub fn update(&mut self, counter: u32) {
+        let elapsed = (counter as u64 + self.period - self.last_counter as u64) % self.period;
+        self.ticks += elapsed;
+        self.last_counter = counter;
+    }
+
+    /// Seconds since the counter started
+    pub fn uptime(&self) -> u32 {
+        (self.ticks / self.frequency as u64) as u32
+    }
+
+    /// Unix time, `None` until the clock has been set
+    pub fn now(&self) -> Option<u32> {
+        self.reference.map(|(unix, ticks)| {
+            let seconds = (self.ticks - ticks) / self.frequency as u64;
+            unix.saturating_add(seconds as u32)
+        })
+    }
+
+    pub fn set(&mut self, unix: u32) {
+        self.reference = Some((unix, self.ticks));
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.reference.is_some()
+    }
+
+    /// Unix time if the clock has been set, seconds since boot otherwise, see
+    /// [`EARLIEST`]
+    pub fn timestamp(&self) -> u32 {
+        self.now()
+            .unwrap_or_else(|| self.uptime().min(EARLIEST - 1))
+    }
+}
+
+pub fn is_leap_year(year: u16) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+/// Howard Hinnant's algorithm, in eras of 400 years that start on March 1st,
+/// which puts leap days at the end of the year
+fn civil_from_days(days: u32) -> (u16, u8, u8) {
+    // Days since 0000-03-01
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + u32::from(month <= 2);
+    (year as u16, month as u8, day as u8)
+}
+
+fn days_from_civil(year: u16, month: u8, day: u8) -> u32 {
+    let year = year as u32 - u32::from(month <= 2);
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let month = month as u32;
+    let month_from_march = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as u32 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Clock, DateTime};
+
+    pub fn conversions() {
+        let epoch = DateTime::from_unix(0);
+        assert_eq!(epoch.iso8601().as_str(), "1970-01-01T00:00:00Z");
+        assert_eq!(epoch.to_unix(), Some(0));
+
+        let date = DateTime::from_unix(1_700_000_000);
+        assert_eq!(date.iso8601().as_str(), "2023-11-14T22:13:20Z");
+        assert_eq!(date.to_unix(), Some(1_700_000_000));
+
+        // A leap day, and the last second that fits
+        let leap_day = DateTime::from_unix(951_825_600);
+        assert_eq!(leap_day.iso8601().as_str(), "2000-02-29T12:00:00Z");
+        assert_eq!(
+            DateTime::from_unix(u32::MAX).iso8601().as_str(),
+            "2106-02-07T06:28:15Z"
+        );
+
+        let invalid = DateTime {
+            day: 29,
+            ..DateTime::from_unix(1_677_000_000)
+        };
+        assert_eq!(invalid.month, 2);
+        assert_eq!(invalid.to_unix(), None);
+        let too_late = DateTime {
+            year: 2107,
+            ..epoch
+        };
+        assert_eq!(too_late.to_unix(), None);
+
+        assert_eq!(DateTime::parse("2023-11-14T22:13:20Z"), Some(date));
+        assert_eq!(DateTime::parse("2023-11-14T22:13:20"), Some(date));
+        assert_eq!(DateTime::parse("2023-02-29T12:00:00Z"), None);
+        assert_eq!(DateTime::parse("2023-11-14 22:13:20Z"), None);
+        assert_eq!(DateTime::parse("2023-11-+4T22:13:20Z"), None);
+    }
+
+    pub fn clock() {
+        // An 8 Hz, 24 bit counter like the nRF's RTC
+        let mut clock = Clock::new(8, 24);
+        assert_eq!(clock.now(), None);
+        clock.update(80);
+        assert_eq!(clock.uptime(), 10);
+        assert_eq!(clock.timestamp(), 10);
+
+        clock.set(1_700_000_000);
+        clock.update(84);
+        assert_eq!(clock.now(), Some(1_700_000_000));
+        clock.update(88);
+        assert_eq!(clock.now(), Some(1_700_000_001));
+
+        // Wrapping around
+        clock.update((1 << 24) - 8);
+        clock.update(8);
+        assert_eq!(clock.uptime(), (1 << 21) + 1);
+        assert_eq!(clock.timestamp(), 1_700_000_000 + (1 << 21) - 10 + 1);
+    }
+}
@@ -0,0 +1,287 @@
+//! Ventilation advice from the recent CO2 trend
+//!
+//! A line is fitted through the last few minutes of CO2 readings. While CO2
+//! is rising, the line tells when it will reach the threshold. While it's
+//! falling, e.g. after opening a window, the room is assumed to follow the
+//! usual exponential decay towards the outdoor level,
+//! `C(t) = C_out + (C_0 - C_out) * exp(-n * t)`, and the air change rate `n`
+//! is the slope of the line fitted through `ln(C - C_out)`.
+
+use micromath::F32Ext;
+
+/// Slopes within this many ppm per minute count as steady
+const STEADY_SLOPE: f32 = 2.;
+
+/// Readings this close to the outdoor level are too noisy for the decay model
+const MIN_EXCESS: f32 = 20.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Direction {
+    Rising,
+    Steady,
+    Falling,
+}
+
+impl Direction {
+    /// Shown with the custom glyphs on the LCD
+    pub fn arrow(&self) -> char {
+        match self {
+            Direction::Rising => '↑',
+            Direction::Steady => '→',
+            Direction::Falling => '↓',
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Advice {
+    pub direction: Direction,
+    /// ppm per minute
+    pub slope: f32,
+    /// Minutes until the threshold is reached, 0 if it already has been
+    pub time_to_threshold: Option<f32>,
+    /// Air changes per hour, while CO2 is falling
+    pub air_change_rate: Option<f32>,
+}
+
+impl Advice {
+    /// A message that fits on a 16 character row
+    pub fn message(&self) -> heapless::String<16> {
+        let mut message = heapless::String::new();
+        match (self.time_to_threshold, self.air_change_rate) {
+            (Some(minutes), _) if minutes < 0.5 => {
+                message.push_str("Ventilate now").ok();
+            }
+            (Some(minutes), _) if minutes < 99.5 => {
+                ufmt::uwrite!(message, "Ventilate in {}m", minutes.round() as u32).ok();
+            }
+            // Tenths only below 10, and two digits at most, to fit
+            (_, Some(rate)) if rate < 9.95 => {
+                let rate = (rate * 10.).round() as u32;
+                ufmt::uwrite!(message, "Air change {}.{}/h", rate / 10, rate % 10).ok();
+            }
+            (_, Some(rate)) => {
+                let rate = (rate.round() as u32).min(99);
+                ufmt::uwrite!(message, "Air change {}/h", rate).ok();
+            }
+            _ => {
+                message
+                    .push_str(match self.direction {
+                        Direction::Rising => "CO2 rising",
+                        Direction::Steady => "CO2 steady",
+                        Direction::Falling => "CO2 falling",
+                    })
+                    .ok();
+            }
+        }
+        message
+    }
+}
+
+/// The last `N` CO2 readings, taken at least `interval` seconds apart
+pub struct Co2Trend<const N: usize> {
+    samples: [(u32, f32); N],
+    start: usize,
+    len: usize,
+    interval: u32,
+}
+
+/// Ten minutes worth of readings, every 30 seconds
+pub type DefaultCo2Trend = Co2Trend<20>;
+
+const DEFAULT_INTERVAL: u32 = 30;
+
+impl<const N: usize> Co2Trend<N> {
+    pub const fn new(interval: u32) -> Self {
+        Co2Trend {
+            samples: [(0, 0.); N],
+            start: 0,
+            len: 0,
+            interval,
+        }
+    }
+
+    /// Add a reading taken at `now` seconds since boot. Readings that come
+    /// sooner than `interval` after the previous one are ignored.
+    pub fn add(&mut self, now: u32, co2: f32) {
+        if let Some((last, _)) = self.newest() {
+            // Wrapping, because the seconds counter is allowed to overflow
+            if now.wrapping_sub(last) < self.interval {
+                return;
+            }
+        }
+        if self.len < N {
+            self.samples[(self.start + self.len) % N] = (now, co2);
+            self.len += 1;
+        } else {
+            self.samples[self.start] = (now, co2);
+            self.start = (self.start + 1) % N;
+        }
+    }
+
+    fn newest(&self) -> Option<(u32, f32)> {
+        (self.len > 0).then(|| self.samples[(self.start + self.len - 1) % N])
+    }
+
+    /// Samples as (minutes relative to the newest sample, value)
+    fn points(&self) -> impl Iterator<Item = (f32, f32)> + '_ {
+        let newest = self.newest().map_or(0, |(time, _)| time);
+        (0..self.len).map(move |i| {
+            let (time, value) = self.samples[(self.start + i) % N];
+            (-(newest.wrapping_sub(time) as f32) / 60., value)
+        })
+    }
+
+    /// Least squares fit of `y = slope * x + intercept`
+    fn fit(points: impl Iterator<Item = (f32, f32)>) -> Option<(f32, f32)> {
+        let (mut n, mut sx, mut sy, mut sxx, mut sxy) = (0., 0., 0., 0., 0.);
+        for (x, y) in points {
+            n += 1.;
+            sx += x;
+            sy += y;
+            sxx += x * x;
+            sxy += x * y;
+        }
+        let denominator = n * sxx - sx * sx;
+        if n < 3. || denominator.abs() < f32::EPSILON {
+            return None;
+        }
+        let slope = (n * sxy - sx * sy) / denominator;
+        Some((slope, (sy - slope * sx) / n))
+    }
+
+    /// CO2 change in ppm per minute and the fitted current value
+    pub fn slope(&self) -> Option<(f32, f32)> {
+        Self::fit(self.points())
+    }
+
+    /// Fit the decay towards `outdoor` ppm, in air changes per hour
+    pub fn air_change_rate(&self, outdoor: f32) -> Option<f32> {
+        if self.points().any(|(_, value)| value - outdoor < MIN_EXCESS) {
+            return None;
+        }
+        let (slope, _) = Self::fit(
+            self.points()
+                .map(|(minutes, value)| (minutes, (value - outdoor).ln())),
+        )?;
+        (slope < 0.).then_some(-slope * 60.)
+    }
+
+    pub fn advise(&self, threshold: f32, outdoor: f32) -> Option<Advice> {
+        let (slope, current) = self.slope()?;
+        let direction = if slope > STEADY_SLOPE {
+            Direction::Rising
+        } else if slope < -STEADY_SLOPE {
+            Direction::Falling
+        } else {
+            Direction::Steady
+        };
+        let time_to_threshold = match direction {
+            // Someone's already on it
+            Direction::Falling => None,
+            _ if current >= threshold => Some(0.),
+            Direction::Rising => Some((threshold - current) / slope),
+            Direction::Steady => None,
+        };
+        let air_change_rate = match direction {
+            Direction::Falling => self.air_change_rate(outdoor),
+            _ => None,
+        };
+        Some(Advice {
+            direction,
+            slope,
+            time_to_threshold,
+            air_change_rate,
+        })
+    }
+}
+
+impl<const N: usize> Default for Co2Trend<N> {
+    fn default() -> Self {
+        Self::new(DEFAULT_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Advice, Co2Trend, Direction};
+    use micromath::F32Ext;
+
+    fn assert_close(value: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (value - expected).abs() < tolerance,
+            "{} != {}",
+            value,
+            expected
+        );
+    }
+
+    pub fn rising() {
+        let mut trend: Co2Trend<10> = Co2Trend::new(30);
+        for i in 0..10 {
+            trend.add(i * 60, 800. + i as f32 * 10.);
+            // Ignored, too soon after the previous one
+            trend.add(i * 60 + 10, 2000.);
+        }
+        let advice = trend.advise(1000., 420.).unwrap();
+        assert_eq!(advice.direction, Direction::Rising);
+        assert_close(advice.slope, 10., 0.01);
+        // 890 now, so 11 more minutes
+        assert_close(advice.time_to_threshold.unwrap(), 11., 0.01);
+        assert_eq!(advice.air_change_rate, None);
+        assert_eq!(advice.message().as_str(), "Ventilate in 11m");
+    }
+
+    pub fn decay() {
+        let mut trend: Co2Trend<10> = Co2Trend::new(30);
+        for i in 0..10 {
+            let minutes = i as f32;
+            // Three air changes per hour
+            let co2 = 420. + 1000. * (-3. * minutes / 60.).exp();
+            trend.add(i * 60, co2);
+        }
+        let advice = trend.advise(2000., 420.).unwrap();
+        assert_eq!(advice.direction, Direction::Falling);
+        assert_eq!(advice.time_to_threshold, None);
+        assert_close(advice.air_change_rate.unwrap(), 3., 0.1);
+        assert_eq!(advice.message().as_str(), "Air change 3.0/h");
+    }
+
+    pub fn steady_and_above() {
+        let mut trend: Co2Trend<10> = Co2Trend::new(30);
+        assert_eq!(trend.advise(1000., 420.), None);
+        for i in 0..5 {
+            trend.add(i * 60, 1200.);
+        }
+        let advice = trend.advise(1000., 420.).unwrap();
+        assert_eq!(advice.direction, Direction::Steady);
+        assert_eq!(advice.time_to_threshold, Some(0.));
+        assert_eq!(advice.message().as_str(), "Ventilate now");
+        let advice = trend.advise(1500., 420.).unwrap();
+        assert_eq!(advice.message().as_str(), "CO2 steady");
+    }
+
+    pub fn messages_fit() {
+        let advice = |time_to_threshold, air_change_rate| Advice {
+            direction: Direction::Falling,
+            slope: -20.,
+            time_to_threshold,
+            air_change_rate,
+        };
+        // A String<16> would cut anything longer off, so the whole message
+        // has to be there
+        for (time_to_threshold, air_change_rate, expected) in [
+            (Some(99.4), None, "Ventilate in 99m"),
+            (None, Some(9.94), "Air change 9.9/h"),
+            (None, Some(12.3), "Air change 12/h"),
+            (Some(1e6), Some(1e6), "Air change 99/h"),
+            (None, Some(9.96), "Air change 10/h"),
+            (None, Some(99.6), "Air change 99/h"),
+            (None, None, "CO2 falling"),
+        ] {
+            let message = advice(time_to_threshold, air_change_rate).message();
+            assert_eq!(message.as_str(), expected);
+            assert!(message.len() <= 16);
+        }
+    }
+}
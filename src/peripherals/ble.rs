@@ -0,0 +1,774 @@
+//! Bluetooth LE straight on the nRF's radio, without a stack
+//!
+//! Just enough of the link layer for a peripheral that advertises and takes
+//! one connection at a time, for the [GATT server](crate::logic::gatt). It
+//! sends connectable undirected advertisements on all three advertising
+//! channels, from the chip's random static address, and answers scan
+//! requests with the device's name. Once a central connects, it follows
+//! channel selection algorithm #1 and exchanges one packet per connection
+//! event, which leaves out encryption, data length extension and the other
+//! optional procedures. The central gets told so when it asks for them.
+//!
+//! Everything happens in the RADIO and TIMER4 interrupts, which have to call
+//! [`Ble::on_radio`] and [`Ble::on_timer`]. TIMER4 keeps time in µs, and PPI
+//! channels 0 and 1 start the radio at the right time and capture when
+//! packets arrive. The radio needs the 32 MHz crystal oscillator to be
+//! running.
+
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use nrf52840_hal::pac::{FICR, PPI, RADIO, TIMER4};
+
+use crate::logic::{
+    gatt::{self, Server},
+    link::{self, ChannelMapUpdate, ConnectRequest, ConnectionUpdate},
+    measurement::Measurement,
+    settings::Settings,
+};
+
+/// Advertising channels and their frequencies, MHz above 2400
+const ADVERTISING_CHANNELS: [(u8, u8); 3] = [(37, 2), (38, 26), (39, 80)];
+/// The access address of all advertising packets
+const ADVERTISING_ACCESS_ADDRESS: u32 = 0x8e89_bed6;
+const ADVERTISING_CRC_INIT: u32 = 0x55_5555;
+/// x²⁴ + x¹⁰ + x⁹ + x⁶ + x⁴ + x³ + x + 1
+const CRC_POLYNOMIAL: u32 = 0x00_065b;
+/// Between advertising events, plus up to 10 ms at random
+const ADVERTISING_INTERVAL: u32 = 152_500;
+/// How long to listen for a request after an advertisement, which comes
+/// 150 µs after it if it comes
+const ADVERTISING_LISTEN: u32 = 400;
+
+/// Advertising PDU types
+const ADV_IND: u8 = 0x0;
+const SCAN_REQ: u8 = 0x3;
+const SCAN_RSP: u8 = 0x4;
+const CONNECT_IND: u8 = 0x5;
+/// In the header of advertising PDUs, for a random address of the sender and
+/// of the receiver
+const TX_ADD: u8 = 0x40;
+const RX_ADD: u8 = 0x80;
+
+/// Data PDU LLIDs
+const CONTINUATION: u8 = 0x1;
+const START: u8 = 0x2;
+const CONTROL: u8 = 0x3;
+
+/// LL control opcodes
+const CONNECTION_UPDATE_IND: u8 = 0x00;
+const CHANNEL_MAP_IND: u8 = 0x01;
+const TERMINATE_IND: u8 = 0x02;
+const ENC_REQ: u8 = 0x03;
+const UNKNOWN_RSP: u8 = 0x07;
+const FEATURE_REQ: u8 = 0x08;
+const FEATURE_RSP: u8 = 0x09;
+const VERSION_IND: u8 = 0x0c;
+const REJECT_IND: u8 = 0x0d;
+const REJECT_EXT_IND: u8 = 0x11;
+const PING_REQ: u8 = 0x12;
+const PING_RSP: u8 = 0x13;
+/// Bluetooth 5.0, and the company ID for devices that don't have one
+const VERSION: [u8; 5] = [0x09, 0xff, 0xff, 0x00, 0x00];
+/// Unsupported Remote Feature
+const UNSUPPORTED: u8 = 0x1a;
+
+/// L2CAP channels
+const ATT: u16 = 0x0004;
+const SIGNALING: u16 = 0x0005;
+const SECURITY_MANAGER: u16 = 0x0006;
+
+const ADDRESS_LENGTH: usize = 6;
+/// The most advertising data that fits in a legacy advertisement
+pub const MAX_DATA: usize = 31;
+/// The longest payload of a data PDU without data length extension
+const MAX_PAYLOAD: usize = 27;
+/// Longest PDU payload, the one of a CONNECT_IND
+const MAX_PDU_PAYLOAD: usize = 37;
+
+/// With fast ramp-up
+const RAMP_UP: u32 = 40;
+/// Preamble and access address, from the start of a packet to the radio's
+/// ADDRESS event
+const ADDRESS_TIME: u32 = 40;
+/// Sleep clock accuracy of the timer, which runs off the crystal oscillator
+const CLOCK_ACCURACY: u32 = 50;
+/// Least time for setting up the radio before a connection event
+const LEAD_TIME: u32 = 100;
+
+type Payload = heapless::Vec<u8, MAX_PAYLOAD>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for the next advertising event
+    Idle,
+    /// Sending the advertisement on the channel with this index
+    Advertising(usize),
+    /// Listening for a scan or connection request after it
+    Listening(usize),
+    ScanResponse(usize),
+    /// Connected, between connection events
+    Connected,
+    /// Listening for the central's packet in a connection event
+    Receiving,
+    /// Answering it
+    Transmitting,
+}
+
+/// Times are µs on TIMER4, which wraps around
+struct Connection {
+    access_address: u32,
+    crc_init: u32,
+    interval: u32,
+    /// Supervision timeout
+    timeout: u32,
+    /// Used data channels, bit n for channel n
+    channel_map: u64,
+    hop: u8,
+    unmapped_channel: u8,
+    channel: u8,
+    central_accuracy: u32,
+    counter: u16,
+    /// When the next connection event starts, as far as is known
+    anchor: u32,
+    /// The last anchor that a packet from the central was seen at
+    synced: u32,
+    /// How long the central may start late on top of the window widening,
+    /// the transmit window at the start of a connection or after an update
+    window: u32,
+    /// Our next packet's sequence number, and the central's that we expect
+    sequence: bool,
+    expected: bool,
+    /// Whether the last packet was an empty one, rather than the front of the
+    /// queue
+    sent_empty: bool,
+    /// Parameters that change at an instant, a connection event counter
+    /// value
+    update: Option<ConnectionUpdate>,
+    new_channel_map: Option<ChannelMapUpdate>,
+    terminate: bool,
+}
+
+impl Connection {
+    /// Channel selection algorithm #1
+    fn next_channel(&mut self) {
+        self.unmapped_channel = (self.unmapped_channel + self.hop) % 37;
+        self.channel = link::data_channel(self.unmapped_channel, self.channel_map);
+    }
+
+    /// How much earlier and later than the anchor the central's packet may
+    /// come, because of the accuracy of both clocks
+    fn widening(&self) -> u32 {
+        let elapsed = u64::from(self.anchor.wrapping_sub(self.synced));
+        let accuracy = u64::from(self.central_accuracy + CLOCK_ACCURACY);
+        let widening = (accuracy * elapsed / 1_000_000) as u32 + 16;
+        widening.min(self.interval / 2 - 150)
+    }
+
+    /// Moves on to the next connection event, with whatever parameters
+    /// change at it
+    fn advance(&mut self) {
+        self.counter = self.counter.wrapping_add(1);
+        self.anchor = self.anchor.wrapping_add(self.interval);
+        if let Some(update) = self.update.filter(|update| update.instant == self.counter) {
+            self.interval = update.interval;
+            self.timeout = update.timeout;
+            self.anchor = self.anchor.wrapping_add(update.window_offset);
+            self.window = update.window_size;
+            self.update = None;
+        }
+        if let Some(update) = self
+            .new_channel_map
+            .filter(|update| update.instant == self.counter)
+        {
+            self.channel_map = update.channel_map;
+            self.new_channel_map = None;
+        }
+        self.next_channel();
+    }
+}
+
+pub struct Ble {
+    radio: RADIO,
+    timer: TIMER4,
+    ppi: PPI,
+    state: State,
+    address: [u8; ADDRESS_LENGTH],
+    advertising_data: heapless::Vec<u8, MAX_DATA>,
+    connection: Option<Connection>,
+    server: Server,
+    /// Waiting to go out, in order
+    queue: heapless::Deque<(u8, Payload), 8>,
+    /// A packet from the central that still has to be dealt with
+    received: Option<(u8, Payload)>,
+    /// For the random part of the advertising interval
+    random: u32,
+    /// Header, length and payload of the PDUs, which the radio reads and
+    /// writes through DMA
+    advertisement: [u8; 2 + MAX_PDU_PAYLOAD],
+    scan_response: [u8; 2 + MAX_PDU_PAYLOAD],
+    transmit: [u8; 2 + MAX_PDU_PAYLOAD],
+    receive: [u8; 2 + MAX_PDU_PAYLOAD],
+}
+
+impl Ble {
+    /// Sets up the radio, TIMER4 and the PPI channels, and serves `settings`
+    /// in the configuration characteristic. Nothing happens until
+    /// [`start`](Ble::start).
+    pub fn new(radio: RADIO, timer: TIMER4, ppi: PPI, ficr: &FICR, settings: Settings) -> Self {
+        // The factory programmed address is random, its top two bits have to
+        // be set for it to count as static
+        let low = ficr.deviceaddr[0].read().bits().to_le_bytes();
+        let high = ficr.deviceaddr[1].read().bits().to_le_bytes();
+        let address = [low[0], low[1], low[2], low[3], high[0], high[1] | 0xc0];
+
+        radio.mode.write(|w| w.mode().ble_1mbit());
+        radio.modecnf0.write(|w| w.ru().fast());
+        radio.txpower.write(|w| w.txpower()._0d_bm());
+        // An 8 bit header (S0), an 8 bit length and no S1
+        radio
+            .pcnf0
+            .write(|w| unsafe { w.s0len().bit(true).lflen().bits(8).s1len().bits(0) });
+        radio.pcnf1.write(|w| unsafe {
+            w.maxlen()
+                .bits(MAX_PDU_PAYLOAD as u8)
+                .balen()
+                .bits(3)
+                .endian()
+                .little()
+                .whiteen()
+                .enabled()
+        });
+        radio.txaddress.write(|w| unsafe { w.txaddress().bits(0) });
+        radio.rxaddresses.write(|w| w.addr0().enabled());
+        radio.crccnf.write(|w| w.len().three().skipaddr().skip());
+        radio
+            .crcpoly
+            .write(|w| unsafe { w.crcpoly().bits(CRC_POLYNOMIAL) });
+        radio.tifs.write(|w| unsafe { w.tifs().bits(150) });
+        radio.intenset.write(|w| w.ready().set().disabled().set());
+
+        // 1 MHz, 32 bits. CC0 starts receiving, CC1 captures when a packet's
+        // address goes by, CC2 is for alarms and CC5 for reading the time.
+        timer.mode.write(|w| w.mode().timer());
+        timer.bitmode.write(|w| w.bitmode()._32bit());
+        timer.prescaler.write(|w| unsafe { w.prescaler().bits(4) });
+        timer.intenset.write(|w| w.compare2().set());
+        ppi.ch[0]
+            .eep
+            .write(|w| unsafe { w.bits(&timer.events_compare[0] as *const _ as u32) });
+        ppi.ch[0]
+            .tep
+            .write(|w| unsafe { w.bits(&radio.tasks_rxen as *const _ as u32) });
+        ppi.ch[1]
+            .eep
+            .write(|w| unsafe { w.bits(&radio.events_address as *const _ as u32) });
+        ppi.ch[1]
+            .tep
+            .write(|w| unsafe { w.bits(&timer.tasks_capture[1] as *const _ as u32) });
+        ppi.chenset.write(|w| w.ch1().set());
+
+        let mut scan_response = [0; 2 + MAX_PDU_PAYLOAD];
+        scan_response[0] = SCAN_RSP | TX_ADD;
+        scan_response[2..2 + ADDRESS_LENGTH].copy_from_slice(&address);
+        let mut length = 2 + ADDRESS_LENGTH;
+        // The complete local name, and the Environmental Sensing service
+        for field in [
+            &[gatt::DEVICE_NAME.len() as u8 + 1, 0x09][..],
+            gatt::DEVICE_NAME.as_bytes(),
+            &[3, 0x03, 0x1a, 0x18],
+        ] {
+            scan_response[length..length + field.len()].copy_from_slice(field);
+            length += field.len();
+        }
+        scan_response[1] = (length - 2) as u8;
+
+        Ble {
+            radio,
+            timer,
+            ppi,
+            state: State::Idle,
+            address,
+            advertising_data: heapless::Vec::new(),
+            connection: None,
+            server: Server::new(settings),
+            queue: heapless::Deque::new(),
+            received: None,
+            random: u32::from_le_bytes([low[0], low[1], low[2], low[3]]) | 1,
+            advertisement: [0; 2 + MAX_PDU_PAYLOAD],
+            scan_response,
+            transmit: [0; 2 + MAX_PDU_PAYLOAD],
+            receive: [0; 2 + MAX_PDU_PAYLOAD],
+        }
+    }
+
+    /// Starts advertising. The RADIO and TIMER4 interrupts have to be
+    /// unmasked after.
+    pub fn start(&mut self) {
+        self.timer.tasks_clear.write(|w| unsafe { w.bits(1) });
+        self.timer.tasks_start.write(|w| unsafe { w.bits(1) });
+        self.alarm(self.now().wrapping_add(1000));
+    }
+
+    /// The address the advertisements come from, most significant byte first
+    /// like it usually gets written down
+    pub fn address(&self) -> [u8; ADDRESS_LENGTH] {
+        let mut address = self.address;
+        address.reverse();
+        address
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    /// What goes into the advertisements from the next advertising event on.
+    /// Anything beyond [`MAX_DATA`] bytes gets cut off.
+    pub fn set_advertising_data(&mut self, data: &[u8]) {
+        self.advertising_data.clear();
+        self.advertising_data
+            .extend_from_slice(&data[..data.len().min(MAX_DATA)])
+            .unwrap();
+    }
+
+    /// Serves `measurement`, and notifies the central of the values that
+    /// changed if it asked for that
+    pub fn update(&mut self, measurement: &Measurement) {
+        for notification in self.server.update(measurement) {
+            if self.is_connected() {
+                self.send_l2cap(ATT, &notification);
+            }
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.server.set_settings(settings);
+    }
+
+    /// Settings that the central wrote, once
+    pub fn take_settings(&mut self) -> Option<Settings> {
+        self.server.take_written()
+    }
+
+    pub fn on_radio(&mut self) {
+        if self.radio.events_ready.read().bits() != 0 {
+            self.radio.events_ready.reset();
+            // The advertisement has started going out, and the radio can
+            // take the buffer for what comes back
+            if let State::Advertising(_) = self.state {
+                self.set_packet(&self.receive);
+            }
+        }
+        if self.radio.events_disabled.read().bits() == 0 {
+            return;
+        }
+        self.radio.events_disabled.reset();
+        match self.state {
+            State::Advertising(channel) => {
+                // Receiving, and sending the scan response if the request
+                // asks for one
+                self.radio.shorts.write(|w| {
+                    w.ready_start()
+                        .enabled()
+                        .end_disable()
+                        .enabled()
+                        .disabled_txen()
+                        .enabled()
+                });
+                self.radio.events_address.reset();
+                self.alarm(self.now().wrapping_add(ADVERTISING_LISTEN));
+                self.state = State::Listening(channel);
+            }
+            State::Listening(channel) => self.on_request(channel),
+            State::ScanResponse(channel) => self.next_advertising_channel(channel),
+            State::Receiving => self.on_data(),
+            State::Transmitting => {
+                self.state = State::Connected;
+                if let Some((llid, payload)) = self.received.take() {
+                    self.process(llid, &payload);
+                }
+                self.close_event();
+            }
+            State::Idle | State::Connected => {}
+        }
+    }
+
+    pub fn on_timer(&mut self) {
+        self.timer.events_compare[2].reset();
+        // Nothing counts as missed once the central's packet is coming in
+        let missed = self.radio.events_address.read().bits() == 0;
+        match self.state {
+            State::Idle => self.advertise(0),
+            State::Listening(channel) if missed => {
+                self.disable();
+                self.next_advertising_channel(channel);
+            }
+            State::Receiving if missed => {
+                self.disable();
+                self.state = State::Connected;
+                self.close_event();
+            }
+            _ => {}
+        }
+    }
+
+    fn now(&self) -> u32 {
+        self.timer.tasks_capture[5].write(|w| unsafe { w.bits(1) });
+        self.timer.cc[5].read().bits()
+    }
+
+    /// Interrupts at `time`
+    fn alarm(&self, time: u32) {
+        self.timer.events_compare[2].reset();
+        self.timer.cc[2].write(|w| unsafe { w.bits(time) });
+    }
+
+    fn set_packet(&self, buffer: &[u8; 2 + MAX_PDU_PAYLOAD]) {
+        self.radio
+            .packetptr
+            .write(|w| unsafe { w.bits(buffer.as_ptr() as u32) });
+        // The radio has to see the packet before it starts reading it
+        compiler_fence(Ordering::SeqCst);
+    }
+
+    fn set_channel(&self, channel: u8, frequency: u8, access_address: u32, crc_init: u32) {
+        self.radio
+            .frequency
+            .write(|w| unsafe { w.frequency().bits(frequency) });
+        self.radio
+            .datawhiteiv
+            .write(|w| unsafe { w.datawhiteiv().bits(channel) });
+        self.radio
+            .base0
+            .write(|w| unsafe { w.bits(access_address << 8) });
+        self.radio
+            .prefix0
+            .write(|w| unsafe { w.ap0().bits((access_address >> 24) as u8) });
+        self.radio
+            .crcinit
+            .write(|w| unsafe { w.crcinit().bits(crc_init) });
+    }
+
+    /// Turns the radio off right away, whatever it's doing
+    fn disable(&self) {
+        self.ppi.chenclr.write(|w| w.ch0().clear());
+        self.radio.shorts.reset();
+        self.radio.tasks_disable.write(|w| unsafe { w.bits(1) });
+        while !self.radio.state.read().state().is_disabled() {}
+        self.radio.events_disabled.reset();
+        self.radio.events_ready.reset();
+    }
+
+    /// Sends the advertisement on the advertising channel with `index`
+    fn advertise(&mut self, index: usize) {
+        if index == 0 {
+            self.advertisement[0] = ADV_IND | TX_ADD;
+            self.advertisement[1] = (ADDRESS_LENGTH + self.advertising_data.len()) as u8;
+            self.advertisement[2..2 + ADDRESS_LENGTH].copy_from_slice(&self.address);
+            self.advertisement[2 + ADDRESS_LENGTH..][..self.advertising_data.len()]
+                .copy_from_slice(&self.advertising_data);
+        }
+        let (channel, frequency) = ADVERTISING_CHANNELS[index];
+        self.set_channel(
+            channel,
+            frequency,
+            ADVERTISING_ACCESS_ADDRESS,
+            ADVERTISING_CRC_INIT,
+        );
+        self.set_packet(&self.advertisement);
+        // Listen right after
+        self.radio.shorts.write(|w| {
+            w.ready_start()
+                .enabled()
+                .end_disable()
+                .enabled()
+                .disabled_rxen()
+                .enabled()
+        });
+        self.radio.tasks_txen.write(|w| unsafe { w.bits(1) });
+        self.state = State::Advertising(index);
+    }
+
+    fn next_advertising_channel(&mut self, index: usize) {
+        if index + 1 < ADVERTISING_CHANNELS.len() {
+            self.advertise(index + 1);
+        } else {
+            // xorshift
+            self.random ^= self.random << 13;
+            self.random ^= self.random >> 17;
+            self.random ^= self.random << 5;
+            let delay = ADVERTISING_INTERVAL + self.random % 10_000;
+            self.alarm(self.now().wrapping_add(delay));
+            self.state = State::Idle;
+        }
+    }
+
+    /// A packet came in after an advertisement, and the radio is getting
+    /// ready to answer it
+    fn on_request(&mut self, index: usize) {
+        let header = self.receive[0];
+        let length = usize::from(self.receive[1]);
+        let ok = self.radio.crcstatus.read().crcstatus().is_crcok()
+            && header & RX_ADD != 0
+            && self.receive[2 + ADDRESS_LENGTH..2 + 2 * ADDRESS_LENGTH] == self.address;
+        match header & 0x0f {
+            SCAN_REQ if ok && length == 2 * ADDRESS_LENGTH => {
+                self.set_packet(&self.scan_response);
+                self.radio
+                    .shorts
+                    .write(|w| w.ready_start().enabled().end_disable().enabled());
+                self.state = State::ScanResponse(index);
+            }
+            CONNECT_IND if ok && length == 34 => {
+                self.disable();
+                let end = self.timer.cc[1].read().bits() + (2 + 34 + 3) * 8;
+                self.connect(end);
+            }
+            _ => {
+                self.disable();
+                self.next_advertising_channel(index);
+            }
+        }
+    }
+
+    /// Takes the parameters from the CONNECT_IND in the receive buffer that
+    /// ended at `end`
+    fn connect(&mut self, end: u32) {
+        // A central that sends nonsense doesn't get connected
+        let Some(request) = ConnectRequest::parse(&self.receive[2..2 + ConnectRequest::PAYLOAD_SIZE])
+        else {
+            defmt::warn!("Bluetooth connection request refused");
+            self.next_advertising_channel(ADVERTISING_CHANNELS.len());
+            return;
+        };
+        let mut connection = Connection {
+            access_address: request.access_address,
+            crc_init: request.crc_init,
+            interval: request.interval,
+            timeout: request.timeout,
+            channel_map: request.channel_map,
+            hop: request.hop,
+            unmapped_channel: 0,
+            channel: 0,
+            central_accuracy: request.central_accuracy,
+            counter: 0,
+            anchor: end.wrapping_add(1250 + request.window_offset),
+            synced: end,
+            window: request.window_size,
+            sequence: false,
+            expected: false,
+            sent_empty: true,
+            update: None,
+            new_channel_map: None,
+            terminate: false,
+        };
+        connection.next_channel();
+        defmt::info!("Bluetooth connected");
+        self.connection = Some(connection);
+        self.state = State::Connected;
+        self.schedule();
+    }
+
+    /// Gets the radio to listen at the next connection event that there's
+    /// still time for
+    fn schedule(&mut self) {
+        let now = self.now();
+        let Some(connection) = self.connection.as_mut() else {
+            return;
+        };
+        let start = loop {
+            let start = connection
+                .anchor
+                .wrapping_sub(connection.widening() + RAMP_UP);
+            if start.wrapping_sub(now) as i32 > LEAD_TIME as i32 {
+                break start;
+            }
+            connection.advance();
+        };
+        let channel = connection.channel;
+        let frequency = link::frequency(channel);
+        let timeout = connection
+            .anchor
+            .wrapping_add(connection.widening() + connection.window + ADDRESS_TIME + RAMP_UP);
+        let (access_address, crc_init) = (connection.access_address, connection.crc_init);
+        self.set_channel(channel, frequency, access_address, crc_init);
+        self.set_packet(&self.receive);
+        // Answer as soon as the central's packet is in
+        self.radio.shorts.write(|w| {
+            w.ready_start()
+                .enabled()
+                .end_disable()
+                .enabled()
+                .disabled_txen()
+                .enabled()
+        });
+        self.radio.events_address.reset();
+        self.timer.events_compare[0].reset();
+        self.timer.cc[0].write(|w| unsafe { w.bits(start) });
+        self.ppi.chenset.write(|w| w.ch0().set());
+        self.alarm(timeout);
+        self.state = State::Receiving;
+    }
+
+    /// The central's packet is in, and the radio is getting ready to answer
+    /// it. This has to be quick, the answer goes out 150 µs after.
+    fn on_data(&mut self) {
+        self.ppi.chenclr.write(|w| w.ch0().clear());
+        let ok = self.radio.crcstatus.read().crcstatus().is_crcok();
+        let address_time = self.timer.cc[1].read().bits();
+        let Some(connection) = self.connection.as_mut() else {
+            return;
+        };
+        let header = self.receive[0];
+        let length = usize::from(self.receive[1]).min(MAX_PAYLOAD);
+        let mut acknowledged = false;
+        if ok {
+            connection.anchor = address_time.wrapping_sub(ADDRESS_TIME);
+            connection.synced = connection.anchor;
+            connection.window = 0;
+            acknowledged = (header & 0x04 != 0) != connection.sequence;
+            if acknowledged {
+                connection.sequence = !connection.sequence;
+                if !connection.sent_empty {
+                    self.queue.pop_front();
+                }
+            }
+            if (header & 0x08 != 0) == connection.expected {
+                connection.expected = !connection.expected;
+                if length > 0 {
+                    let payload = Payload::from_slice(&self.receive[2..2 + length]).unwrap();
+                    self.received = Some((header & 0x03, payload));
+                }
+            }
+        }
+        // A packet that wasn't acknowledged goes out again as it was
+        if acknowledged {
+            connection.sent_empty = self.queue.is_empty();
+        }
+        let (llid, payload) = match self.queue.front() {
+            Some((llid, payload)) if !connection.sent_empty => (*llid, &payload[..]),
+            _ => (CONTINUATION, &[][..]),
+        };
+        self.transmit[0] =
+            llid | u8::from(connection.expected) << 2 | u8::from(connection.sequence) << 3;
+        self.transmit[1] = payload.len() as u8;
+        self.transmit[2..2 + payload.len()].copy_from_slice(payload);
+        self.set_packet(&self.transmit);
+        self.radio
+            .shorts
+            .write(|w| w.ready_start().enabled().end_disable().enabled());
+        self.state = State::Transmitting;
+    }
+
+    /// After a connection event, whether anything came in or not
+    fn close_event(&mut self) {
+        let now = self.now();
+        let Some(connection) = self.connection.as_mut() else {
+            return;
+        };
+        let silent = now.wrapping_sub(connection.synced);
+        if connection.terminate || silent > connection.timeout {
+            self.disconnect();
+        } else {
+            connection.advance();
+            self.schedule();
+        }
+    }
+
+    fn disconnect(&mut self) {
+        defmt::info!("Bluetooth disconnected");
+        self.disable();
+        self.connection = None;
+        self.queue.clear();
+        self.received = None;
+        self.server.disconnected();
+        self.alarm(self.now().wrapping_add(1000));
+        self.state = State::Idle;
+    }
+
+    fn send(&mut self, llid: u8, payload: &[u8]) {
+        let payload = Payload::from_slice(payload).unwrap();
+        if self.queue.push_back((llid, payload)).is_err() {
+            defmt::warn!("Bluetooth queue full");
+        }
+    }
+
+    fn send_l2cap(&mut self, channel: u16, data: &[u8]) {
+        let mut payload = Payload::new();
+        payload
+            .extend_from_slice(&(data.len() as u16).to_le_bytes())
+            .unwrap();
+        payload.extend_from_slice(&channel.to_le_bytes()).unwrap();
+        payload.extend_from_slice(data).unwrap();
+        self.send(START, &payload);
+    }
+
+    /// Deals with a packet from the central, once there's time
+    fn process(&mut self, llid: u8, payload: &[u8]) {
+        match llid {
+            CONTROL => self.control(payload),
+            // L2CAP frames are never longer than a packet with the default MTU
+            START if payload.len() >= 4 => {
+                let length = usize::from(u16::from_le_bytes([payload[0], payload[1]]));
+                let channel = u16::from_le_bytes([payload[2], payload[3]]);
+                let data = &payload[4..];
+                if data.len() != length || data.is_empty() {
+                    return;
+                }
+                match channel {
+                    ATT => {
+                        if let Some(response) = self.server.handle(data) {
+                            self.send_l2cap(ATT, &response);
+                        }
+                    }
+                    // Command Reject, Command Not Understood, for anything
+                    // but a rejection or a response to something not sent
+                    SIGNALING if data.len() >= 2 && !matches!(data[0], 0x01 | 0x13) => {
+                        self.send_l2cap(SIGNALING, &[0x01, data[1], 2, 0, 0, 0]);
+                    }
+                    // Pairing Failed, Pairing Not Supported
+                    SECURITY_MANAGER if data[0] == 0x01 => {
+                        self.send_l2cap(SECURITY_MANAGER, &[0x05, 0x05]);
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn control(&mut self, payload: &[u8]) {
+        let Some(connection) = self.connection.as_mut() else {
+            return;
+        };
+        match payload[0] {
+            // Parameters that the connection can't be kept up with end it
+            CONNECTION_UPDATE_IND => match ConnectionUpdate::parse(payload) {
+                Some(update) => connection.update = Some(update),
+                None => {
+                    defmt::warn!("Bluetooth connection update refused");
+                    connection.terminate = true;
+                }
+            },
+            CHANNEL_MAP_IND => match ChannelMapUpdate::parse(payload) {
+                Some(update) => connection.new_channel_map = Some(update),
+                None => {
+                    defmt::warn!("Bluetooth channel map refused");
+                    connection.terminate = true;
+                }
+            },
+            TERMINATE_IND => connection.terminate = true,
+            // No LE features, no encryption
+            FEATURE_REQ => self.send(CONTROL, &[FEATURE_RSP, 0, 0, 0, 0, 0, 0, 0, 0]),
+            ENC_REQ => self.send(CONTROL, &[REJECT_IND, UNSUPPORTED]),
+            VERSION_IND => {
+                let mut version = [VERSION_IND; 6];
+                version[1..].copy_from_slice(&VERSION);
+                self.send(CONTROL, &version);
+            }
+            PING_REQ => self.send(CONTROL, &[PING_RSP]),
+            // Answers to what's never asked
+            UNKNOWN_RSP | FEATURE_RSP | REJECT_IND | REJECT_EXT_IND | PING_RSP => {}
+            opcode => self.send(CONTROL, &[UNKNOWN_RSP, opcode]),
+        }
+    }
+}
@@ -1,8 +1,21 @@
 use embedded_hal::digital::v2::InputPin;
 
+/// What [`Button::check_press`] detected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Press {
+    /// The button was released before the long press threshold
+    Short,
+    /// The button has been held down for the long press threshold. Reported
+    /// once, while it's still held, so that there's feedback without letting
+    /// go.
+    Long,
+}
+
 pub struct Button<P> {
     pin: P,
     was_pressed: bool,
+    // Number of consecutive checks during which the button was pressed
+    held_for: u32,
 }
 
 impl<P> Button<P>
@@ -17,6 +30,7 @@ where
         Button {
             pin,
             was_pressed: false,
+            held_for: 0,
         }
     }
 
@@ -31,4 +45,18 @@ where
         self.was_pressed = is_pressed;
         rising_edge
     }
+
+    /// Tell apart short and long presses. Must be called periodically, a long
+    /// press is one that lasts for `long_press_checks` calls.
+    pub fn check_press(&mut self, long_press_checks: u32) -> Option<Press> {
+        let is_pressed = self.is_pressed();
+        let was_pressed = self.was_pressed;
+        self.was_pressed = is_pressed;
+        if is_pressed {
+            self.held_for = self.held_for.saturating_add(1);
+            return (self.held_for == long_press_checks).then_some(Press::Long);
+        }
+        let held_for = core::mem::replace(&mut self.held_for, 0);
+        (was_pressed && held_for < long_press_checks).then_some(Press::Short)
+    }
 }
@@ -0,0 +1,42 @@
+use crate::future::pwm::SetDutyCycle;
+
+/// A piezo buzzer on a PWM channel. The pitch is set by the period of the
+/// PWM, the buzzer is switched on by running it at 50% duty cycle.
+pub struct Buzzer<P> {
+    channel: P,
+    is_on: bool,
+}
+
+impl<P> Buzzer<P>
+where
+    P: SetDutyCycle,
+{
+    pub fn new(mut channel: P) -> Result<Self, P::Error> {
+        channel.set_duty_cycle_fully_off()?;
+        Ok(Buzzer {
+            channel,
+            is_on: false,
+        })
+    }
+
+    pub fn set(&mut self, on: bool) -> Result<(), P::Error> {
+        if on == self.is_on {
+            return Ok(());
+        }
+        if on {
+            self.channel.set_duty_cycle_percent(50)?;
+        } else {
+            self.channel.set_duty_cycle_fully_off()?;
+        }
+        self.is_on = on;
+        Ok(())
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.is_on
+    }
+
+    pub fn free(self) -> P {
+        self.channel
+    }
+}
@@ -0,0 +1,261 @@
+//! Displays that the page rendering code can draw on
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use hd44780_driver::bus::DataBus;
+
+use crate::logic::charset::GlyphSet;
+use crate::logic::layout::Screen;
+use crate::peripherals::lcd::Lcd;
+
+/// A display that shows a grid of characters
+pub trait TextDisplay {
+    type Error: core::fmt::Debug;
+
+    /// Number of columns and rows of characters
+    fn size(&self) -> (u8, u8);
+
+    /// Write `text` starting from the given position. Characters that don't
+    /// fit on the row are dropped.
+    fn write_at(&mut self, col: u8, row: u8, text: &str) -> Result<(), Self::Error>;
+
+    fn clear(&mut self) -> Result<(), Self::Error>;
+
+    /// Make the custom glyphs in `glyphs` available. Only HD44780 displays
+    /// need this, everything else can draw all the characters anyway.
+    ///
+    /// NOTE: characters that are already shown may change, so the screen
+    /// should be invalidated afterwards.
+    fn set_glyphs(&mut self, _glyphs: GlyphSet) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Send buffered changes to the display. Displays that are written to
+    /// directly have nothing to do here.
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Send the parts of `screen` that changed since the last call
+    fn show<const COLS: usize, const ROWS: usize>(
+        &mut self,
+        screen: &mut Screen<COLS, ROWS>,
+    ) -> Result<(), Self::Error> {
+        screen.flush(|col, row, text| self.write_at(col, row, text))?;
+        self.flush()
+    }
+}
+
+/// A HD44780 character LCD, either wired up in 4-bit mode or through an I2C
+/// backpack
+pub struct Hd44780Display<B: DataBus, D> {
+    lcd: Lcd<B>,
+    delay: D,
+    cols: u8,
+    rows: u8,
+}
+
+impl<B, D> Hd44780Display<B, D>
+where
+    B: DataBus,
+    D: DelayUs<u16> + DelayMs<u8>,
+{
+    pub fn new(lcd: Lcd<B>, delay: D, cols: u8, rows: u8) -> Self {
+        Hd44780Display {
+            lcd,
+            delay,
+            cols,
+            rows,
+        }
+    }
+
+    /// Access to the LCD, e.g. for loading custom glyphs
+    pub fn lcd(&mut self) -> (&mut Lcd<B>, &mut D) {
+        (&mut self.lcd, &mut self.delay)
+    }
+
+    pub fn free(self) -> (Lcd<B>, D) {
+        (self.lcd, self.delay)
+    }
+}
+
+impl<B, D> TextDisplay for Hd44780Display<B, D>
+where
+    B: DataBus,
+    D: DelayUs<u16> + DelayMs<u8>,
+{
+    type Error = hd44780_driver::error::Error;
+
+    fn size(&self) -> (u8, u8) {
+        (self.cols, self.rows)
+    }
+
+    fn write_at(&mut self, col: u8, row: u8, text: &str) -> Result<(), Self::Error> {
+        if col >= self.cols || row >= self.rows {
+            return Ok(());
+        }
+        let fits = (self.cols - col) as usize;
+        let end = text.char_indices().nth(fits).map_or(text.len(), |(i, _)| i);
+        self.lcd.set_cursor(col, row, &mut self.delay)?;
+        self.lcd.write_str(&text[..end], &mut self.delay)
+    }
+
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        self.lcd.clear(&mut self.delay)
+    }
+
+    fn set_glyphs(&mut self, glyphs: GlyphSet) -> Result<(), Self::Error> {
+        self.lcd.set_glyph_set(glyphs, &mut self.delay)
+    }
+}
+
+#[cfg(feature = "ssd1306")]
+pub use self::ssd1306_display::Ssd1306Display;
+
+#[cfg(feature = "ssd1306")]
+mod ssd1306_display {
+    use display_interface::DisplayError;
+    use embedded_graphics::{
+        mono_font::{iso_8859_1::FONT_8X13, MonoTextStyle, MonoTextStyleBuilder},
+        pixelcolor::BinaryColor,
+        prelude::*,
+        primitives::{Line, PrimitiveStyle, Rectangle},
+        text::{Baseline, Text},
+    };
+    use embedded_hal::blocking::i2c;
+    use ssd1306::{mode::BufferedGraphicsMode, prelude::*, I2CDisplayInterface, Ssd1306};
+
+    use super::TextDisplay;
+    use crate::logic::charset::BARS;
+    use crate::logic::history::History;
+
+    const CHAR_WIDTH: u8 = 8;
+    const CHAR_HEIGHT: u8 = 13;
+    const WIDTH: u8 = 128;
+    const HEIGHT: u8 = 64;
+
+    /// A 128x64 SSD1306 OLED used as a 16x4 character display
+    ///
+    /// The font covers ISO 8859-1, so °, µ and ³ are shown, but the arrows
+    /// aren't. The bars of sparklines are drawn as rectangles.
+    pub struct Ssd1306Display<DI> {
+        display: Ssd1306<DI, DisplaySize128x64, BufferedGraphicsMode<DisplaySize128x64>>,
+        style: MonoTextStyle<'static, BinaryColor>,
+    }
+
+    impl<I2C: i2c::Write> Ssd1306Display<I2CInterface<I2C>> {
+        pub fn new_i2c(i2c: I2C) -> Result<Self, DisplayError> {
+            Self::new(I2CDisplayInterface::new(i2c))
+        }
+    }
+
+    impl<DI: WriteOnlyDataCommand> Ssd1306Display<DI> {
+        pub fn new(interface: DI) -> Result<Self, DisplayError> {
+            let mut display = Ssd1306::new(interface, DisplaySize128x64, DisplayRotation::Rotate0)
+                .into_buffered_graphics_mode();
+            display.init()?;
+            // The background color makes new text overwrite what was there
+            let style = MonoTextStyleBuilder::new()
+                .font(&FONT_8X13)
+                .text_color(BinaryColor::On)
+                .background_color(BinaryColor::Off)
+                .build();
+            Ok(Ssd1306Display { display, style })
+        }
+
+        /// Access to the underlying display for drawing graphics
+        pub fn display(
+            &mut self,
+        ) -> &mut Ssd1306<DI, DisplaySize128x64, BufferedGraphicsMode<DisplaySize128x64>> {
+            &mut self.display
+        }
+
+        /// Draw the last `span` buckets of `history` as a line chart that fills
+        /// `area`. Gaps in the history are left as gaps in the line.
+        pub fn draw_line_chart<const N: usize>(
+            &mut self,
+            history: &History<N>,
+            span: usize,
+            area: Rectangle,
+        ) -> Result<(), DisplayError> {
+            area.into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+                .draw(&mut self.display)?;
+            let Some((min, max)) = history.min_max(span) else {
+                return Ok(());
+            };
+            let span = span.min(N).max(2);
+            let size = area.size;
+            let bottom = area.top_left.y + size.height as i32 - 1;
+            // Keep a flat line in the middle
+            let range = if max - min < f32::EPSILON {
+                0.
+            } else {
+                max - min
+            };
+            let point = |i: usize, value: f32| {
+                let x = area.top_left.x + (i * (size.width as usize - 1) / (span - 1)) as i32;
+                let fraction = if range > 0. {
+                    (value - min) / range
+                } else {
+                    0.5
+                };
+                let y = bottom - (fraction * (size.height - 1) as f32 + 0.5) as i32;
+                Point::new(x, y)
+            };
+
+            let style = PrimitiveStyle::with_stroke(BinaryColor::On, 1);
+            let mut previous = None;
+            for (i, value) in history.recent(span).enumerate() {
+                let current = value.map(|value| point(i, value));
+                match (previous, current) {
+                    (Some(start), Some(end)) => Line::new(start, end)
+                        .into_styled(style)
+                        .draw(&mut self.display)?,
+                    (None, Some(end)) => Pixel(end, BinaryColor::On).draw(&mut self.display)?,
+                    _ => {}
+                }
+                previous = current;
+            }
+            Ok(())
+        }
+    }
+
+    impl<DI: WriteOnlyDataCommand> TextDisplay for Ssd1306Display<DI> {
+        type Error = DisplayError;
+
+        fn size(&self) -> (u8, u8) {
+            (WIDTH / CHAR_WIDTH, HEIGHT / CHAR_HEIGHT)
+        }
+
+        fn write_at(&mut self, col: u8, row: u8, text: &str) -> Result<(), Self::Error> {
+            let top = (row * CHAR_HEIGHT) as i32;
+            for (i, c) in text.chars().enumerate() {
+                let position = Point::new((col as usize + i) as i32 * CHAR_WIDTH as i32, top);
+                let Some(level) = BARS.iter().position(|&bar| bar == c) else {
+                    let mut buf = [0; 4];
+                    Text::with_baseline(c.encode_utf8(&mut buf), position, self.style, Baseline::Top)
+                        .draw(&mut self.display)?;
+                    continue;
+                };
+                // Block elements aren't in the font
+                let cell = Size::new(CHAR_WIDTH as u32, CHAR_HEIGHT as u32);
+                Rectangle::new(position, cell)
+                    .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+                    .draw(&mut self.display)?;
+                let height = (level as u32 + 1) * CHAR_HEIGHT as u32 / BARS.len() as u32;
+                let bar_top = position + Point::new(0, (CHAR_HEIGHT as u32 - height) as i32);
+                Rectangle::new(bar_top, Size::new(CHAR_WIDTH as u32 - 1, height))
+                    .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+                    .draw(&mut self.display)?;
+            }
+            Ok(())
+        }
+
+        fn clear(&mut self) -> Result<(), Self::Error> {
+            self.display.clear(BinaryColor::Off)
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            self.display.flush()
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! DS3231 battery backed real time clock, to get the time back after a power
+//! cycle
+//!
+//! The clock is kept in UTC, in the 24 hour mode.
+
+use embedded_hal::blocking::i2c;
+
+use crate::logic::time::DateTime;
+
+const ADDRESS: u8 = 0x68;
+/// The seconds register, the date and time follow it
+const SECONDS: u8 = 0x00;
+const STATUS: u8 = 0x0f;
+/// Set when the oscillator has stopped, e.g. because the battery ran out,
+/// until the time gets set again
+const OSCILLATOR_STOPPED: u8 = 0x80;
+/// In the month register
+const CENTURY: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error<E> {
+    I2c(E),
+    /// The clock lost track of time and has to be set
+    NotSet,
+    /// The registers hold a date that doesn't exist
+    InvalidTime,
+}
+
+pub struct Ds3231<T> {
+    i2c: T,
+}
+
+impl<T, E> Ds3231<T>
+where
+    T: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+{
+    pub fn new(i2c: T) -> Self {
+        Ds3231 { i2c }
+    }
+
+    pub fn read_time(&mut self) -> Result<DateTime, Error<E>> {
+        let mut status = [0];
+        self.i2c
+            .write_read(ADDRESS, &[STATUS], &mut status)
+            .map_err(Error::I2c)?;
+        if status[0] & OSCILLATOR_STOPPED != 0 {
+            return Err(Error::NotSet);
+        }
+
+        let mut registers = [0; 7];
+        self.i2c
+            .write_read(ADDRESS, &[SECONDS], &mut registers)
+            .map_err(Error::I2c)?;
+        let century = if registers[5] & CENTURY != 0 {
+            2100
+        } else {
+            2000
+        };
+        let time = DateTime {
+            year: century + from_bcd(registers[6]) as u16,
+            month: from_bcd(registers[5] & !CENTURY),
+            day: from_bcd(registers[4]),
+            hour: from_bcd(registers[2] & 0x3f),
+            minute: from_bcd(registers[1]),
+            second: from_bcd(registers[0]),
+        };
+        time.to_unix().ok_or(Error::InvalidTime)?;
+        Ok(time)
+    }
+
+    /// The DS3231 counts years from 2000 to 2199
+    pub fn set_time(&mut self, time: &DateTime) -> Result<(), Error<E>> {
+        if time.to_unix().is_none() || !(2000..2200).contains(&time.year) {
+            return Err(Error::InvalidTime);
+        }
+        let years = time.year - 2000;
+        let century = if years >= 100 { CENTURY } else { 0 };
+        let weekday = (time.to_unix().unwrap() / 86_400 + 3) % 7 + 1;
+        self.i2c
+            .write(
+                ADDRESS,
+                &[
+                    SECONDS,
+                    to_bcd(time.second),
+                    to_bcd(time.minute),
+                    to_bcd(time.hour),
+                    weekday as u8,
+                    to_bcd(time.day),
+                    to_bcd(time.month) | century,
+                    to_bcd((years % 100) as u8),
+                ],
+            )
+            .map_err(Error::I2c)?;
+        // Setting the time clears the flag, the rest of the status register
+        // stays at its defaults
+        self.i2c.write(ADDRESS, &[STATUS, 0x08]).map_err(Error::I2c)
+    }
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
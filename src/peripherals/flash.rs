@@ -0,0 +1,45 @@
+//! The nRF52840's own flash, through its non-volatile memory controller
+
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use nrf52840_hal::nvmc::{Instance, Nvmc, NvmcError};
+
+use crate::logic::flash::Flash;
+
+pub const PAGE_SIZE: usize = 4096;
+
+/// The upper half of the 1 MB of flash, well past the end of the firmware,
+/// for the stores to split between themselves
+pub const STORAGE_AREA: usize = 0x0008_0000;
+pub const STORAGE_PAGES: usize = 128;
+
+/// The `pages` flash pages starting at `address`, for handing to [`Nvmc`]
+///
+/// # Safety
+///
+/// Nothing else may use the area, and it may only be taken once
+pub unsafe fn area(address: usize, pages: usize) -> &'static mut [u8] {
+    core::slice::from_raw_parts_mut(address as *mut u8, pages * PAGE_SIZE)
+}
+
+impl<T: Instance> Flash for Nvmc<T> {
+    type Error = NvmcError;
+
+    const PAGE_SIZE: usize = <Self as NorFlash>::ERASE_SIZE;
+    const WORD_SIZE: usize = <Self as NorFlash>::WRITE_SIZE;
+
+    fn capacity(&self) -> usize {
+        ReadNorFlash::capacity(self)
+    }
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        ReadNorFlash::read(self, offset, bytes)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        NorFlash::write(self, offset, bytes)
+    }
+
+    fn erase_page(&mut self, offset: u32) -> Result<(), Self::Error> {
+        NorFlash::erase(self, offset, offset + PAGE_SIZE as u32)
+    }
+}
@@ -0,0 +1,238 @@
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::blocking::i2c;
+use embedded_hal::digital::v2::OutputPin;
+use hd44780_driver::{
+    bus::{DataBus, FourBitBus, I2CBus},
+    error::Result,
+    Direction,
+};
+
+use crate::logic::charset::{self, Glyph, GlyphSet};
+
+/// DDRAM addresses at which the rows start
+const ROW_OFFSETS: [u8; 4] = [0x00, 0x40, 0x14, 0x54];
+
+/// A HD44780 character LCD with our custom glyphs loaded into its CGRAM
+///
+/// `hd44780_driver::HD44780` doesn't give access to the CGRAM, so we drive the
+/// display through its bus implementations ourselves.
+pub struct Lcd<B: DataBus> {
+    bus: B,
+    glyphs: GlyphSet,
+}
+
+impl<RS, EN, D4, D5, D6, D7> Lcd<FourBitBus<RS, EN, D4, D5, D6, D7>>
+where
+    RS: OutputPin,
+    EN: OutputPin,
+    D4: OutputPin,
+    D5: OutputPin,
+    D6: OutputPin,
+    D7: OutputPin,
+{
+    pub fn new_4bit<D: DelayUs<u16> + DelayMs<u8>>(
+        rs: RS,
+        en: EN,
+        d4: D4,
+        d5: D5,
+        d6: D6,
+        d7: D7,
+        delay: &mut D,
+    ) -> Result<Self> {
+        let mut lcd = Lcd {
+            bus: FourBitBus::from_pins(rs, en, d4, d5, d6, d7),
+            glyphs: GlyphSet::Symbols,
+        };
+        lcd.init(delay)?;
+        Ok(lcd)
+    }
+}
+
+impl<I2C: i2c::Write> Lcd<I2CBus<I2C>> {
+    /// For displays connected through a PCF8574 based I2C backpack
+    pub fn new_i2c<D: DelayUs<u16> + DelayMs<u8>>(
+        i2c: I2C,
+        address: u8,
+        delay: &mut D,
+    ) -> Result<Self> {
+        let mut lcd = Lcd {
+            bus: I2CBus::new(i2c, address),
+            glyphs: GlyphSet::Symbols,
+        };
+        lcd.init(delay)?;
+        Ok(lcd)
+    }
+}
+
+impl<B: DataBus> Lcd<B> {
+    // Follows the 4-bit init sequence of hd44780_driver, except that the cursor
+    // is left invisible
+    fn init<D: DelayUs<u16> + DelayMs<u8>>(&mut self, delay: &mut D) -> Result<()> {
+        // Wait for the LCD to wake up if it was off
+        delay.delay_ms(15u8);
+
+        // Initialize in 4-bit mode
+        self.bus.write(0x33, false, delay)?;
+        delay.delay_ms(5u8);
+        self.bus.write(0x32, false, delay)?;
+        delay.delay_us(100);
+
+        // 4-bit interface, 2 lines, 5x8 font
+        self.write_command(0x28, delay)?;
+        // Display on, cursor off, blink off
+        self.write_command(0x0c, delay)?;
+        self.clear(delay)?;
+        // Increment the cursor on write, don't shift the display
+        self.write_command(0x06, delay)?;
+
+        self.load_glyphs(self.glyphs.glyphs(), delay)?;
+        self.set_cursor_pos(0, delay)
+    }
+
+    fn write_command<D: DelayUs<u16> + DelayMs<u8>>(
+        &mut self,
+        command: u8,
+        delay: &mut D,
+    ) -> Result<()> {
+        self.bus.write(command, false, delay)?;
+        // Wait for the command to be processed
+        delay.delay_us(100);
+        Ok(())
+    }
+
+    /// Store `glyph` in one of the eight CGRAM slots. The glyph can then be
+    /// shown by writing the slot number as a character code.
+    ///
+    /// NOTE: this moves the address counter into CGRAM, so the cursor position
+    /// must be set again before writing any text.
+    pub fn set_custom_char<D: DelayUs<u16> + DelayMs<u8>>(
+        &mut self,
+        slot: u8,
+        glyph: &Glyph,
+        delay: &mut D,
+    ) -> Result<()> {
+        self.write_command(0b0100_0000 | ((slot & 0b111) << 3), delay)?;
+        for row in glyph {
+            self.write_byte(row & 0b1_1111, delay)?;
+        }
+        Ok(())
+    }
+
+    /// Upload `glyphs` into consecutive CGRAM slots starting from 0
+    pub fn load_glyphs<D: DelayUs<u16> + DelayMs<u8>>(
+        &mut self,
+        glyphs: &[Glyph],
+        delay: &mut D,
+    ) -> Result<()> {
+        for (slot, glyph) in glyphs.iter().take(8).enumerate() {
+            self.set_custom_char(slot as u8, glyph, delay)?;
+        }
+        Ok(())
+    }
+
+    /// Switch the set of custom glyphs in the CGRAM. Characters that are
+    /// already on the display and use custom glyphs change their appearance
+    /// too, so they should be written again.
+    pub fn set_glyph_set<D: DelayUs<u16> + DelayMs<u8>>(
+        &mut self,
+        glyphs: GlyphSet,
+        delay: &mut D,
+    ) -> Result<()> {
+        if glyphs == self.glyphs {
+            return Ok(());
+        }
+        self.load_glyphs(glyphs.glyphs(), delay)?;
+        self.glyphs = glyphs;
+        Ok(())
+    }
+
+    pub fn glyph_set(&self) -> GlyphSet {
+        self.glyphs
+    }
+
+    /// Unshifts the display and sets the cursor position to 0
+    pub fn reset<D: DelayUs<u16> + DelayMs<u8>>(&mut self, delay: &mut D) -> Result<()> {
+        self.write_command(0b0000_0010, delay)?;
+        // Returning home takes 1.52ms
+        delay.delay_ms(2u8);
+        Ok(())
+    }
+
+    pub fn clear<D: DelayUs<u16> + DelayMs<u8>>(&mut self, delay: &mut D) -> Result<()> {
+        self.write_command(0b0000_0001, delay)?;
+        // Clearing takes 1.52ms
+        delay.delay_ms(2u8);
+        Ok(())
+    }
+
+    /// Set the DDRAM address directly. See [`Lcd::set_cursor`] for addressing
+    /// by column and row.
+    pub fn set_cursor_pos<D: DelayUs<u16> + DelayMs<u8>>(
+        &mut self,
+        position: u8,
+        delay: &mut D,
+    ) -> Result<()> {
+        self.write_command(0b1000_0000 | (position & 0b0111_1111), delay)
+    }
+
+    /// Works for both 16x2 and 20x4 displays. On the 20x4 ones rows 2 and 3
+    /// are continuations of rows 0 and 1 in the DDRAM.
+    pub fn set_cursor<D: DelayUs<u16> + DelayMs<u8>>(
+        &mut self,
+        col: u8,
+        row: u8,
+        delay: &mut D,
+    ) -> Result<()> {
+        let row_start = ROW_OFFSETS[row as usize % ROW_OFFSETS.len()];
+        self.set_cursor_pos(row_start + col, delay)
+    }
+
+    pub fn shift_cursor<D: DelayUs<u16> + DelayMs<u8>>(
+        &mut self,
+        direction: Direction,
+        delay: &mut D,
+    ) -> Result<()> {
+        let bits = match direction {
+            Direction::Left => 0b0000_0000,
+            Direction::Right => 0b0000_0100,
+        };
+        self.write_command(0b0001_0000 | bits, delay)
+    }
+
+    /// Write a raw character code
+    pub fn write_byte<D: DelayUs<u16> + DelayMs<u8>>(
+        &mut self,
+        data: u8,
+        delay: &mut D,
+    ) -> Result<()> {
+        self.bus.write(data, true, delay)?;
+        // Wait for the data to be processed
+        delay.delay_us(100);
+        Ok(())
+    }
+
+    /// Write raw character codes
+    pub fn write_bytes<D: DelayUs<u16> + DelayMs<u8>>(
+        &mut self,
+        data: &[u8],
+        delay: &mut D,
+    ) -> Result<()> {
+        for &byte in data {
+            self.write_byte(byte, delay)?;
+        }
+        Ok(())
+    }
+
+    /// Write UTF-8 text, translating it to the character codes of the display.
+    /// See [`charset::encode_char`] for which characters are supported.
+    pub fn write_str<D: DelayUs<u16> + DelayMs<u8>>(
+        &mut self,
+        text: &str,
+        delay: &mut D,
+    ) -> Result<()> {
+        for byte in charset::encode(text, self.glyphs) {
+            self.write_byte(byte, delay)?;
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,15 @@
+pub mod ble;
 pub mod button;
+pub mod buzzer;
+pub mod display;
+pub mod ds3231;
+pub mod flash;
+pub mod lcd;
 pub mod led;
+pub mod rtc;
 pub mod scd30;
+#[cfg(feature = "sdcard")]
+pub mod sdcard;
 pub mod sgp40;
+pub mod spi_flash;
+pub mod usb_serial;
@@ -0,0 +1,47 @@
+//! Wall-clock time from one of the nRF's real time counters
+//!
+//! The counter runs off the 32.768 kHz low frequency clock, which has to be
+//! started before, and keeps going while the CPU sleeps.
+
+use nrf52840_hal::rtc::{self, Instance, Rtc};
+
+use crate::logic::time::Clock;
+
+/// Ticks per second, the slowest that the prescaler allows
+pub const FREQUENCY: u32 = 8;
+/// The counter wraps around after 2^24 ticks, which is 24 days at 8 Hz
+const BITS: u32 = 24;
+
+pub struct RtcClock<T> {
+    rtc: Rtc<T>,
+    clock: Clock,
+}
+
+impl<T: Instance> RtcClock<T> {
+    /// Starts counting, the time has to be [`set`](RtcClock::set) before
+    /// there is any
+    pub fn new(rtc: T) -> Result<Self, rtc::Error> {
+        let rtc = Rtc::new(rtc, 32_768 / FREQUENCY - 1)?;
+        rtc.clear_counter();
+        rtc.enable_counter();
+        Ok(RtcClock {
+            rtc,
+            clock: Clock::new(FREQUENCY, BITS),
+        })
+    }
+
+    /// Has to be called at least once every 24 days
+    pub fn update(&mut self) {
+        self.clock.update(self.rtc.get_counter());
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Set the time to `unix`
+    pub fn set(&mut self, unix: u32) {
+        self.update();
+        self.clock.set(unix);
+    }
+}
@@ -116,4 +116,67 @@ where
 
         Ok(temperature_offset)
     }
+
+    /// Altitude above sea level in metres, which the sensor compensates for
+    /// unless it's been given the ambient pressure in
+    /// [`SCD30::start_continuous_measurement`]. Starting with a pressure of 0
+    /// turns the pressure compensation off again.
+    pub fn set_altitude_compensation(
+        &mut self,
+        altitude: u16,
+    ) -> Result<(), <T as i2c::Write>::Error> {
+        let mut command: [u8; 5] = [0x51, 0x02, 0x00, 0x00, 0x00];
+        let altitude_bytes = altitude.to_be_bytes();
+        command[2] = altitude_bytes[0];
+        command[3] = altitude_bytes[1];
+
+        let mut crc = Crc::<u8>::new(0x31, 8, 0xff, 0x00, false);
+        crc.update(&altitude_bytes);
+        command[4] = crc.finish();
+
+        self.0.write(DEFAULT_ADDRESS, &command)?;
+
+        Ok(())
+    }
+
+    pub fn read_altitude_compensation(&mut self) -> Result<u16, <T as i2c::Write>::Error> {
+        let command: [u8; 2] = [0x51, 0x02];
+        self.0.write(DEFAULT_ADDRESS, &command)?;
+        let mut buf = [0; 3];
+        self.0.read(DEFAULT_ADDRESS, &mut buf)?;
+
+        Ok(u16::from_be_bytes([buf[0], buf[1]]))
+    }
+
+    /// Tell the sensor that the CO2 concentration it's currently in is
+    /// `reference` ppm, so that it corrects its calibration accordingly. It
+    /// should have been measuring continuously in a stable environment for
+    /// at least two minutes.
+    pub fn set_forced_recalibration(
+        &mut self,
+        reference: u16,
+    ) -> Result<(), <T as i2c::Write>::Error> {
+        let mut command: [u8; 5] = [0x52, 0x04, 0x00, 0x00, 0x00];
+        let reference_bytes = reference.to_be_bytes();
+        command[2] = reference_bytes[0];
+        command[3] = reference_bytes[1];
+
+        let mut crc = Crc::<u8>::new(0x31, 8, 0xff, 0x00, false);
+        crc.update(&reference_bytes);
+        command[4] = crc.finish();
+
+        self.0.write(DEFAULT_ADDRESS, &command)?;
+
+        Ok(())
+    }
+
+    /// The reference value of the last forced recalibration, ppm
+    pub fn read_forced_recalibration(&mut self) -> Result<u16, <T as i2c::Write>::Error> {
+        let command: [u8; 2] = [0x52, 0x04];
+        self.0.write(DEFAULT_ADDRESS, &command)?;
+        let mut buf = [0; 3];
+        self.0.read(DEFAULT_ADDRESS, &mut buf)?;
+
+        Ok(u16::from_be_bytes([buf[0], buf[1]]))
+    }
 }
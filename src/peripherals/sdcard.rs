@@ -0,0 +1,71 @@
+//! Measurement log as daily CSV files on a FAT formatted microSD card
+
+use embedded_sdmmc::{
+    BlockDevice, Directory, Error, Mode, TimeSource, Timestamp, Volume, VolumeIdx, VolumeManager,
+};
+
+use crate::logic::{csv, measurement::Measurement, storage::Storage};
+
+/// Stamps files with the start of the FAT epoch, for when there's no clock
+pub struct UnknownTime;
+
+impl TimeSource for UnknownTime {
+    fn get_timestamp(&self) -> Timestamp {
+        Timestamp::from_fat(0, 0)
+    }
+}
+
+pub struct CsvStorage<D: BlockDevice, T: TimeSource> {
+    volumes: VolumeManager<D, T>,
+    volume: Volume,
+}
+
+impl<D: BlockDevice, T: TimeSource> CsvStorage<D, T> {
+    /// Writes to the root directory of the card's first partition
+    pub fn new(device: D, time_source: T) -> Result<Self, Error<D::Error>> {
+        let mut volumes = VolumeManager::new(device, time_source);
+        let volume = volumes.get_volume(VolumeIdx(0))?;
+        Ok(CsvStorage { volumes, volume })
+    }
+
+    fn append_to(
+        &mut self,
+        directory: &Directory,
+        timestamp: u32,
+        measurement: &Measurement,
+    ) -> Result<(), Error<D::Error>> {
+        let name = csv::file_name(timestamp);
+        let mut file = self.volumes.open_file_in_dir(
+            &mut self.volume,
+            directory,
+            &name,
+            Mode::ReadWriteCreateOrAppend,
+        )?;
+        let mut result = Ok(0);
+        if file.length() == 0 {
+            result = self
+                .volumes
+                .write(&mut self.volume, &mut file, csv::header().as_bytes());
+        }
+        let row = csv::row(timestamp, measurement);
+        result = result.and_then(|_| {
+            self.volumes
+                .write(&mut self.volume, &mut file, row.as_bytes())
+        });
+        // Closing right away keeps the damage of a power loss to one row
+        self.volumes.close_file(&self.volume, file)?;
+        result.map(|_| ())
+    }
+}
+
+impl<D: BlockDevice, T: TimeSource> Storage for CsvStorage<D, T> {
+    type Error = Error<D::Error>;
+
+    /// Appends a row to the file for the day of `timestamp`, Unix time
+    fn append(&mut self, timestamp: u32, measurement: &Measurement) -> Result<(), Self::Error> {
+        let root = self.volumes.open_root_dir(&self.volume)?;
+        let result = self.append_to(&root, timestamp, measurement);
+        self.volumes.close_dir(&self.volume, root);
+        result
+    }
+}
@@ -2,11 +2,6 @@ use crc_all::Crc;
 use embedded_hal::blocking::{delay::DelayMs, i2c};
 use gas_index_algorithm::{AlgorithmType, GasIndexAlgorithm};
 
-// pub struct FirmwareVersion {
-//     pub major: u8,
-//     pub minor: u8,
-// }
-
 pub struct SGP40<T> {
     i2c: T,
     algo: GasIndexAlgorithm,
@@ -32,9 +27,23 @@ where
         SGP40 { i2c, algo }
     }
 
-    // TODO: there's a serial number not a firmware version available only
-    // pub fn get_firmware_version(&mut self) -> Result<FirmwareVersion, Error> {
-    // }
+    /// The 48 bit serial number, which is all there is to tell sensors apart
+    /// by, since there's no firmware version or feature set to read
+    pub fn get_serial_number(
+        &mut self,
+        delay: &mut impl DelayMs<u8>,
+    ) -> Result<u64, <T as i2c::Write>::Error> {
+        let command: [u8; 2] = [0x36, 0x82];
+        self.i2c.write(DEFAULT_ADDRESS, &command)?;
+        delay.delay_ms(1);
+        // Three words, each followed by its CRC
+        let mut buf = [0; 9];
+        self.i2c.read(DEFAULT_ADDRESS, &mut buf)?;
+        let serial = buf.chunks(3).fold(0, |serial, word| {
+            serial << 16 | u64::from(u16::from_be_bytes([word[0], word[1]]))
+        });
+        Ok(serial)
+    }
 
     pub fn measure_raw_signal_compensated(
         &mut self,
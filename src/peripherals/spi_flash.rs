@@ -0,0 +1,159 @@
+//! SPI NOR flash that speaks the common JEDEC command set, e.g. the Winbond
+//! W25Q series
+
+use embedded_hal::{blocking::spi, digital::v2::OutputPin};
+
+use crate::logic::flash::Flash;
+
+const READ_JEDEC_ID: u8 = 0x9f;
+const RELEASE_POWER_DOWN: u8 = 0xab;
+const WRITE_ENABLE: u8 = 0x06;
+const READ_STATUS: u8 = 0x05;
+const READ_DATA: u8 = 0x03;
+const PAGE_PROGRAM: u8 = 0x02;
+const SECTOR_ERASE: u8 = 0x20;
+
+/// Bit of the status register that's set while a program or erase is going on
+const BUSY: u8 = 0x01;
+/// Programming wraps around within pages of this many bytes
+const PROGRAM_PAGE_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Error<E> {
+    Spi(E),
+    ChipSelect,
+    /// The JEDEC ID that came back doesn't look like a flash chip, e.g. all
+    /// ones or zeros if nothing's connected
+    UnknownDevice([u8; 3]),
+    OutOfBounds,
+}
+
+pub struct SpiFlash<SPI, CS> {
+    spi: SPI,
+    cs: CS,
+    capacity: usize,
+}
+
+impl<SPI, CS, E> SpiFlash<SPI, CS>
+where
+    SPI: spi::Transfer<u8, Error = E> + spi::Write<u8, Error = E>,
+    CS: OutputPin,
+{
+    /// Wakes the chip up and works out its size from its JEDEC ID
+    pub fn new(spi: SPI, cs: CS) -> Result<Self, Error<E>> {
+        let mut flash = SpiFlash {
+            spi,
+            cs,
+            capacity: 0,
+        };
+        flash.cs.set_high().map_err(|_| Error::ChipSelect)?;
+        flash.command(&[RELEASE_POWER_DOWN], &mut [])?;
+        let id = flash.jedec_id()?;
+        // The third byte is log2 of the size in bytes on most chips. Only 3
+        // byte addresses are sent, which go up to 16 MiB.
+        if id[0] == 0x00 || id[0] == 0xff || !(16..=24).contains(&id[2]) {
+            return Err(Error::UnknownDevice(id));
+        }
+        flash.capacity = 1 << id[2];
+        Ok(flash)
+    }
+
+    /// Manufacturer, memory type and capacity
+    pub fn jedec_id(&mut self) -> Result<[u8; 3], Error<E>> {
+        let mut id = [0; 3];
+        self.command(&[READ_JEDEC_ID], &mut id)?;
+        Ok(id)
+    }
+
+    /// Send `command`, then clock in `response`
+    fn command(&mut self, command: &[u8], response: &mut [u8]) -> Result<(), Error<E>> {
+        self.cs.set_low().map_err(|_| Error::ChipSelect)?;
+        let result = self.spi.write(command).and_then(|_| {
+            if response.is_empty() {
+                return Ok(());
+            }
+            response.fill(0);
+            self.spi.transfer(response).map(|_| ())
+        });
+        self.cs.set_high().map_err(|_| Error::ChipSelect)?;
+        result.map_err(Error::Spi)
+    }
+
+    /// Send `command` followed by `data`
+    fn command_with_data(&mut self, command: &[u8], data: &[u8]) -> Result<(), Error<E>> {
+        self.cs.set_low().map_err(|_| Error::ChipSelect)?;
+        let result = self.spi.write(command).and_then(|_| self.spi.write(data));
+        self.cs.set_high().map_err(|_| Error::ChipSelect)?;
+        result.map_err(Error::Spi)
+    }
+
+    fn wait_until_ready(&mut self) -> Result<(), Error<E>> {
+        let mut status = [BUSY];
+        while status[0] & BUSY != 0 {
+            self.command(&[READ_STATUS], &mut status)?;
+        }
+        Ok(())
+    }
+
+    fn addressed(command: u8, address: u32) -> [u8; 4] {
+        let [_, high, middle, low] = address.to_be_bytes();
+        [command, high, middle, low]
+    }
+
+    fn check(&self, offset: u32, len: usize) -> Result<(), Error<E>> {
+        if offset as usize + len > self.capacity {
+            return Err(Error::OutOfBounds);
+        }
+        Ok(())
+    }
+
+    pub fn release(self) -> (SPI, CS) {
+        (self.spi, self.cs)
+    }
+}
+
+impl<SPI, CS, E> Flash for SpiFlash<SPI, CS>
+where
+    SPI: spi::Transfer<u8, Error = E> + spi::Write<u8, Error = E>,
+    CS: OutputPin,
+{
+    type Error = Error<E>;
+
+    /// The smallest erasable unit, a sector
+    const PAGE_SIZE: usize = 4096;
+    const WORD_SIZE: usize = 1;
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.check(offset, bytes.len())?;
+        self.command(&Self::addressed(READ_DATA, offset), bytes)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.check(offset, bytes.len())?;
+        let mut offset = offset as usize;
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            // Don't cross into the next program page
+            let len = bytes
+                .len()
+                .min(PROGRAM_PAGE_SIZE - offset % PROGRAM_PAGE_SIZE);
+            self.command(&[WRITE_ENABLE], &mut [])?;
+            self.command_with_data(&Self::addressed(PAGE_PROGRAM, offset as u32), &bytes[..len])?;
+            self.wait_until_ready()?;
+            offset += len;
+            bytes = &bytes[len..];
+        }
+        Ok(())
+    }
+
+    fn erase_page(&mut self, offset: u32) -> Result<(), Self::Error> {
+        self.check(offset, Self::PAGE_SIZE)?;
+        self.command(&[WRITE_ENABLE], &mut [])?;
+        self.command(&Self::addressed(SECTOR_ERASE, offset), &mut [])?;
+        self.wait_until_ready()
+    }
+}
@@ -0,0 +1,96 @@
+//! USB CDC-ACM serial port, so that a computer can read the measurements
+//! without a debug probe
+//!
+//! The USB stack only makes progress when it gets polled, which has to happen
+//! at least every few milliseconds while the host talks to the device.
+
+use usb_device::{
+    bus::{UsbBus, UsbBusAllocator},
+    device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid},
+    UsbError,
+};
+use usbd_serial::{SerialPort, USB_CLASS_CDC};
+
+/// The pid.codes test VID and PID
+const VID_PID: UsbVidPid = UsbVidPid(0x1209, 0x0001);
+/// Lines waiting to go out, enough for a couple of CSV rows
+const QUEUE_SIZE: usize = 1024;
+
+pub struct UsbSerial<'a, B: UsbBus> {
+    device: UsbDevice<'a, B>,
+    serial: SerialPort<'a, B, [u8; 64], [u8; 128]>,
+    queue: heapless::Vec<u8, QUEUE_SIZE>,
+    open: bool,
+}
+
+impl<'a, B: UsbBus> UsbSerial<'a, B> {
+    pub fn new(bus: &'a UsbBusAllocator<B>) -> Self {
+        let serial = SerialPort::new_with_store(bus, [0; 64], [0; 128]);
+        let device = UsbDeviceBuilder::new(bus, VID_PID)
+            .manufacturer("airlog")
+            .product("airlog sensor")
+            .serial_number("1")
+            .device_class(USB_CLASS_CDC)
+            .build();
+        UsbSerial {
+            device,
+            serial,
+            queue: heapless::Vec::new(),
+            open: false,
+        }
+    }
+
+    /// Services the USB stack and sends what's queued. Returns whether a
+    /// terminal has just opened the port.
+    pub fn poll(&mut self) -> bool {
+        self.device.poll(&mut [&mut self.serial]);
+        let was_open = self.open;
+        self.open = self.device.state() == UsbDeviceState::Configured && self.serial.dtr();
+        if !self.open {
+            // Nobody's listening
+            self.queue.clear();
+            return false;
+        }
+
+        while !self.queue.is_empty() {
+            match self.serial.write(&self.queue) {
+                Ok(sent) => {
+                    self.queue.rotate_left(sent);
+                    self.queue.truncate(self.queue.len() - sent);
+                }
+                Err(UsbError::WouldBlock) => break,
+                Err(_) => {
+                    self.queue.clear();
+                    break;
+                }
+            }
+        }
+        !was_open
+    }
+
+    /// Takes what the host has sent, returns how many bytes that was
+    pub fn read(&mut self, bytes: &mut [u8]) -> usize {
+        self.serial.read(bytes).unwrap_or(0)
+    }
+
+    /// Queues a whole line to be sent, or drops it if a terminal hasn't
+    /// opened the port or there's no room for it
+    pub fn write_line(&mut self, line: &str) -> bool {
+        if !self.open || self.queue.len() + line.len() > QUEUE_SIZE {
+            return false;
+        }
+        self.queue.extend_from_slice(line.as_bytes()).is_ok()
+    }
+
+    /// Like [`UsbSerial::write_line`], but waits for room in the queue,
+    /// unless the port gets closed. For replies that mustn't get lost.
+    pub fn send_line(&mut self, line: &str) -> bool {
+        while self.open && !self.write_line(line) {
+            if line.len() > QUEUE_SIZE {
+                return false;
+            }
+            self.poll();
+        }
+        self.open
+    }
+}